@@ -2,15 +2,17 @@
 mod macros;
 
 mod romloader;
+mod savefile;
 mod views;
 mod window;
 
 use clap::{App, Arg};
 use cpal::traits::StreamTrait;
 use egui::ViewportBuilder;
-use log::LevelFilter;
-use nes::run;
+use log::{error, LevelFilter};
+use nes::{run, ControlMessage};
 use romloader::RomLoader;
+use savefile::SaveFile;
 use std::sync::mpsc::channel;
 use window::DebuggerWindow;
 
@@ -49,11 +51,23 @@ fn main() {
     let record = matches.is_present("record");
     let arecord = matches.is_present("arecord");
 
-    let rom = RomLoader::load(path).unwrap();
+    let rom = match RomLoader::load(path) {
+        Ok(rom) => rom,
+        Err(err) => {
+            error!("Unable to load {}: {}", path, err);
+            std::process::exit(1);
+        }
+    };
     let header = rom.header.clone();
     let (send_control, receive_control) = channel();
     let (send_frame, receive_frame) = channel();
 
+    if header.has_battery_save() {
+        if let Some(data) = SaveFile::for_rom(path).load() {
+            let _ = send_control.send(ControlMessage::LoadSaveData(data));
+        }
+    }
+
     let native_options = eframe::NativeOptions {
         viewport: ViewportBuilder::default().with_inner_size([1920.0, 1080.0]),
         ..Default::default()
@@ -0,0 +1,31 @@
+use log::{error, info};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Battery-backed PRG RAM, persisted to a .sav file next to the ROM
+pub struct SaveFile {
+    path: PathBuf,
+}
+
+impl SaveFile {
+    pub fn for_rom<P: AsRef<Path>>(rom_path: P) -> Self {
+        let path = rom_path.as_ref().with_extension("sav");
+        Self { path }
+    }
+
+    /// Read the save file, if one exists
+    pub fn load(&self) -> Option<Vec<u8>> {
+        let data = fs::read(&self.path).ok()?;
+        info!("Loaded save file {:?}", self.path);
+        Some(data)
+    }
+
+    pub fn store(&self, data: &[u8]) {
+        match fs::write(&self.path, data) {
+            Ok(()) => info!("Wrote save file {:?}", self.path),
+            Err(err) => error!("Unable to write save file {:?}: {}", self.path, err),
+        }
+    }
+}
@@ -1,6 +1,7 @@
 mod cpu;
 mod debug;
 mod log;
+mod movie;
 mod nes;
 // mod nametable;
 // mod oam;
@@ -10,12 +11,14 @@ mod ppu;
 // mod arecord;
 // mod record;
 mod rom;
+mod savestate;
 mod screenshot;
 mod view;
 
 pub use self::log::LogView;
 pub use cpu::CpuView;
 pub use debug::DebugView;
+pub use movie::MovieView;
 // pub use nametable::NametableView;
 // pub use oam::OamView;
 pub use palette::PaletteView;
@@ -25,5 +28,6 @@ pub use ppu::PpuView;
 // pub use record::RecordView;
 pub use self::nes::NesView;
 pub use rom::RomView;
+pub use savestate::SaveStateView;
 pub use screenshot::ScreenshotView;
 pub use view::View;
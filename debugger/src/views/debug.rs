@@ -9,6 +9,7 @@ use std::sync::mpsc::Sender;
 
 const SHOW_DEBUGGER: KeyboardShortcut = shortcut!(ALT, D);
 const STEP_CPU: KeyboardShortcut = shortcut!(S);
+const STEP_BACK: KeyboardShortcut = shortcut!(B);
 const RUN_PAUSE: KeyboardShortcut = shortcut!(Space);
 
 pub struct DebugView {
@@ -237,6 +238,10 @@ impl View for DebugView {
         if input_state.consume_shortcut(&STEP_CPU) {
             self.send(EmulationState::Step);
         }
+
+        if input_state.consume_shortcut(&STEP_BACK) {
+            let _ = self.send_event.send(ControlMessage::StepBack);
+        }
     }
 
     fn custom_menu(&mut self, ui: &mut Ui, ctx: &Context) {
@@ -250,8 +255,18 @@ impl View for DebugView {
             let step_cpu =
                 egui::Button::new("Step CPU").shortcut_text(ctx.format_shortcut(&STEP_CPU));
 
+            let step_back =
+                egui::Button::new("Step back").shortcut_text(ctx.format_shortcut(&STEP_BACK));
+
             ui.add(run_pause);
             ui.add(step_cpu);
+
+            if ui
+                .add_enabled(self.state == EmulationState::Pause, step_back)
+                .clicked()
+            {
+                let _ = self.send_event.send(ControlMessage::StepBack);
+            }
         });
     }
 
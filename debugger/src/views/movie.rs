@@ -0,0 +1,85 @@
+use super::View;
+use egui::{Context, Ui};
+use nes::ControlMessage;
+use std::{
+    path::{Path, PathBuf},
+    sync::mpsc::Sender,
+};
+
+/// Records and plays back input movies, stored next to the ROM as .nesm.
+/// FCEUX movies are read from and written to .fm2 next to the ROM.
+pub struct MovieView {
+    send_control: Sender<ControlMessage>,
+    path: PathBuf,
+    fm2_path: PathBuf,
+}
+
+impl MovieView {
+    pub fn new<P: AsRef<Path>>(rom_path: P, send_control: Sender<ControlMessage>) -> Self {
+        let path = rom_path.as_ref().with_extension("nesm");
+        let fm2_path = rom_path.as_ref().with_extension("fm2");
+
+        Self {
+            send_control,
+            path,
+            fm2_path,
+        }
+    }
+
+    fn send(&self, msg: ControlMessage) {
+        let _ = self.send_control.send(msg);
+    }
+}
+
+impl View for MovieView {
+    fn custom_menu(&mut self, ui: &mut Ui, _ctx: &Context) {
+        ui.menu_button("Movie", |ui| {
+            if ui.button("Record from power on").clicked() {
+                self.send(ControlMessage::RecordMovie {
+                    path: self.path.clone(),
+                    from_power_on: true,
+                });
+                ui.close_menu();
+            }
+
+            if ui.button("Record from here").clicked() {
+                self.send(ControlMessage::RecordMovie {
+                    path: self.path.clone(),
+                    from_power_on: false,
+                });
+                ui.close_menu();
+            }
+
+            if ui
+                .add_enabled(self.path.exists(), egui::Button::new("Play"))
+                .clicked()
+            {
+                self.send(ControlMessage::PlayMovie(self.path.clone()));
+                ui.close_menu();
+            }
+
+            ui.separator();
+
+            if ui.button("Record FM2 from power on").clicked() {
+                self.send(ControlMessage::RecordMovie {
+                    path: self.fm2_path.clone(),
+                    from_power_on: true,
+                });
+                ui.close_menu();
+            }
+
+            let play_fm2 = egui::Button::new("Play FM2");
+            if ui.add_enabled(self.fm2_path.exists(), play_fm2).clicked() {
+                self.send(ControlMessage::PlayMovie(self.fm2_path.clone()));
+                ui.close_menu();
+            }
+
+            ui.separator();
+
+            if ui.button("Stop").clicked() {
+                self.send(ControlMessage::StopMovie);
+                ui.close_menu();
+            }
+        });
+    }
+}
@@ -44,6 +44,14 @@ impl View for RomView {
                         ui.label(format!("{:?}", header.region()));
                         ui.end_row();
 
+                        ui.label("Timing");
+                        ui.label(format!("{:?}", header.timing()));
+                        ui.end_row();
+
+                        ui.label("Console");
+                        ui.label(format!("{:?}", header.console_type()));
+                        ui.end_row();
+
                         ui.label("iNES version");
                         ui.label(format!("{}", header.ines_version()));
                         ui.end_row();
@@ -52,6 +60,10 @@ impl View for RomView {
                         ui.label(format!("{}", header.mapper()));
                         ui.end_row();
 
+                        ui.label("Submapper");
+                        ui.label(format!("{}", header.submapper()));
+                        ui.end_row();
+
                         ui.label("Nametable mirroring");
                         ui.label(format!("{:?}", header.mirroring()));
                         ui.end_row();
@@ -72,7 +84,7 @@ impl View for RomView {
                         ui.label(format!(
                             "{} ({}KB)",
                             header.prg_banks(),
-                            header.prg_banks() * 16
+                            header.prg_rom_bytes() / 1024
                         ));
                         ui.end_row();
 
@@ -80,9 +92,29 @@ impl View for RomView {
                         ui.label(format!(
                             "{} ({}KB)",
                             header.chr_banks(),
-                            header.chr_banks() * 8
+                            header.chr_rom_bytes() / 1024
+                        ));
+                        ui.end_row();
+
+                        ui.label("PRG RAM");
+                        ui.label(format!(
+                            "{}KB ({}KB battery-backed)",
+                            header.prg_ram_bytes() / 1024,
+                            header.prg_nvram_bytes() / 1024
+                        ));
+                        ui.end_row();
+
+                        ui.label("CHR RAM");
+                        ui.label(format!(
+                            "{}KB ({}KB battery-backed)",
+                            header.chr_ram_bytes() / 1024,
+                            header.chr_nvram_bytes() / 1024
                         ));
                         ui.end_row();
+
+                        ui.label("Expansion device");
+                        ui.label(format!("{}", header.default_expansion_device()));
+                        ui.end_row();
                     });
             });
     }
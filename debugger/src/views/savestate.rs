@@ -0,0 +1,115 @@
+use super::View;
+use egui::{os::OperatingSystem, Button, Context, KeyboardShortcut, ModifierNames, Ui};
+use nes::ControlMessage;
+use std::{
+    path::{Path, PathBuf},
+    sync::mpsc::Sender,
+};
+
+const SLOT_COUNT: usize = 10;
+
+const SAVE_SHORTCUTS: [KeyboardShortcut; SLOT_COUNT] = [
+    shortcut!(F1),
+    shortcut!(F2),
+    shortcut!(F3),
+    shortcut!(F4),
+    shortcut!(F5),
+    shortcut!(F6),
+    shortcut!(F7),
+    shortcut!(F8),
+    shortcut!(F9),
+    shortcut!(F10),
+];
+
+const LOAD_SHORTCUTS: [KeyboardShortcut; SLOT_COUNT] = [
+    shortcut!(SHIFT, F1),
+    shortcut!(SHIFT, F2),
+    shortcut!(SHIFT, F3),
+    shortcut!(SHIFT, F4),
+    shortcut!(SHIFT, F5),
+    shortcut!(SHIFT, F6),
+    shortcut!(SHIFT, F7),
+    shortcut!(SHIFT, F8),
+    shortcut!(SHIFT, F9),
+    shortcut!(SHIFT, F10),
+];
+
+/// Numbered save state slots, stored next to the ROM as .ss1 through .ss10
+pub struct SaveStateView {
+    send_control: Sender<ControlMessage>,
+    slots: Vec<PathBuf>,
+}
+
+impl SaveStateView {
+    pub fn new<P: AsRef<Path>>(rom_path: P, send_control: Sender<ControlMessage>) -> Self {
+        let rom_path = rom_path.as_ref();
+        let slots = (1..=SLOT_COUNT)
+            .map(|n| rom_path.with_extension(format!("ss{}", n)))
+            .collect();
+
+        Self {
+            send_control,
+            slots,
+        }
+    }
+
+    fn save(&self, slot: usize) {
+        let msg = ControlMessage::SaveState(self.slots[slot].clone());
+        let _ = self.send_control.send(msg);
+    }
+
+    fn load(&self, slot: usize) {
+        let msg = ControlMessage::LoadState(self.slots[slot].clone());
+        let _ = self.send_control.send(msg);
+    }
+}
+
+fn shortcut_text(shortcut: &KeyboardShortcut) -> String {
+    shortcut.format(
+        &ModifierNames::NAMES,
+        OperatingSystem::from_target_os() == OperatingSystem::Mac,
+    )
+}
+
+impl View for SaveStateView {
+    fn custom_menu(&mut self, ui: &mut Ui, _ctx: &Context) {
+        ui.menu_button("State", |ui| {
+            for (slot, shortcut) in SAVE_SHORTCUTS.iter().enumerate() {
+                let label = format!("Save slot {}", slot + 1);
+                let button = Button::new(label).shortcut_text(shortcut_text(shortcut));
+
+                if ui.add(button).clicked() {
+                    self.save(slot);
+                    ui.close_menu();
+                }
+            }
+
+            ui.separator();
+
+            for (slot, shortcut) in LOAD_SHORTCUTS.iter().enumerate() {
+                let label = format!("Load slot {}", slot + 1);
+                let button = Button::new(label).shortcut_text(shortcut_text(shortcut));
+                let enabled = self.slots[slot].exists();
+
+                if ui.add_enabled(enabled, button).clicked() {
+                    self.load(slot);
+                    ui.close_menu();
+                }
+            }
+        });
+    }
+
+    fn input(&mut self, input_state: &mut egui::InputState) {
+        // Load first, since the unmodified save shortcuts also match when
+        // SHIFT is held
+        for slot in 0..SLOT_COUNT {
+            if input_state.consume_shortcut(&LOAD_SHORTCUTS[slot]) {
+                self.load(slot);
+            }
+
+            if input_state.consume_shortcut(&SAVE_SHORTCUTS[slot]) {
+                self.save(slot);
+            }
+        }
+    }
+}
@@ -1,7 +1,9 @@
+use crate::savefile::SaveFile;
 use crate::views::{
     CpuView,
     DebugView,
     LogView,
+    MovieView,
     // NametableView,
     NesView,
     // OamView,
@@ -11,22 +13,28 @@ use crate::views::{
     // RecordView,
     // AudioRecordView,
     RomView,
+    SaveStateView,
     ScreenshotView,
     View,
 };
 use egui::{Button, KeyboardShortcut};
-use nes::{ControlMessage, EmulationState, INesHeader, VideoMessage};
+use nes::{
+    ControlMessage, ControlRequest, ControlResponse, EmulationState, INesHeader, VideoMessage,
+};
 use std::{
     path::PathBuf,
     sync::mpsc::{Receiver, Sender},
+    time::Duration,
 };
 
 const QUIT_SHORTCUT: KeyboardShortcut = shortcut!(CTRL, Q);
+const SAVE_FLUSH_TIMEOUT: Duration = Duration::from_secs(1);
 
 pub struct DebuggerWindow {
     receive_frame: Receiver<VideoMessage>,
     send_control: Sender<ControlMessage>,
     views: Vec<Box<dyn View>>,
+    save_file: Option<SaveFile>,
     first_update: bool,
 }
 
@@ -70,6 +78,10 @@ impl eframe::App for DebuggerWindow {
             }
         });
 
+        if ctx.input(|i| i.viewport().close_requested()) {
+            self.flush_save();
+        }
+
         loop {
             match self.receive_frame.try_recv() {
                 Ok(VideoMessage::FrameAvailable(frame)) => {
@@ -118,12 +130,21 @@ impl DebuggerWindow {
         _record: bool,
         _arecord: bool,
     ) -> Self {
+        let rom_path = rom_path.into();
         let first_update = true;
+        let save_file = if rom_header.has_battery_save() {
+            Some(SaveFile::for_rom(&rom_path))
+        } else {
+            None
+        };
+
         let views: Vec<Box<dyn View>> = vec![
             Box::new(CpuView::new(initial_state, send_control.clone())),
             Box::new(NesView::new(send_control.clone())),
             Box::new(PpuView::new(initial_state)),
             Box::new(LogView::new(send_control.clone())),
+            Box::new(SaveStateView::new(&rom_path, send_control.clone())),
+            Box::new(MovieView::new(&rom_path, send_control.clone())),
             Box::new(RomView::new(rom_path, rom_header)),
             Box::new(ScreenshotView::new()),
             Box::new(PatternView::new(initial_state)),
@@ -141,7 +162,31 @@ impl DebuggerWindow {
             receive_frame,
             send_control,
             views,
+            save_file,
             first_update,
         }
     }
+
+    /// Ask the emulator for the contents of battery-backed RAM and write it
+    /// to disk. Called as the window closes.
+    fn flush_save(&mut self) {
+        let save_file = match self.save_file.take() {
+            Some(f) => f,
+            None => return,
+        };
+
+        let req = ControlMessage::ControlRequest(ControlRequest::SaveData);
+        if self.send_control.send(req).is_err() {
+            return;
+        }
+
+        while let Ok(msg) = self.receive_frame.recv_timeout(SAVE_FLUSH_TIMEOUT) {
+            if let VideoMessage::ControlResponse(ControlResponse::SaveData(data)) = msg {
+                if let Some(data) = data {
+                    save_file.store(&data);
+                }
+                return;
+            }
+        }
+    }
 }
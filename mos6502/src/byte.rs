@@ -0,0 +1,13 @@
+/// Convert two arbitrary sized numbers into a 16 bit word
+/// It only really makes sense when using u8 or potentially u16. The low byte
+/// is evaluated first, so bus reads happen in the order the CPU makes them.
+macro_rules! word {
+    (
+        $lo:expr,
+        $hi:expr
+    ) => {{
+        let lo = $lo;
+        let hi = $hi;
+        ((hi as u16) << 8) | (lo as u16)
+    }};
+}
@@ -0,0 +1,4293 @@
+use crate::{dma::Dma, mem::Mem, variant::Variant};
+use bitflags::bitflags;
+
+/// The size (in bytes) of one page in memory.
+const PAGE_SIZE: u16 = 0x0100;
+
+/// The memory page that the stack uses. Under normal operating conditions the
+/// stack pointer is always between 0x0100 and 0x01FF.
+const STACK_BASE: u16 = 0x0100;
+
+/// When an NMI occurs, the program counter is updated to the value stored in
+/// this memory address.
+const NMI_VECTOR: u16 = 0xFFFA;
+
+/// When the CPU is reset, the program counter is updated to the value stored in
+/// this memory address.
+const RESET_VECTOR: u16 = 0xFFFC;
+
+/// When an interrupt occurs, the program counter is updated to the value stored
+/// in this address
+const BRK_VECTOR: u16 = 0xFFFE;
+
+/// XAA and LXA OR the accumulator with a value that varies between chips
+/// before using it. This is the value seen on most of them.
+const XAA_MAGIC: u8 = 0xEE;
+
+/// Configures opcodes and points them to functions on self. For each operation
+/// that should be bound, provide the opcode, the function to invoke on self,
+/// the addressing mode to invoke on self, and whether the addressing mode only
+/// needs its fix-up cycle when indexing crosses a page.
+///
+/// The given operations are compiled into one match expression which will
+/// update the CPU's internal state. Cycles aren't counted here - every bus
+/// access takes one cycle, so the count falls out of the reads and writes
+/// that each addressing mode and operation perform.
+macro_rules! ops {
+    (
+        $this:ident,
+        $test:expr,
+        [
+            $( ( $op:literal, $fn:ident, $addr:ident, $extra:literal ) ),*
+            $(,)?
+        ]
+    ) => {
+        match $test {
+            $(
+                $op => {
+                    let addr = $this.$addr($extra);
+                    $this.$fn(addr);
+                },
+            )*
+        }
+    };
+}
+
+/// Represents a destination in memory to read from or write to during a CPU
+/// operation. Some addressing modes may incur additional cycles when they cross
+/// page boundaries or execute branch jumps. These cycles are accounted for when
+/// loading variables or evaluating branch conditions.
+#[derive(Clone, Copy, Debug)]
+enum Address {
+    Absolute(u16),
+    Accumulator,
+    Immediate(u8),
+    Implied,
+    Indirect(u16),
+}
+
+bitflags! {
+    #[derive(Clone, Copy, Debug, Default, Eq, PartialEq, PartialOrd, Ord)]
+    pub struct Flags: u8 {
+        /// The carry flag is somewhat overloaded in meaning, but it typically means
+        /// that during the previous operation the high bit of the operand was set and
+        /// shifted out, either due to a shift operation or some mathematical process.
+        /// See the documentation for the specific op code to understand what this flag
+        /// means in practice.
+        const CARRY = 0b0000_0001;
+
+        /// The zero flag indicates that the last operation resulted in a zero value.
+        const ZERO = 0b0000_0010;
+
+        /// The IRQ disable flag prevents interrupts from being serviced by the CPU.
+        /// Note that NMI interrupts cannot be disabled and will always run when the CPU
+        /// receives them.
+        const IRQ_DISABLE = 0b0000_0100;
+
+        /// The decimal flag makes ADC and SBC treat their operands as binary coded
+        /// decimal. The NES doesn't implement decimal mode (see [Variant]), but it
+        /// is still possible to set the flag.
+        const DECIMAL = 0b0000_1000;
+
+        /// The break bit isn't really a flag in that the hardware doesn't have a flag
+        /// to represent a break. However, when the processor pushes its flags to the
+        /// stack it pushes a single byte, and bit 4 of that byte can be enabled to
+        /// indicate that the interrupt occurred as a result of the BRK opcode.
+        const BREAK = 0b0001_0000;
+
+        // const UNUSED      = 0b0010_0000;
+
+        /// The overflow flag is typically used to represent an overflow when performing
+        /// addition and subtraction, but the actual semantics depend on the opcode. See
+        /// the documentation for individual opcodes to understand what this flag means
+        /// in practice.
+        const OVERFLOW = 0b0100_0000;
+
+        /// The negative flag indicates that the last operation resulted in a negative
+        /// two's complement value, i.e. bit 7 of the result was set.
+        const NEGATIVE = 0b1000_0000;
+    }
+}
+
+pub struct Cpu<M: Mem + Send> {
+    /// The number of CPU cycles that have elapsed since power on
+    pub cy: u64,
+
+    /// The accumulator register
+    pub a: u8,
+
+    /// The X register
+    pub x: u8,
+
+    /// The y register
+    pub y: u8,
+
+    /// The stack pointer. This contains the low byte of the stack address. The
+    /// hight byte is always 0x01, and the stack grows "up", meaning that an
+    /// empty stack pointer points to 0x01FF and a full stack points to 0x0100.
+    pub s: u8,
+
+    /// This byte holds all of the CPU's status flags. It's useful to represent
+    /// them as a byte rather than a bunch of booleans since some operations
+    /// read/write them to/from the stack as a single byte.
+    pub flags: Flags,
+
+    /// The program counter points to the next piece of data for the CPU to
+    /// evaluate. The 6502 initializes the PC by setting it to the word stored
+    /// in memory at 0xFFFC and 0xFFFD (the reset vector).
+    pub pc: u16,
+
+    /// All addressable space that the CPU can access. On the NES this
+    /// includes RAM, the game pak, the PPU, and so on.
+    pub mem: M,
+
+    /// Which chip is being emulated
+    variant: Variant,
+
+    /// Set when the CPU sees an edge on the NMI line, until the NMI has been
+    /// serviced
+    nmi_pending: bool,
+
+    /// Set while the IRQ line is low and the IRQ disable flag is clear
+    irq_pending: bool,
+
+    /// The interrupt lines as they were polled at the end of the previous
+    /// cycle. Interrupts are polled on the second to last cycle of each
+    /// instruction, so these decide whether an interrupt runs next.
+    prev_nmi_pending: bool,
+    prev_irq_pending: bool,
+
+    /// Set once the CPU executes a KIL instruction. A jammed CPU does nothing
+    /// until it is reset.
+    jammed: bool,
+
+    /// The page for the DMA unit to copy, from a write that started a page
+    /// copy which the CPU hasn't halted for yet
+    oam_dma: Option<u8>,
+}
+
+/// The parts of the CPU's state that programs can't see, but which decide
+/// what it does next. Save states need these to resume exactly.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct InternalState {
+    pub nmi_pending: bool,
+    pub irq_pending: bool,
+    pub prev_nmi_pending: bool,
+    pub prev_irq_pending: bool,
+    pub jammed: bool,
+    pub oam_dma: Option<u8>,
+}
+
+/// Where a DMC DMA is up to. It has to spend a halt cycle and a dummy cycle
+/// before it can read.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum DmcDma {
+    Idle,
+    Halt,
+    Dummy,
+    Ready,
+}
+
+impl DmcDma {
+    fn next(self) -> Self {
+        match self {
+            DmcDma::Halt => DmcDma::Dummy,
+            DmcDma::Dummy | DmcDma::Ready => DmcDma::Ready,
+            DmcDma::Idle => DmcDma::Idle,
+        }
+    }
+}
+
+impl<M: Mem + Send> Cpu<M> {
+    /// Creates a new CPU of the given variant with the given addressable
+    /// components. At boot time the IRQ disable flag will be set, and the
+    /// state of other registers and flags is left as undefined.
+    pub fn new(variant: Variant, mem: M) -> Cpu<M> {
+        Cpu {
+            a: 0,
+            x: 0,
+            y: 0,
+            flags: Flags::from_bits_retain(0x24),
+            s: 0x00,
+            pc: 0x0000,
+            cy: 0,
+            mem,
+            variant,
+            nmi_pending: false,
+            irq_pending: false,
+            prev_nmi_pending: false,
+            prev_irq_pending: false,
+            jammed: false,
+            oam_dma: None,
+        }
+    }
+
+    /// Reset the CPU. This will load the program counter from the reset vector
+    /// and set the IRQ disable flag.
+    ///
+    /// Reset takes 7 cycles. It runs through the same steps as an interrupt,
+    /// but the stack writes are turned into reads, so the stack pointer moves
+    /// down by 3 without anything being pushed.
+    pub fn reset(&mut self) {
+        self.loadb(self.pc);
+        self.loadb(self.pc);
+
+        for _ in 0..3 {
+            self.loadb(STACK_BASE + self.s as u16);
+            self.s = self.s.wrapping_sub(1);
+        }
+
+        self.pc = self.loadw(RESET_VECTOR);
+        self.flags.insert(Flags::IRQ_DISABLE);
+
+        self.nmi_pending = false;
+        self.irq_pending = false;
+        self.prev_nmi_pending = false;
+        self.prev_irq_pending = false;
+        self.jammed = false;
+    }
+
+    /// Whether the CPU has executed a KIL instruction and stopped. Only a
+    /// reset gets it going again.
+    pub fn is_jammed(&self) -> bool {
+        self.jammed
+    }
+
+    pub fn variant(&self) -> Variant {
+        self.variant
+    }
+
+    pub fn internal_state(&self) -> InternalState {
+        InternalState {
+            nmi_pending: self.nmi_pending,
+            irq_pending: self.irq_pending,
+            prev_nmi_pending: self.prev_nmi_pending,
+            prev_irq_pending: self.prev_irq_pending,
+            jammed: self.jammed,
+            oam_dma: self.oam_dma,
+        }
+    }
+
+    pub fn set_internal_state(&mut self, state: InternalState) {
+        self.nmi_pending = state.nmi_pending;
+        self.irq_pending = state.irq_pending;
+        self.prev_nmi_pending = state.prev_nmi_pending;
+        self.prev_irq_pending = state.prev_irq_pending;
+        self.jammed = state.jammed;
+        self.oam_dma = state.oam_dma;
+    }
+
+    /// Advance by one CPU cycle, running every other device on the bus
+    /// alongside it
+    fn tick(&mut self) {
+        self.cy += 1;
+        self.mem.tick();
+    }
+
+    /// Sample the interrupt lines at the end of a cycle. Whatever was seen on
+    /// the cycle before the last one of an instruction decides whether an
+    /// interrupt runs after it, which is why CLI, SEI, and PLP only take
+    /// effect after the following instruction.
+    fn poll_interrupts(&mut self) {
+        self.prev_nmi_pending = self.nmi_pending;
+        if self.mem.poll_nmi() {
+            self.nmi_pending = true;
+        }
+
+        self.prev_irq_pending = self.irq_pending;
+        self.irq_pending = self.mem.irq_line() && !self.flags.contains(Flags::IRQ_DISABLE);
+    }
+
+    /// Triggers a non-maskable interrupt. This causes the current program
+    /// counter and status flags to be pushed to the stack, and the PC is set to
+    /// the address contained in the NMI vector (0xFFFA and 0xFFFB).
+    ///
+    /// An NMI will always set the IRQ disable flag, and will not push the break
+    /// bit to the stack when storing the flags.
+    ///
+    /// This runs the interrupt right away. NMIs signalled through [Mem::poll_nmi]
+    /// are run by [Cpu::step] once the current instruction finishes.
+    pub fn nmi(&mut self) {
+        self.nmi_pending = true;
+        self.interrupt();
+    }
+
+    /// Triggers an intterupt request. The current program counter and status
+    /// flags are pushed to the stack, and the program counter will be
+    /// initialized to the value in the IRQ vector (0xFFFE and 0xFFFF).
+    ///
+    /// An IRQ will always set the IRQ disable flag, and will not push the break
+    /// bit to the stack when storing the flags.
+    ///
+    /// This runs the interrupt right away, regardless of the IRQ disable flag.
+    /// IRQs signalled through [Mem::irq_line] are run by [Cpu::step], which
+    /// respects the flag.
+    pub fn irq(&mut self) {
+        self.interrupt();
+    }
+
+    /// The interrupt sequence shared by NMIs and IRQs. It begins with two
+    /// reads of the next instruction, which is then thrown away.
+    fn interrupt(&mut self) {
+        self.loadb(self.pc);
+        self.loadb(self.pc);
+        self.flags.remove(Flags::BREAK);
+
+        self.pushw(self.pc);
+        let vector = self.interrupt_vector();
+        self.pushb(self.flags.bits());
+        self.flags.insert(Flags::IRQ_DISABLE);
+        self.pc = self.loadw(vector);
+
+        // The first instruction of the handler always runs before another
+        // interrupt can be taken
+        self.prev_nmi_pending = false;
+    }
+
+    /// Choose the vector for an interrupt or BRK, once the return address has
+    /// been pushed. A pending NMI hijacks the sequence at this point, so an
+    /// IRQ or BRK ends up running the NMI handler instead.
+    fn interrupt_vector(&mut self) -> u16 {
+        if self.nmi_pending {
+            self.nmi_pending = false;
+            NMI_VECTOR
+        } else {
+            BRK_VECTOR
+        }
+    }
+
+    /// The bus's DMA hooks, if this variant has a DMA unit
+    fn dma_unit(&mut self) -> Option<&mut dyn Dma> {
+        if self.variant.has_dma() {
+            self.mem.dma()
+        } else {
+            None
+        }
+    }
+
+    /// The address of the byte a device is waiting on the DMA unit to fetch
+    fn dma_fetch_addr(&mut self) -> Option<u16> {
+        self.dma_unit().and_then(|dma| dma.fetch_addr())
+    }
+
+    /// Stall the CPU while pending DMA transfers take over the bus. The CPU
+    /// can only be halted on a read, so this runs just before the read at
+    /// `addr`, which the CPU keeps repeating while it waits.
+    ///
+    /// OAM DMA copies one page (256 bytes) of memory from CPU address space
+    /// into the PPU's internal OAM (object attribute memory). Although it is
+    /// possible to write one byte at a time to the PPU using the OAMADDR and
+    /// OAMDATA ports (0x2003 and 0x2004 in the CPU's memory space), this would
+    /// be very slow compared to transferring the memory in bulk with a DMA
+    /// call. It takes 513 or 514 cycles: one to halt the CPU, possibly one more
+    /// so that the reads land on get cycles, then a read and a write for each
+    /// byte.
+    ///
+    /// DMC DMA fetches one sample byte for the APU. It needs a halt cycle and
+    /// a dummy cycle before it can read on a get cycle, so it takes 3 or 4
+    /// cycles. Cycles spent on OAM DMA count towards these, so when the two
+    /// collide the DMC read only steals a cycle or two from the OAM copy.
+    fn dma(&mut self, addr: u16) {
+        let (port, repeat_reads) = match self.dma_unit() {
+            Some(dma) => (dma.page_copy_port(), dma.repeats_halted_reads(addr)),
+            None => return,
+        };
+
+        let mut oam = self.oam_dma.take().map(|page| (page as u16) << 8);
+        let mut oam_count = 0u16;
+        let mut oam_val = 0u8;
+        let mut dmc = DmcDma::Idle;
+
+        if self.dma_fetch_addr().is_some() {
+            dmc = DmcDma::Dummy;
+        }
+        self.dma_read(addr);
+
+        loop {
+            if dmc == DmcDma::Idle && self.dma_fetch_addr().is_some() {
+                dmc = DmcDma::Halt;
+            }
+
+            if dmc == DmcDma::Idle && oam.is_none() {
+                return;
+            }
+
+            let get_cycle = self.cy & 1 == 0;
+
+            if get_cycle && dmc == DmcDma::Ready {
+                if let Some(dmc_addr) = self.dma_fetch_addr() {
+                    let val = self.dma_read(dmc_addr);
+                    if let Some(dma) = self.dma_unit() {
+                        dma.fetch_complete(val);
+                    }
+                } else {
+                    self.dma_read(addr);
+                }
+
+                dmc = DmcDma::Idle;
+                continue;
+            }
+
+            dmc = dmc.next();
+
+            match oam {
+                Some(page) if get_cycle => {
+                    oam_val = self.dma_read(page | oam_count >> 1);
+                    oam_count += 1;
+                }
+                Some(_) if oam_count & 1 == 1 => {
+                    self.dma_write(port, oam_val);
+                    oam_count += 1;
+
+                    if oam_count == 2 * PAGE_SIZE {
+                        oam = None;
+                    }
+                }
+                _ if repeat_reads => {
+                    self.dma_read(addr);
+                }
+                _ => {
+                    self.tick();
+                    self.poll_interrupts();
+                }
+            }
+        }
+    }
+
+    /// A read made by a DMA unit while the CPU is halted
+    fn dma_read(&mut self, addr: u16) -> u8 {
+        self.tick();
+        let val = self.mem.loadb(addr);
+        self.poll_interrupts();
+        val
+    }
+
+    /// A write made by a DMA unit while the CPU is halted
+    fn dma_write(&mut self, addr: u16, val: u8) {
+        self.tick();
+        self.mem.storeb(addr, val);
+        self.poll_interrupts();
+    }
+
+    /// Runs a single CPU instruction. The instruction will be read from memory
+    /// located at the current program counter value, and the stack pointer will
+    /// automatically advance past the instruction as it is read.
+    ///
+    /// One instruction will take several cycles to complete. Every read and
+    /// write the instruction makes, including the dummy accesses the real
+    /// hardware performs, takes one cycle and ticks the rest of the system
+    /// through [Mem::tick] first, so devices observe each access on the cycle
+    /// it actually happens.
+    ///
+    /// If an interrupt was seen while the previous instruction ran, the
+    /// interrupt sequence runs instead of the next instruction.
+    ///
+    /// A jammed CPU spends one cycle without touching the bus, so the rest of
+    /// the system keeps running.
+    pub fn step(&mut self) {
+        if self.jammed {
+            self.tick();
+            return;
+        }
+
+        if self.prev_nmi_pending || self.prev_irq_pending {
+            self.interrupt();
+            return;
+        }
+
+        let op = self.loadb_bump_pc();
+
+        ops!(
+            self,
+            op,
+            [
+                (0x00, brk, imp, false),
+                (0x01, ora, izx, false),
+                (0x02, kil, imp, false),
+                (0x03, slo, izx, false),
+                (0x04, nop, zp0, false),
+                (0x05, ora, zp0, false),
+                (0x06, asl, zp0, false),
+                (0x07, slo, zp0, false),
+                (0x08, php, imp, false),
+                (0x09, ora, imm, false),
+                (0x0A, asl, acc, false),
+                (0x0B, anc, imm, false),
+                (0x0C, nop, abs, false),
+                (0x0D, ora, abs, false),
+                (0x0E, asl, abs, false),
+                (0x0F, slo, abs, false),
+                (0x10, bpl, rel, true),
+                (0x11, ora, izy, true),
+                (0x12, kil, imp, false),
+                (0x13, slo, izy, false),
+                (0x14, nop, zpx, false),
+                (0x15, ora, zpx, false),
+                (0x16, asl, zpx, false),
+                (0x17, slo, zpx, false),
+                (0x18, clc, imp, false),
+                (0x19, ora, aby, true),
+                (0x1A, nop, imp, false),
+                (0x1B, slo, aby, false),
+                (0x1C, nop, abx, true),
+                (0x1D, ora, abx, true),
+                (0x1E, asl, abx, false),
+                (0x1F, slo, abx, false),
+                (0x20, jsr, imm, false),
+                (0x21, and, izx, false),
+                (0x22, kil, imp, false),
+                (0x23, rla, izx, false),
+                (0x24, bit, zp0, false),
+                (0x25, and, zp0, false),
+                (0x26, rol, zp0, false),
+                (0x27, rla, zp0, false),
+                (0x28, plp, imp, false),
+                (0x29, and, imm, false),
+                (0x2A, rol, acc, false),
+                (0x2B, anc, imm, false),
+                (0x2C, bit, abs, false),
+                (0x2D, and, abs, false),
+                (0x2E, rol, abs, false),
+                (0x2F, rla, abs, false),
+                (0x30, bmi, rel, true),
+                (0x31, and, izy, true),
+                (0x32, kil, imp, false),
+                (0x33, rla, izy, false),
+                (0x34, nop, zpx, false),
+                (0x35, and, zpx, false),
+                (0x36, rol, zpx, false),
+                (0x37, rla, zpx, false),
+                (0x38, sec, imp, false),
+                (0x39, and, aby, true),
+                (0x3A, nop, imp, false),
+                (0x3B, rla, aby, false),
+                (0x3C, nop, abx, true),
+                (0x3D, and, abx, true),
+                (0x3E, rol, abx, false),
+                (0x3F, rla, abx, false),
+                (0x40, rti, imp, false),
+                (0x41, eor, izx, false),
+                (0x42, kil, imp, false),
+                (0x43, sre, izx, false),
+                (0x44, nop, zp0, false),
+                (0x45, eor, zp0, false),
+                (0x46, lsr, zp0, false),
+                (0x47, sre, zp0, false),
+                (0x48, pha, imp, false),
+                (0x49, eor, imm, false),
+                (0x4A, lsr, acc, false),
+                (0x4B, alr, imm, false),
+                (0x4C, jmp, abs, false),
+                (0x4D, eor, abs, false),
+                (0x4E, lsr, abs, false),
+                (0x4F, sre, abs, false),
+                (0x50, bvc, rel, true),
+                (0x51, eor, izy, true),
+                (0x52, kil, imp, false),
+                (0x53, sre, izy, false),
+                (0x54, nop, zpx, false),
+                (0x55, eor, zpx, false),
+                (0x56, lsr, zpx, false),
+                (0x57, sre, zpx, false),
+                (0x58, cli, imp, false),
+                (0x59, eor, aby, true),
+                (0x5A, nop, imp, false),
+                (0x5B, sre, aby, false),
+                (0x5C, nop, abx, true),
+                (0x5D, eor, abx, true),
+                (0x5E, lsr, abx, false),
+                (0x5F, sre, abx, false),
+                (0x60, rts, imp, false),
+                (0x61, adc, izx, false),
+                (0x62, kil, imp, false),
+                (0x63, rra, izx, false),
+                (0x64, nop, zp0, false),
+                (0x65, adc, zp0, false),
+                (0x66, ror, zp0, false),
+                (0x67, rra, zp0, false),
+                (0x68, pla, imp, false),
+                (0x69, adc, imm, false),
+                (0x6A, ror, acc, false),
+                (0x6B, arr, imm, false),
+                (0x6C, jmp, ind, false),
+                (0x6D, adc, abs, false),
+                (0x6E, ror, abs, false),
+                (0x6F, rra, abs, false),
+                (0x70, bvs, rel, true),
+                (0x71, adc, izy, true),
+                (0x72, kil, imp, false),
+                (0x73, rra, izy, false),
+                (0x74, nop, zpx, false),
+                (0x75, adc, zpx, false),
+                (0x76, ror, zpx, false),
+                (0x77, rra, zpx, false),
+                (0x78, sei, imp, false),
+                (0x79, adc, aby, true),
+                (0x7A, nop, imp, false),
+                (0x7B, rra, aby, false),
+                (0x7C, nop, abx, true),
+                (0x7D, adc, abx, true),
+                (0x7E, ror, abx, false),
+                (0x7F, rra, abx, false),
+                (0x80, nop, imm, false),
+                (0x81, sta, izx, false),
+                (0x82, nop, imm, false),
+                (0x83, sax, izx, false),
+                (0x84, sty, zp0, false),
+                (0x85, sta, zp0, false),
+                (0x86, stx, zp0, false),
+                (0x87, sax, zp0, false),
+                (0x88, dey, imp, false),
+                (0x89, nop, imm, false),
+                (0x8A, txa, imp, false),
+                (0x8B, xaa, imm, false),
+                (0x8C, sty, abs, false),
+                (0x8D, sta, abs, false),
+                (0x8E, stx, abs, false),
+                (0x8F, sax, abs, false),
+                (0x90, bcc, rel, true),
+                (0x91, sta, izy, false),
+                (0x92, kil, imp, false),
+                (0x93, ahx, izy, false),
+                (0x94, sty, zpx, false),
+                (0x95, sta, zpx, false),
+                (0x96, stx, zpy, false),
+                (0x97, sax, zpy, false),
+                (0x98, tya, imp, false),
+                (0x99, sta, aby, false),
+                (0x9A, txs, imp, false),
+                (0x9B, tas, aby, false),
+                (0x9C, shy, abx, false),
+                (0x9D, sta, abx, false),
+                (0x9E, shx, aby, false),
+                (0x9F, ahx, aby, false),
+                (0xA0, ldy, imm, false),
+                (0xA1, lda, izx, false),
+                (0xA2, ldx, imm, false),
+                (0xA3, lax, izx, false),
+                (0xA4, ldy, zp0, false),
+                (0xA5, lda, zp0, false),
+                (0xA6, ldx, zp0, false),
+                (0xA7, lax, zp0, false),
+                (0xA8, tay, imp, false),
+                (0xA9, lda, imm, false),
+                (0xAA, tax, imp, false),
+                (0xAB, lxa, imm, false),
+                (0xAC, ldy, abs, false),
+                (0xAD, lda, abs, false),
+                (0xAE, ldx, abs, false),
+                (0xAF, lax, abs, false),
+                (0xB0, bcs, rel, true),
+                (0xB1, lda, izy, true),
+                (0xB2, kil, imp, false),
+                (0xB3, lax, izy, true),
+                (0xB4, ldy, zpx, false),
+                (0xB5, lda, zpx, false),
+                (0xB6, ldx, zpy, false),
+                (0xB7, lax, zpy, false),
+                (0xB8, clv, imp, false),
+                (0xB9, lda, aby, true),
+                (0xBA, tsx, imp, false),
+                (0xBB, las, aby, true),
+                (0xBC, ldy, abx, true),
+                (0xBD, lda, abx, true),
+                (0xBE, ldx, aby, true),
+                (0xBF, lax, aby, true),
+                (0xC0, cpy, imm, false),
+                (0xC1, cmp, izx, false),
+                (0xC2, nop, imm, false),
+                (0xC3, dcp, izx, false),
+                (0xC4, cpy, zp0, false),
+                (0xC5, cmp, zp0, false),
+                (0xC6, dec, zp0, false),
+                (0xC7, dcp, zp0, false),
+                (0xC8, iny, imp, false),
+                (0xC9, cmp, imm, false),
+                (0xCA, dex, imp, false),
+                (0xCB, axs, imm, false),
+                (0xCC, cpy, abs, false),
+                (0xCD, cmp, abs, false),
+                (0xCE, dec, abs, false),
+                (0xCF, dcp, abs, false),
+                (0xD0, bne, rel, true),
+                (0xD1, cmp, izy, true),
+                (0xD2, kil, imp, false),
+                (0xD3, dcp, izy, false),
+                (0xD4, nop, zpx, false),
+                (0xD5, cmp, zpx, false),
+                (0xD6, dec, zpx, false),
+                (0xD7, dcp, zpx, false),
+                (0xD8, cld, imp, false),
+                (0xD9, cmp, aby, true),
+                (0xDA, nop, imp, false),
+                (0xDB, dcp, aby, false),
+                (0xDC, nop, abx, true),
+                (0xDD, cmp, abx, true),
+                (0xDE, dec, abx, false),
+                (0xDF, dcp, abx, false),
+                (0xE0, cpx, imm, false),
+                (0xE1, sbc, izx, false),
+                (0xE2, nop, imm, false),
+                (0xE3, isc, izx, false),
+                (0xE4, cpx, zp0, false),
+                (0xE5, sbc, zp0, false),
+                (0xE6, inc, zp0, false),
+                (0xE7, isc, zp0, false),
+                (0xE8, inx, imp, false),
+                (0xE9, sbc, imm, false),
+                (0xEA, nop, imp, false),
+                (0xEB, sbc, imm, false),
+                (0xEC, cpx, abs, false),
+                (0xED, sbc, abs, false),
+                (0xEE, inc, abs, false),
+                (0xEF, isc, abs, false),
+                (0xF0, beq, rel, true),
+                (0xF1, sbc, izy, true),
+                (0xF2, kil, imp, false),
+                (0xF3, isc, izy, false),
+                (0xF4, nop, zpx, false),
+                (0xF5, sbc, zpx, false),
+                (0xF6, inc, zpx, false),
+                (0xF7, isc, zpx, false),
+                (0xF8, sed, imp, false),
+                (0xF9, sbc, aby, true),
+                (0xFA, nop, imp, false),
+                (0xFB, isc, aby, false),
+                (0xFC, nop, abx, true),
+                (0xFD, sbc, abx, true),
+                (0xFE, inc, abx, false),
+                (0xFF, isc, abx, false),
+            ]
+        );
+    }
+
+    // Addressing modes
+
+    /// Instructions using absolute addressing contain a full 16 bit address to
+    /// identify the target location.
+    fn abs(&mut self, _extra: bool) -> Address {
+        let addr = self.loadw_bump_pc();
+        Address::Absolute(addr)
+    }
+
+    /// The address to be accessed by an instruction using X register indexed
+    /// absolute addressing is computed by taking the 16 bit address from the
+    /// instruction and added the contents of the X register. For example if X
+    /// contains $92 then an STA $2000,X instruction will store the accumulator
+    /// at $2092 (e.g. $2000 + $92).
+    fn abx(&mut self, extra: bool) -> Address {
+        let val = self.loadw_bump_pc();
+        let addr = val.wrapping_add(self.x.into());
+
+        self.index_fixup(val, addr, extra);
+        Address::Absolute(addr)
+    }
+
+    /// The Y register indexed absolute addressing mode is the same as the
+    /// previous mode only with the contents of the Y register added to the 16
+    /// bit address from the instruction.
+    fn aby(&mut self, extra: bool) -> Address {
+        let val = self.loadw_bump_pc();
+        let addr = val.wrapping_add(self.y.into());
+
+        self.index_fixup(val, addr, extra);
+        Address::Absolute(addr)
+    }
+
+    /// Some instructions have an option to operate directly upon the
+    /// accumulator. The programmer specifies this by using a special operand
+    /// value, 'A'.
+    fn acc(&mut self, _extra: bool) -> Address {
+        self.loadb(self.pc);
+        Address::Accumulator
+    }
+
+    /// Immediate addressing allows the programmer to directly specify an 8 bit
+    /// constant within the instruction. It is indicated by a '#' symbol
+    /// followed by a numeric expression.
+    fn imm(&mut self, _extra: bool) -> Address {
+        let val = self.loadb_bump_pc();
+        Address::Immediate(val)
+    }
+
+    /// For many 6502 instructions the source and destination of the information
+    /// to be manipulated is implied directly by the function of the instruction
+    /// itself and no further operand needs to be specified. Operations like
+    /// 'Clear Carry Flag' (CLC) and 'Return from Subroutine' (RTS) are
+    /// implicit.
+    ///
+    /// The CPU still reads the byte after the opcode, then ignores it.
+    fn imp(&mut self, _extra: bool) -> Address {
+        self.loadb(self.pc);
+        Address::Implied
+    }
+
+    /// JMP is the only 6502 instruction to support indirection. The instruction
+    /// contains a 16 bit address which identifies the location of the least
+    /// significant byte of another 16 bit memory address which is the real
+    /// target of the instruction.
+    ///
+    /// For example if location $0120 contains $FC and location $0121 contains
+    /// $BA then the instruction JMP ($0120) will cause the next instruction
+    /// execution to occur at $BAFC (e.g. the contents of $0120 and $0121).
+    fn ind(&mut self, _extra: bool) -> Address {
+        let lo = self.loadb_bump_pc() as u16;
+        let hi = self.loadb_bump_pc() as u16;
+
+        Address::Indirect(word!(lo, hi))
+    }
+
+    /// Indexed indirect addressing is normally used in conjunction with a table
+    /// of address held on zero page. The address of the table is taken from the
+    /// instruction and the X register added to it (with zero page wrap around)
+    /// to give the location of the least significant byte of the target
+    /// address.
+    fn izx(&mut self, _extra: bool) -> Address {
+        let val = self.loadb_bump_pc();
+        let addr: u16 = val.wrapping_add(self.x).into();
+
+        // The CPU reads from the table pointer while it adds X
+        self.loadb(val.into());
+
+        if addr == 0x00FF {
+            // Zero page access should wrap when reading from 0x00FF
+            let addr = word!(self.loadb(0x00FF), self.loadb(0x0000));
+            Address::Absolute(addr)
+        } else {
+            let addr = self.loadw(addr);
+            Address::Absolute(addr)
+        }
+    }
+
+    /// Indirect indexed addressing is the most common indirection mode used on
+    /// the 6502. An instruction contains the zero page location of the least
+    /// significant byte of 16 bit address. The Y register is dynamically added
+    /// to this value to generated the actual target address for operation.
+    fn izy(&mut self, extra: bool) -> Address {
+        let val = self.loadb_bump_pc();
+
+        let lo = self.loadb(val as u16);
+        let hi = self.loadb(val.wrapping_add(1) as u16);
+        let base = word!(lo, hi);
+        let addr = base.wrapping_add(self.y as u16);
+
+        self.index_fixup(base, addr, extra);
+        Address::Absolute(addr)
+    }
+
+    /// Relative addressing mode is used by branch instructions (e.g. BEQ, BNE,
+    /// etc.) which contain a signed 8 bit relative offset (e.g. -128 to +127)
+    /// which is added to program counter if the condition is true. As the
+    /// program counter itself is incremented during instruction execution by
+    /// two the effective address range for the target instruction must be with
+    /// -126 to +129 bytes of the branch.
+    fn rel(&mut self, _extra: bool) -> Address {
+        let delta = self.loadb_bump_pc() as i8;
+        let addr = delta as i32 + self.pc as i32;
+        Address::Absolute(addr as u16)
+    }
+
+    /// An instruction using zero page addressing mode has only an 8 bit address
+    /// operand. This limits it to addressing only the first 256 bytes of memory
+    /// (e.g. $0000 to $00FF) where the most significant byte of the address is
+    /// always zero. In zero page mode only the least significant byte of the
+    /// address is held in the instruction making it shorter by one byte
+    /// (important for space saving) and one less memory fetch during execution
+    /// (important for speed).
+    ///
+    /// An assembler will automatically select zero page addressing mode if the
+    /// operand evaluates to a zero page address and the instruction supports
+    /// the mode (not all do).
+    fn zp0(&mut self, _extra: bool) -> Address {
+        let addr = self.loadb_bump_pc().into();
+        Address::Absolute(addr)
+    }
+
+    /// The address to be accessed by an instruction using indexed zero page
+    /// addressing is calculated by taking the 8 bit zero page address from the
+    /// instruction and adding the current value of the X register to it. For
+    /// example if the X register contains $0F and the instruction LDA $80,X is
+    /// executed then the accumulator will be loaded from $008F
+    /// (e.g. $80 + $0F => $8F).
+    ///
+    /// The address calculation wraps around if the sum of the base address and
+    /// the register exceed $FF. If we repeat the last example but with $FF in
+    /// the X register then the accumulator will be loaded from $007F
+    /// (e.g. $80 + $FF => $7F) and not $017F.
+    fn zpx(&mut self, _extra: bool) -> Address {
+        let val = self.loadb_bump_pc();
+        self.loadb(val.into());
+        let addr = val.wrapping_add(self.x).into();
+        Address::Absolute(addr)
+    }
+
+    /// The address to be accessed by an instruction using indexed zero page
+    /// addressing is calculated by taking the 8 bit zero page address from the
+    /// instruction and adding the current value of the Y register to it. This
+    /// mode can only be used with the LDX and STX instructions.
+    fn zpy(&mut self, _extra: bool) -> Address {
+        let val = self.loadb_bump_pc();
+        self.loadb(val.into());
+        let addr = val.wrapping_add(self.y).into();
+        Address::Absolute(addr)
+    }
+
+    // Ops
+
+    /// This instruction adds the contents of a memory location to the
+    /// accumulator together with the carry bit. If overflow occurs the carry
+    /// bit is set, this enables multiple byte addition to be performed.
+    fn adc(&mut self, addr: Address) {
+        let m = self.addr_loadb(&addr);
+        self.add_with_carry(m);
+    }
+
+    /// Stores the binary AND of the accumulator, the X register, and one more
+    /// than the high byte of the target address. This is one of the unstable
+    /// store instructions (see [Cpu::unstable_store]).
+    ///
+    /// M = A & X & (H + 1)
+    fn ahx(&mut self, addr: Address) {
+        self.unstable_store(addr, self.y, self.a & self.x);
+    }
+
+    /// ANDs the accumulator with an immediate value, then shifts the result
+    /// one bit to the right. This is the same as performing an AND followed
+    /// by an LSR on the accumulator.
+    ///
+    /// A,Z,C,N = (A & M) >> 1
+    fn alr(&mut self, addr: Address) {
+        let val = self.addr_loadb(&addr) & self.a;
+        self.a = self.shift_right(val);
+    }
+
+    /// ANDs the accumulator with an immediate value, then copies bit 7 of the
+    /// result into the carry flag as if it had been shifted out.
+    ///
+    /// A,Z,N = A & M, C = N
+    fn anc(&mut self, addr: Address) {
+        let val = self.addr_loadb(&addr);
+        self.a = self.set_zn(self.a & val);
+        self.flags.set(Flags::CARRY, self.a & 0x80 != 0);
+    }
+
+    /// A logical AND is performed, bit by bit, on the accumulator contents
+    /// using the contents of a byte of memory.
+    ///
+    /// A,Z,N = A&M
+    fn and(&mut self, addr: Address) {
+        let val = self.addr_loadb(&addr);
+        self.a = self.set_zn(val & self.a);
+    }
+
+    /// ANDs the accumulator with an immediate value, then rotates the result
+    /// one bit to the right. The carry and overflow flags come from bits 6
+    /// and 5 of the result rather than from the rotation.
+    ///
+    /// A,Z,N = (A & M) ROR 1, C = A:6, V = A:6 ^ A:5
+    fn arr(&mut self, addr: Address) {
+        let val = self.addr_loadb(&addr) & self.a;
+        let carry = self.flags.contains(Flags::CARRY) as u8;
+        self.a = self.set_zn(val >> 1 | carry << 7);
+
+        if self.decimal_mode() {
+            self.decimal_arr_fixup(val);
+            return;
+        }
+
+        let bit6 = self.a & 0x40 != 0;
+        let bit5 = self.a & 0x20 != 0;
+        self.flags.set(Flags::CARRY, bit6);
+        self.flags.set(Flags::OVERFLOW, bit6 != bit5);
+    }
+
+    /// This operation shifts all the bits of the accumulator or memory contents
+    /// one bit left. Bit 0 is set to 0 and bit 7 is placed in the carry flag.
+    /// The effect of this operation is to multiply the memory contents by 2
+    /// (ignoring 2's complement considerations), setting the carry if the
+    /// result will not fit in 8 bits.
+    ///
+    /// A,Z,C,N = M*2 or M,Z,C,N = M*2
+    fn asl(&mut self, addr: Address) {
+        self.rmw(&addr, Self::shift_left);
+    }
+
+    /// Subtracts an immediate value from the binary AND of the accumulator
+    /// and the X register, storing the result in X. The carry flag is set as
+    /// it would be by CMP, and the carry flag isn't used as a borrow.
+    ///
+    /// X,Z,C,N = (A & X) - M
+    fn axs(&mut self, addr: Address) {
+        let val = self.addr_loadb(&addr);
+        let ax = self.a & self.x;
+        self.compare(ax, val);
+        self.x = ax.wrapping_sub(val);
+    }
+
+    /// If the carry flag is clear then add the relative displacement to the
+    /// program counter to cause a branch to a new location.
+    fn bcc(&mut self, addr: Address) {
+        let carry = self.flags.contains(Flags::CARRY);
+        self.branch_base(!carry, addr);
+    }
+
+    /// If the carry flag is set then add the relative displacement to the
+    /// program counter to cause a branch to a new location.
+    fn bcs(&mut self, addr: Address) {
+        let carry = self.flags.contains(Flags::CARRY);
+        self.branch_base(carry, addr);
+    }
+
+    /// If the zero flag is set then add the relative displacement to the
+    /// program counter to cause a branch to a new location.
+    fn beq(&mut self, addr: Address) {
+        let zero = self.flags.contains(Flags::ZERO);
+        self.branch_base(zero, addr);
+    }
+
+    /// This instruction is used to test if one or more bits are set in a target
+    /// memory location. The mask pattern in A is ANDed with the value in memory
+    /// to set or clear the zero flag, but the result is not kept.  Bits 7 and 6
+    /// of the value from memory are copied into the N and V flags.
+    ///
+    /// A & M, N = M7, V = M6
+    fn bit(&mut self, addr: Address) {
+        let val = self.addr_loadb(&addr);
+        self.flags.set(Flags::ZERO, self.a & val == 0);
+        self.flags.set(Flags::OVERFLOW, val & 0x40 != 0);
+        self.flags.set(Flags::NEGATIVE, val & 0x80 != 0);
+    }
+
+    /// If the negative flag is set then add the relative displacement to the
+    /// program counter to cause a branch to a new location.
+    fn bmi(&mut self, addr: Address) {
+        let negative = self.flags.contains(Flags::NEGATIVE);
+        self.branch_base(negative, addr);
+    }
+
+    /// If the zero flag is clear then add the relative displacement to the
+    /// program counter to cause a branch to a new location.
+    fn bne(&mut self, addr: Address) {
+        let zero = self.flags.contains(Flags::ZERO);
+        self.branch_base(!zero, addr);
+    }
+
+    /// If the negative flag is clear then add the relative displacement to the
+    /// program counter to cause a branch to a new location.
+    fn bpl(&mut self, addr: Address) {
+        let negative = self.flags.contains(Flags::NEGATIVE);
+        self.branch_base(!negative, addr);
+    }
+
+    /// The BRK instruction forces the generation of an interrupt request. The
+    /// program counter and processor status are pushed on the stack then the
+    /// IRQ interrupt vector at $FFFE/F is loaded into the PC and the break flag
+    /// in the status set to one.
+    fn brk(&mut self, _addr: Address) {
+        let flags = (self.flags | Flags::from_bits_retain(0x30)).bits();
+        // Push PC + 2 onto the stack. Since PC has already been incremented
+        // from parsing the BRK, we only need to add one here.
+        self.pushw(self.pc.wrapping_add(1));
+        let vector = self.interrupt_vector();
+        self.pushb(flags);
+        self.flags.insert(Flags::IRQ_DISABLE);
+        self.pc = self.loadw(vector);
+
+        // Don't let an NMI that hijacked the BRK run again straight away
+        self.prev_nmi_pending = false;
+    }
+
+    /// If the overflow flag is clear then add the relative displacement to the
+    /// program counter to cause a branch to a new location.
+    fn bvc(&mut self, addr: Address) {
+        let overflow = self.flags.contains(Flags::OVERFLOW);
+        self.branch_base(!overflow, addr);
+    }
+
+    /// If the overflow flag is set then add the relative displacement to the
+    /// program counter to cause a branch to a new location.
+    fn bvs(&mut self, addr: Address) {
+        let overflow = self.flags.contains(Flags::OVERFLOW);
+        self.branch_base(overflow, addr);
+    }
+
+    /// Set the carry flag to zero
+    fn clc(&mut self, _addr: Address) {
+        self.flags.remove(Flags::CARRY);
+    }
+
+    /// Sets the decimal mode flag to zero.
+    fn cld(&mut self, _addr: Address) {
+        self.flags.remove(Flags::DECIMAL);
+    }
+
+    /// Clears the interrupt disable flag allowing normal interrupt requests to
+    /// be serviced.
+    fn cli(&mut self, _addr: Address) {
+        self.flags.remove(Flags::IRQ_DISABLE);
+    }
+
+    /// Clears the overflow flag.
+    ///
+    /// V = 0
+    fn clv(&mut self, _addr: Address) {
+        self.flags.remove(Flags::OVERFLOW);
+    }
+
+    /// This instruction compares the contents of the accumulator with another
+    /// memory held value and sets the zero and carry flags as appropriate.
+    ///
+    /// Z,C,N = A-M
+    fn cmp(&mut self, addr: Address) {
+        let val = self.addr_loadb(&addr);
+        self.compare(self.a, val);
+    }
+
+    /// This instruction compares the contents of the X register with another
+    /// memory held value and sets the zero and carry flags as appropriate.
+    ///
+    /// Z,C,N = X-M
+    fn cpx(&mut self, addr: Address) {
+        let val = self.addr_loadb(&addr);
+        self.compare(self.x, val);
+    }
+
+    /// This instruction compares the contents of the Y register with another
+    /// memory held value and sets the zero and carry flags as appropriate.
+    ///
+    /// Z,C,N = Y-M
+    fn cpy(&mut self, addr: Address) {
+        let val = self.addr_loadb(&addr);
+        self.compare(self.y, val);
+    }
+
+    /// Assigns the result of decrementing the given address by one back to the
+    /// source address, then subtracts the result from the accumulator setting
+    /// the zero, carry, and negative flags as appropriate. This is the same as
+    /// performing an DEC followed by a CMP.
+    ///
+    /// M = M - 1, Z,N,C = A - M
+    fn dcp(&mut self, addr: Address) {
+        let val = self.rmw(&addr, |_, val| val.wrapping_sub(1));
+        self.compare(self.a, val);
+    }
+
+    /// Subtracts one from the value held at a specified memory location setting
+    /// the zero and negative flags as appropriate.
+    ///
+    /// M,Z,N = M-1
+    fn dec(&mut self, addr: Address) {
+        self.rmw(&addr, |cpu, val| cpu.set_zn(val.wrapping_sub(1)));
+    }
+
+    /// Subtracts one from the X register setting the zero and negative flags as
+    /// appropriate.
+    ///
+    /// X,Z,N = X-1
+    fn dex(&mut self, _addr: Address) {
+        self.x = self.set_zn(self.x.wrapping_sub(1));
+    }
+
+    /// Subtracts one from the X register setting the zero and negative flags as
+    /// appropriate.
+    fn dey(&mut self, _addr: Address) {
+        self.y = self.set_zn(self.y.wrapping_sub(1));
+    }
+
+    /// An exclusive OR is performed, bit by bit, on the accumulator contents
+    /// using the contents of a byte of memory.
+    ///
+    /// A,Z,N = A^M
+    fn eor(&mut self, addr: Address) {
+        let val = self.addr_loadb(&addr);
+        self.a ^= val;
+        self.set_zn(self.a);
+    }
+
+    /// Adds one to the value held at a specified memory location setting the
+    /// zero and negative flags as appropriate.
+    ///
+    /// M,Z,N = M+1
+    fn inc(&mut self, addr: Address) {
+        self.rmw(&addr, |cpu, val| cpu.set_zn(val.wrapping_add(1)));
+    }
+
+    /// Adds one to the X register setting the zero and negative flags as
+    /// appropriate.
+    ///
+    /// X,Z,N = X+1
+    fn inx(&mut self, _addr: Address) {
+        self.x = self.set_zn(self.x.wrapping_add(1));
+    }
+
+    /// Adds one to the Y register setting the zero and negative flags as
+    /// appropriate.
+    ///
+    /// Y,Z,N = Y+1
+    fn iny(&mut self, _addr: Address) {
+        self.y = self.set_zn(self.y.wrapping_add(1));
+    }
+
+    /// Assigns the result of incrementing the given address by one back to the
+    /// source address, then subtracts the result from the accumulator setting
+    /// the zero, carry, and negative flags as appropriate. This is the same as
+    /// performing an INC followed by a CMP.
+    ///
+    /// M = M + 1, Z,N,C = A - M
+    fn isc(&mut self, addr: Address) {
+        let val = self.rmw(&addr, |_, val| val.wrapping_add(1));
+        self.subtract_with_borrow(val);
+    }
+
+    /// Sets the program counter to the address specified by the operand.
+    fn jmp(&mut self, addr: Address) {
+        match addr {
+            Address::Indirect(addr) => {
+                // 6502 bug: when the indirect address falls on a page boundary
+                // the address is fetched by wrapping to 0 of the same page, not
+                // by reading the high byte from the next page as expected
+                if addr & 0x00FF == 0x00FF {
+                    let page = addr & 0xFF00;
+                    let lo = self.loadb(page + 0x00FF);
+                    let hi = self.loadb(page);
+                    self.pc = word!(lo, hi);
+                } else {
+                    self.pc = self.loadw(addr);
+                }
+            }
+            Address::Absolute(addr) => {
+                self.pc = addr;
+            }
+            _ => unreachable!("JMP only supports absolute or indirect addresses"),
+        }
+    }
+
+    /// The JSR instruction pushes the address (minus one) of the return point
+    /// on to the stack and then sets the program counter to the target memory
+    /// address.
+    ///
+    /// JSR pushes the return address before it has read the high byte of the
+    /// target, so it is given only the low byte as an immediate value.
+    fn jsr(&mut self, addr: Address) {
+        if let Address::Immediate(lo) = addr {
+            // PC is pointing at the last byte of the JSR instruction, which is
+            // what should be pushed
+            self.loadb(STACK_BASE + self.s as u16);
+            self.pushw(self.pc);
+            let hi = self.loadb(self.pc);
+            self.pc = word!(lo, hi);
+            return;
+        }
+
+        unreachable!("JSR reads the low byte of its target as an immediate value");
+    }
+
+    /// Halts the CPU. It stops fetching instructions until it is reset, but
+    /// the rest of the system keeps running.
+    fn kil(&mut self, _addr: Address) {
+        self.jammed = true;
+    }
+
+    /// ANDs a byte of memory with the stack pointer, storing the result in
+    /// the accumulator, the X register, and the stack pointer.
+    ///
+    /// A,X,S,Z,N = M & S
+    fn las(&mut self, addr: Address) {
+        let val = self.addr_loadb(&addr) & self.s;
+        self.a = val;
+        self.x = val;
+        self.s = val;
+        self.set_zn(val);
+    }
+
+    /// Loads a byte of memory into both the accumulator and the x register
+    /// setting the zero and negative flags as appropriate
+    ///
+    /// A,X,Z,N = M
+    fn lax(&mut self, addr: Address) {
+        let val = self.addr_loadb(&addr);
+        self.a = val;
+        self.x = val;
+        self.set_zn(val);
+    }
+
+    /// Loads a byte of memory into the accumulator setting the zero and
+    /// negative flags as appropriate.
+    ///
+    /// A,Z,N = M
+    fn lda(&mut self, addr: Address) {
+        let val = self.addr_loadb(&addr);
+        self.a = self.set_zn(val);
+    }
+
+    /// Loads a byte of memory into the X register setting the zero and negative
+    /// flags as appropriate.
+    ///
+    /// X,Z,N = M
+    fn ldx(&mut self, addr: Address) {
+        let val = self.addr_loadb(&addr);
+        self.x = self.set_zn(val);
+    }
+
+    /// Loads a byte of memory into the Y register setting the zero and negative
+    /// flags as appropriate.
+    ///
+    /// Y,Z,N = M
+    fn ldy(&mut self, addr: Address) {
+        let val = self.addr_loadb(&addr);
+        self.y = self.set_zn(val);
+    }
+
+    /// Each of the bits in A or M is shift one place to the right. The bit that
+    /// was in bit 0 is shifted into the carry flag. Bit 7 is set to zero.
+    ///
+    /// A,C,Z,N = A/2 or M,C,Z,N = M/2
+    fn lsr(&mut self, addr: Address) {
+        self.rmw(&addr, Self::shift_right);
+    }
+
+    /// ANDs an immediate value into the accumulator and copies the result to
+    /// the X register. Like XAA, the accumulator is first mixed with a value
+    /// that depends on the chip.
+    ///
+    /// A,X,Z,N = (A | 0xEE) & M
+    fn lxa(&mut self, addr: Address) {
+        let val = self.addr_loadb(&addr);
+        self.a = self.set_zn((self.a | XAA_MAGIC) & val);
+        self.x = self.a;
+    }
+
+    /// The NOP instruction causes no changes to the processor other than the
+    /// normal incrementing of the program counter to the next instruction.
+    ///
+    /// The unofficial NOPs that take an operand still read it from memory.
+    fn nop(&mut self, addr: Address) {
+        if let Address::Absolute(addr) = addr {
+            self.loadb(addr);
+        }
+    }
+
+    /// An inclusive OR is performed, bit by bit, on the accumulator contents
+    /// using the contents of a byte of memory.
+    ///
+    /// A,Z,N = A | M
+    fn ora(&mut self, addr: Address) {
+        let val = self.addr_loadb(&addr);
+        self.a = self.set_zn(self.a | val);
+    }
+
+    /// Pushes a copy of the accumulator on to the stack.
+    fn pha(&mut self, _addr: Address) {
+        self.pushb(self.a);
+    }
+
+    /// Pushes a copy of the status flags on to the stack.
+    fn php(&mut self, _addr: Address) {
+        // When pushing, bits 4 & 5 are always 1.
+        let bits = (self.flags | Flags::from_bits_retain(0x30)).bits();
+        self.pushb(bits);
+    }
+
+    /// Pulls an 8 bit value from the stack and into the accumulator. The zero
+    /// and negative flags are set as appropriate.
+    fn pla(&mut self, _addr: Address) {
+        self.stack_dummy_read();
+        self.a = self.popb();
+        self.set_zn(self.a);
+    }
+
+    /// Pulls an 8 bit value from the stack and into the processor flags. The
+    /// flags will take on new states as determined by the value pulled.
+    fn plp(&mut self, _addr: Address) {
+        // the 4th bit ("B flag") isn't set by this op
+        // the 5th bit isn't a bit, but for emulation it's just always high.
+        self.stack_dummy_read();
+        let flags = self.popb() & 0xEF | 0x20;
+        self.flags = Flags::from_bits_retain(flags);
+    }
+
+    /// Rotates the given memory location one bit to the left, then ANDs the
+    /// result into the accumulator. This is the same as performing a ROL
+    /// followed by an AND.
+    ///
+    /// M = M ROL 1, A,Z,N = A & M
+    fn rla(&mut self, addr: Address) {
+        let val = self.rmw(&addr, Self::rotate_left);
+        self.a = self.set_zn(self.a & val);
+    }
+
+    /// Move each of the bits in either A or M one place to the left. Bit 0 is
+    /// filled with the current value of the carry flag whilst the old bit 7
+    /// becomes the new carry flag value.
+    fn rol(&mut self, addr: Address) {
+        self.rmw(&addr, Self::rotate_left);
+    }
+
+    /// Move each of the bits in either A or M one place to the right. Bit 7 is
+    /// filled with the current value of the carry flag whilst the old bit 0
+    /// becomes the new carry flag value.
+    fn ror(&mut self, addr: Address) {
+        self.rmw(&addr, Self::rotate_right);
+    }
+
+    /// Rotates the given memory location one bit to the right, then adds the
+    /// result to the accumulator. This is the same as performing a ROR
+    /// followed by an ADC.
+    ///
+    /// M = M ROR 1, A,Z,C,N,V = A + M + C
+    fn rra(&mut self, addr: Address) {
+        let val = self.rmw(&addr, Self::rotate_right);
+        self.add_with_carry(val);
+    }
+
+    /// The RTI instruction is used at the end of an interrupt processing
+    /// routine. It pulls the processor flags from the stack followed by the
+    /// program counter.
+    fn rti(&mut self, _addr: Address) {
+        // Bit 5 isn't real, but it's always high for emu purposes
+        self.stack_dummy_read();
+        self.flags = Flags::from_bits_retain(self.popb() | 0x20);
+        self.pc = self.popw();
+    }
+
+    /// The RTS instruction is used at the end of a subroutine to return to the
+    /// calling routine. It pulls the program counter (minus one) from the
+    /// stack.
+    fn rts(&mut self, _addr: Address) {
+        self.stack_dummy_read();
+        self.pc = self.popw();
+        self.loadb_bump_pc();
+    }
+
+    /// The SAX instruction stores the binary AND of the accumulator and the X
+    /// register into the given memory location.
+    ///
+    /// M = S & X
+    fn sax(&mut self, addr: Address) {
+        self.addr_storeb(&addr, self.a & self.x);
+    }
+
+    /// This instruction subtracts the contents of a memory location to the
+    /// accumulator together with the not of the carry bit. If overflow occurs
+    /// the carry bit is clear, this enables multiple byte subtraction to be
+    /// performed.
+    ///
+    /// A,Z,C,N = A-M-(1-C)
+    fn sbc(&mut self, addr: Address) {
+        let m = self.addr_loadb(&addr);
+        self.subtract_with_borrow(m);
+    }
+
+    /// Set the carry flag to one
+    fn sec(&mut self, _addr: Address) {
+        self.flags.insert(Flags::CARRY);
+    }
+
+    /// Set the decimal mode flag to one.
+    ///
+    /// D = 1
+    fn sed(&mut self, _addr: Address) {
+        self.flags.insert(Flags::DECIMAL);
+    }
+
+    /// Set the interrupt disable flag to one
+    fn sei(&mut self, _addr: Address) {
+        self.flags.insert(Flags::IRQ_DISABLE);
+    }
+
+    /// Stores the binary AND of the X register and one more than the high
+    /// byte of the target address. This is one of the unstable store
+    /// instructions (see [Cpu::unstable_store]).
+    ///
+    /// M = X & (H + 1)
+    fn shx(&mut self, addr: Address) {
+        self.unstable_store(addr, self.y, self.x);
+    }
+
+    /// Stores the binary AND of the Y register and one more than the high
+    /// byte of the target address. This is one of the unstable store
+    /// instructions (see [Cpu::unstable_store]).
+    ///
+    /// M = Y & (H + 1)
+    fn shy(&mut self, addr: Address) {
+        self.unstable_store(addr, self.x, self.y);
+    }
+
+    /// Assigns the result of shifting bits to the left once from the given
+    /// address back t othat address, and then assigns A | M back to the
+    /// accumulator. Setting the negative, zero, and carry flags as appropriate.
+    /// This is the same as performing an ASL followed by an ORA.
+    ///
+    /// M = M << 1, N,Z,C,A = A | M
+    fn slo(&mut self, addr: Address) {
+        let val = self.rmw(&addr, Self::shift_left);
+        self.a = self.set_zn(self.a | val);
+    }
+
+    /// Shifts the given memory location one bit to the right, then XORs the
+    /// result into the accumulator. This is the same as performing an LSR
+    /// followed by an EOR.
+    ///
+    /// M = M >> 1, A,Z,N = A ^ M
+    fn sre(&mut self, addr: Address) {
+        let val = self.rmw(&addr, Self::shift_right);
+        self.a = self.set_zn(self.a ^ val);
+    }
+
+    /// Stores the contents of the accumulator into memory.
+    ///
+    /// M = A
+    fn sta(&mut self, addr: Address) {
+        self.addr_storeb(&addr, self.a);
+    }
+
+    /// Stores the contents of the X register into memory.
+    fn stx(&mut self, addr: Address) {
+        self.addr_storeb(&addr, self.x);
+    }
+
+    /// Stores the contents of the Y register into memory.
+    fn sty(&mut self, addr: Address) {
+        self.addr_storeb(&addr, self.y);
+    }
+
+    /// Stores the binary AND of the accumulator and the X register in the
+    /// stack pointer, then stores the stack pointer ANDed with one more than
+    /// the high byte of the target address. This is one of the unstable store
+    /// instructions (see [Cpu::unstable_store]).
+    ///
+    /// S = A & X, M = S & (H + 1)
+    fn tas(&mut self, addr: Address) {
+        self.s = self.a & self.x;
+        self.unstable_store(addr, self.y, self.s);
+    }
+
+    /// Copies the current contents of the accumulator into the X register and
+    /// sets the zero and negative flags as appropriate.
+    ///
+    /// X = A
+    fn tax(&mut self, _addr: Address) {
+        self.x = self.set_zn(self.a);
+    }
+
+    /// Copies the current contents of the accumulator into the Y register and
+    /// sets the zero and negative flags as appropriate.
+    ///
+    /// Y = A
+    fn tay(&mut self, _addr: Address) {
+        self.y = self.set_zn(self.a);
+    }
+
+    /// Copies the current contents of the stack register into the X register
+    /// and sets the zero and negative flags as appropriate.
+    ///
+    /// X = S
+    fn tsx(&mut self, _addr: Address) {
+        self.x = self.set_zn(self.s);
+    }
+
+    /// Copies the current contents of the X register into the accumulator and
+    /// sets the zero and negative flags as appropriate.
+    fn txa(&mut self, _addr: Address) {
+        self.a = self.set_zn(self.x);
+    }
+
+    /// Copies the current contents of the X register into the stack register.
+    fn txs(&mut self, _addr: Address) {
+        self.s = self.x;
+    }
+
+    /// Copies the current contents of the Y register into the accumulator and
+    /// sets the zero and negative flags as appropriate.
+    fn tya(&mut self, _addr: Address) {
+        self.a = self.set_zn(self.y);
+    }
+
+    /// ANDs the X register and an immediate value into the accumulator. The
+    /// real instruction mixes in a value that depends on the individual chip
+    /// and even its temperature, so the commonly seen 0xEE is used.
+    ///
+    /// A,Z,N = (A | 0xEE) & X & M
+    fn xaa(&mut self, addr: Address) {
+        let val = self.addr_loadb(&addr);
+        self.a = self.set_zn((self.a | XAA_MAGIC) & self.x & val);
+    }
+
+    // Branch helpers
+
+    fn branch_base(&mut self, condition: bool, addr: Address) {
+        if !condition {
+            return;
+        }
+
+        if let Address::Absolute(addr) = addr {
+            // A taken branch doesn't poll for IRQs on its extra cycle, so an
+            // IRQ that shows up during it waits for another instruction
+            if self.irq_pending && !self.prev_irq_pending {
+                self.irq_pending = false;
+            }
+
+            // Taking the branch costs a cycle, during which the opcode after
+            // the branch is read and thrown away
+            self.loadb(self.pc);
+
+            // When crossing page boundaries yet another cycle is needed. The
+            // CPU reads from the target offset within the original page
+            // before fixing up the high byte.
+            if self.pc & 0xFF00 != addr & 0xFF00 {
+                self.loadb((self.pc & 0xFF00) | (addr & 0x00FF));
+            }
+
+            self.pc = addr;
+            return;
+        }
+
+        unreachable!("Invalid address in branch instruction");
+    }
+
+    // Arithmetic helpers
+
+    /// Whether ADC and SBC work in binary coded decimal
+    fn decimal_mode(&self) -> bool {
+        self.variant.has_decimal_mode() && self.flags.contains(Flags::DECIMAL)
+    }
+
+    /// Add a value and the carry flag to the accumulator, setting the carry
+    /// and overflow flags from the result
+    fn add_with_carry(&mut self, val: u8) {
+        if self.decimal_mode() {
+            self.decimal_add(val);
+        } else {
+            self.binary_add(val);
+        }
+    }
+
+    /// Subtract a value and the borrow (the inverse of the carry flag) from
+    /// the accumulator. The flags always come from the binary difference, even
+    /// in decimal mode.
+    fn subtract_with_borrow(&mut self, val: u8) {
+        let decimal = self.decimal_mode().then(|| self.decimal_difference(val));
+
+        // Subtracting is adding the one's complement, with the carry flag
+        // standing in for the +1 of the two's complement
+        self.binary_add(val ^ 0xFF);
+
+        if let Some(a) = decimal {
+            self.a = a;
+        }
+    }
+
+    fn binary_add(&mut self, val: u8) {
+        let m: u16 = val.into();
+        let a: u16 = self.a.into();
+        let c: u16 = self.flags.intersection(Flags::CARRY).bits().into();
+
+        let result = a + m + c;
+
+        let overflow = (!(a ^ m) & (a ^ result)) & 0x80 != 0;
+
+        self.flags.set(Flags::OVERFLOW, overflow);
+        self.flags.set(Flags::CARRY, result > 0x00FF);
+
+        let result = result as u8;
+        self.a = self.set_zn(result);
+    }
+
+    /// Add in binary coded decimal. The NMOS 6502 takes the zero flag from the
+    /// binary sum, and the negative and overflow flags from the sum after the
+    /// low digit has been adjusted but before the high digit has.
+    fn decimal_add(&mut self, val: u8) {
+        let m: u16 = val.into();
+        let a: u16 = self.a.into();
+        let c: u16 = self.flags.intersection(Flags::CARRY).bits().into();
+
+        let mut lo = (a & 0x0F) + (m & 0x0F) + c;
+        if lo > 0x09 {
+            lo += 0x06;
+        }
+
+        let half_carry = if lo > 0x0F { 0x10 } else { 0 };
+        let mut result = (a & 0xF0) + (m & 0xF0) + half_carry + (lo & 0x0F);
+
+        let overflow = (!(a ^ m) & (a ^ result)) & 0x80 != 0;
+
+        self.flags.set(Flags::ZERO, (a + m + c) & 0xFF == 0);
+        self.flags.set(Flags::NEGATIVE, result & 0x80 != 0);
+        self.flags.set(Flags::OVERFLOW, overflow);
+
+        if result > 0x9F {
+            result += 0x60;
+        }
+
+        self.flags.set(Flags::CARRY, result > 0xFF);
+        self.a = result as u8;
+    }
+
+    /// The accumulator after subtracting in binary coded decimal
+    fn decimal_difference(&self, val: u8) -> u8 {
+        let m: i16 = val.into();
+        let a: i16 = self.a.into();
+        let borrow = !self.flags.contains(Flags::CARRY) as i16;
+
+        let mut lo = (a & 0x0F) - (m & 0x0F) - borrow;
+        let mut hi = (a & 0xF0) - (m & 0xF0);
+
+        if lo < 0 {
+            lo -= 0x06;
+            hi -= 0x10;
+        }
+
+        if hi < 0 {
+            hi -= 0x60;
+        }
+
+        (hi & 0xF0 | lo & 0x0F) as u8
+    }
+
+    /// In decimal mode ARR adjusts each digit of its result as if it were
+    /// correcting a decimal addition, and takes the carry from the high digit.
+    /// `val` is the value before it was rotated.
+    fn decimal_arr_fixup(&mut self, val: u8) {
+        let val: u16 = val.into();
+        let mut a = self.a;
+
+        self.flags.set(Flags::OVERFLOW, (a ^ val as u8) & 0x40 != 0);
+
+        if (val & 0x0F) + (val & 0x01) > 0x05 {
+            a = a & 0xF0 | a.wrapping_add(0x06) & 0x0F;
+        }
+
+        let carry = (val & 0xF0) + (val & 0x10) > 0x50;
+        if carry {
+            a = a & 0x0F | a.wrapping_add(0x60) & 0xF0;
+        }
+
+        self.flags.set(Flags::CARRY, carry);
+        self.a = a;
+    }
+
+    /// Set the zero, carry, and negative flags by subtracting a value from a
+    /// register
+    fn compare(&mut self, reg: u8, val: u8) {
+        self.flags.set(Flags::CARRY, reg >= val);
+        self.set_zn(reg.wrapping_sub(val));
+    }
+
+    fn shift_left(&mut self, val: u8) -> u8 {
+        self.flags.set(Flags::CARRY, val & 0x80 != 0);
+        self.set_zn(val << 1)
+    }
+
+    fn shift_right(&mut self, val: u8) -> u8 {
+        self.flags.set(Flags::CARRY, val & 0x01 != 0);
+        self.set_zn(val >> 1)
+    }
+
+    fn rotate_left(&mut self, val: u8) -> u8 {
+        let carry = self.flags.contains(Flags::CARRY) as u8;
+        self.flags.set(Flags::CARRY, val & 0x80 != 0);
+        self.set_zn(val << 1 | carry)
+    }
+
+    fn rotate_right(&mut self, val: u8) -> u8 {
+        let carry = self.flags.contains(Flags::CARRY) as u8;
+        self.flags.set(Flags::CARRY, val & 0x01 != 0);
+        self.set_zn(val >> 1 | carry << 7)
+    }
+
+    // Addressing helpers
+
+    /// Indexed addressing modes first read from the un-fixed address, which
+    /// has the right low byte but possibly the wrong page. Reads only pay for
+    /// this cycle when they actually cross a page, since the value read is
+    /// correct otherwise. Writes and read-modify-writes always pay for it.
+    fn index_fixup(&mut self, base: u16, addr: u16, page_cross_only: bool) {
+        let crossed = base & 0xFF00 != addr & 0xFF00;
+
+        if crossed || !page_cross_only {
+            self.loadb((base & 0xFF00) | (addr & 0x00FF));
+        }
+    }
+
+    /// The unstable stores AND the value being written with one more than the
+    /// high byte of the base address, before it was indexed. When indexing
+    /// crosses a page, the high byte of the address is corrupted as well, and
+    /// the value ends up being written to the page it names instead.
+    fn unstable_store(&mut self, addr: Address, index: u8, val: u8) {
+        if let Address::Absolute(addr) = addr {
+            let base = addr.wrapping_sub(index as u16);
+            let val = val & ((base >> 8) as u8).wrapping_add(1);
+
+            let addr = if base & 0xFF00 != addr & 0xFF00 {
+                (val as u16) << 8 | (addr & 0x00FF)
+            } else {
+                addr
+            };
+
+            self.storeb(addr, val);
+            return;
+        }
+
+        unreachable!("Unstable stores only write to absolute addresses");
+    }
+
+    /// Read-modify-write instructions read the value, write it straight back
+    /// while they work on it, then write the result. Returns the result.
+    fn rmw<F>(&mut self, addr: &Address, f: F) -> u8
+    where
+        F: FnOnce(&mut Self, u8) -> u8,
+    {
+        let val = self.addr_loadb(addr);
+
+        if let Address::Absolute(addr) = *addr {
+            self.storeb(addr, val);
+        }
+
+        let result = f(self, val);
+        self.addr_storeb(addr, result);
+        result
+    }
+
+    fn addr_loadb(&mut self, addr: &Address) -> u8 {
+        match addr {
+            Address::Absolute(addr) => self.loadb(*addr),
+            Address::Accumulator => self.a,
+            Address::Immediate(val) => *val,
+            Address::Indirect(_) => unreachable!("Can't load from indirect address"),
+            Address::Implied => unreachable!("Can't load from implied address"),
+        }
+    }
+
+    fn addr_storeb(&mut self, addr: &Address, val: u8) {
+        match addr {
+            Address::Absolute(addr) => self.storeb(*addr, val),
+            Address::Accumulator => self.a = val,
+            _ => panic!("Can't store to this address type"),
+        }
+    }
+
+    // PC helpers
+
+    fn loadb_bump_pc(&mut self) -> u8 {
+        let val = self.loadb(self.pc);
+        self.pc = self.pc.wrapping_add(1);
+        val
+    }
+
+    fn loadw_bump_pc(&mut self) -> u16 {
+        let val = self.loadw(self.pc);
+        self.pc = self.pc.wrapping_add(2);
+        val
+    }
+
+    // flags
+
+    fn set_zn(&mut self, val: u8) -> u8 {
+        self.flags.set(Flags::ZERO, val == 0);
+        self.flags.set(Flags::NEGATIVE, (val & 0x80) != 0);
+        val
+    }
+
+    // Stack helpers
+
+    fn pushb(&mut self, val: u8) {
+        let s: u16 = self.s.into();
+        let addr = STACK_BASE + s;
+        self.s = self.s.wrapping_sub(1);
+        self.storeb(addr, val);
+    }
+
+    fn pushw(&mut self, val: u16) {
+        let hi = val >> 8;
+        let lo = val & 0xFF;
+
+        self.pushb(hi as u8);
+        self.pushb(lo as u8);
+    }
+
+    fn popb(&mut self) -> u8 {
+        self.s = self.s.wrapping_add(1);
+        self.loadb(STACK_BASE + self.s as u16)
+    }
+
+    fn popw(&mut self) -> u16 {
+        let lo = self.popb();
+        let hi = self.popb();
+        word!(lo, hi)
+    }
+
+    /// Pulling from the stack takes an extra cycle up front, where the CPU
+    /// reads from the stack before incrementing the stack pointer
+    fn stack_dummy_read(&mut self) {
+        self.loadb(STACK_BASE + self.s as u16);
+    }
+}
+
+impl<M: Mem + Send> Mem for Cpu<M> {
+    fn peekb(&self, addr: u16) -> u8 {
+        self.mem.peekb(addr)
+    }
+
+    /// Every read takes one CPU cycle, plus any cycles stolen by DMA
+    fn loadb(&mut self, addr: u16) -> u8 {
+        if self.oam_dma.is_some() || self.dma_fetch_addr().is_some() {
+            self.dma(addr);
+        }
+
+        self.tick();
+        let val = self.mem.loadb(addr);
+        self.poll_interrupts();
+        val
+    }
+
+    /// Every write takes one CPU cycle. On the 2A03, a write can start a page
+    /// copy instead, which begins on the CPU's next read.
+    fn storeb(&mut self, addr: u16, val: u8) {
+        self.tick();
+
+        let page_copy = self
+            .dma_unit()
+            .is_some_and(|dma| dma.starts_page_copy(addr));
+
+        if page_copy {
+            self.oam_dma = Some(val);
+        } else {
+            self.mem.storeb(addr, val);
+        }
+
+        self.poll_interrupts();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use matches::assert_matches;
+
+    macro_rules! cpu {
+        ( ) => {
+            cpu!(0x00)
+        };
+
+        ( $flags:expr ) => {
+            cpu!($flags, vec![])
+        };
+
+        ( $flags:expr, $mem:expr ) => {{
+            let mut cpu = Cpu::new(
+                Variant::Ricoh2A03,
+                VecMem {
+                    mem: $mem,
+                    irq: false,
+                    nmi: false,
+                    dmc: None,
+                    dmc_byte: None,
+                },
+            );
+            cpu.pc = 0;
+            cpu.s = 0xFF;
+            cpu.flags = Flags::from_bits_retain($flags);
+            cpu
+        }};
+    }
+
+    struct VecMem {
+        mem: Vec<u8>,
+        irq: bool,
+        nmi: bool,
+        dmc: Option<u16>,
+        dmc_byte: Option<u8>,
+    }
+
+    impl Mem for VecMem {
+        /// Dummy reads can land anywhere, so reads past the end of memory are
+        /// treated as open
+        fn peekb(&self, addr: u16) -> u8 {
+            self.mem.get(addr as usize).copied().unwrap_or(0)
+        }
+
+        fn storeb(&mut self, addr: u16, val: u8) {
+            self.mem[addr as usize] = val;
+        }
+
+        fn poll_nmi(&mut self) -> bool {
+            std::mem::take(&mut self.nmi)
+        }
+
+        fn irq_line(&self) -> bool {
+            self.irq
+        }
+
+        fn dma(&mut self) -> Option<&mut dyn Dma> {
+            Some(self)
+        }
+    }
+
+    /// Wired up like the NES: writes to $4014 copy a page to $2004
+    impl Dma for VecMem {
+        fn starts_page_copy(&self, addr: u16) -> bool {
+            addr == 0x4014
+        }
+
+        fn page_copy_port(&self) -> u16 {
+            0x2004
+        }
+
+        fn fetch_addr(&self) -> Option<u16> {
+            self.dmc
+        }
+
+        fn fetch_complete(&mut self, val: u8) {
+            self.dmc = None;
+            self.dmc_byte = Some(val);
+        }
+    }
+
+    mod interrupts {
+        use super::*;
+
+        #[test]
+        fn reset_sets_i_flag() {
+            let mut cpu = cpu!(0x00, vec![0; 0x10000]);
+            cpu.reset();
+            assert!(cpu.flags.contains(Flags::IRQ_DISABLE));
+        }
+
+        #[test]
+        fn nmi_sets_i_flag() {
+            let mut cpu = cpu!(0x00, vec![0; 0x10000]);
+            cpu.nmi();
+            assert!(cpu.flags.contains(Flags::IRQ_DISABLE));
+        }
+
+        #[test]
+        fn nmi_clears_b_flag() {
+            let mut cpu = cpu!(Flags::BREAK.bits(), vec![0; 0x10000]);
+            cpu.nmi();
+            assert!(!cpu.flags.contains(Flags::BREAK));
+        }
+
+        #[test]
+        fn nmi_pushes_pc_to_stack() {
+            let mut cpu = cpu!(0x00, vec![0; 0x10000]);
+            cpu.s = 0xFF;
+            cpu.pc = 0xABCD;
+            cpu.nmi();
+            assert_eq!(cpu.mem.mem[0x01FE], 0xCD);
+            assert_eq!(cpu.mem.mem[0x01FF], 0xAB);
+        }
+
+        #[test]
+        fn nmi_pushes_flags_to_stack() {
+            let mut cpu = cpu!(Flags::ZERO.bits() | Flags::CARRY.bits(), vec![0; 0x10000]);
+            cpu.s = 0xFF;
+            cpu.nmi();
+            assert_eq!(
+                cpu.mem.mem[0x01FD],
+                Flags::ZERO.bits() | Flags::CARRY.bits()
+            );
+        }
+
+        #[test]
+        fn nmi_sets_pc() {
+            let mut mem = vec![0; 0x10000];
+            mem[0xFFFA] = 0xCD;
+            mem[0xFFFB] = 0xAB;
+            let mut cpu = cpu!(0x00, mem);
+            cpu.nmi();
+            assert_eq!(cpu.pc, 0xABCD);
+        }
+
+        /// A CPU running NOPs from 0x0200, with every vector pointing at a
+        /// different handler
+        fn nop_cpu(flags: u8) -> Cpu<VecMem> {
+            let mut mem = vec![0xEA; 0x10000];
+            mem[0xFFFA..].copy_from_slice(&[0x00, 0x10, 0x00, 0x20, 0x00, 0x30]);
+            let mut cpu = cpu!(flags, mem);
+            cpu.pc = 0x0200;
+            cpu
+        }
+
+        #[test]
+        fn irq_ignored_with_i_flag() {
+            let mut cpu = nop_cpu(Flags::IRQ_DISABLE.bits());
+            cpu.mem.irq = true;
+            cpu.step();
+            cpu.step();
+            assert_eq!(cpu.pc, 0x0202);
+        }
+
+        #[test]
+        fn irq_runs_after_current_instruction() {
+            let mut cpu = nop_cpu(0x00);
+            cpu.mem.irq = true;
+            cpu.step();
+            assert_eq!(cpu.pc, 0x0201);
+            cpu.step();
+            assert_eq!(cpu.pc, 0x3000);
+            assert_eq!(cpu.cy, 9);
+        }
+
+        #[test]
+        fn cli_delays_irq_by_one_instruction() {
+            let mut cpu = nop_cpu(Flags::IRQ_DISABLE.bits());
+            cpu.mem.mem[0x0200] = 0x58;
+            cpu.mem.irq = true;
+            cpu.step();
+            cpu.step();
+            assert_eq!(cpu.pc, 0x0202);
+            cpu.step();
+            assert_eq!(cpu.pc, 0x3000);
+        }
+
+        #[test]
+        fn irq_can_run_right_after_sei() {
+            let mut cpu = nop_cpu(0x00);
+            cpu.mem.mem[0x0200] = 0x78;
+            cpu.mem.irq = true;
+            cpu.step();
+            cpu.step();
+            assert_eq!(cpu.pc, 0x3000);
+        }
+
+        #[test]
+        fn nmi_line_is_edge_triggered() {
+            let mut cpu = nop_cpu(0x00);
+            cpu.mem.nmi = true;
+            cpu.step();
+            cpu.step();
+            assert_eq!(cpu.pc, 0x1000);
+            cpu.step();
+            cpu.step();
+            assert_eq!(cpu.pc, 0x1002);
+        }
+
+        #[test]
+        fn nmi_hijacks_brk() {
+            let mut cpu = nop_cpu(0x00);
+            cpu.mem.mem[0x0200] = 0x00;
+            cpu.step();
+            assert_eq!(cpu.pc, 0x3000);
+
+            let mut cpu = nop_cpu(0x00);
+            cpu.mem.mem[0x0200] = 0x00;
+            cpu.nmi_pending = true;
+            cpu.step();
+            assert_eq!(cpu.pc, 0x1000);
+            assert_eq!(cpu.mem.mem[0x01FD] & 0x10, 0x10);
+
+            // The first instruction of the handler runs before the NMI could
+            // be taken again
+            cpu.step();
+            assert_eq!(cpu.pc, 0x1001);
+        }
+
+        #[test]
+        fn taken_branch_delays_irq() {
+            let mut cpu = nop_cpu(0x00);
+            cpu.mem.irq = true;
+
+            // The IRQ showed up while the branch read its operand
+            cpu.irq_pending = true;
+            cpu.bne(Address::Absolute(0x0202));
+            cpu.step();
+            assert_eq!(cpu.pc, 0x0203);
+            cpu.step();
+            assert_eq!(cpu.pc, 0x3000);
+        }
+
+        #[test]
+        fn irq_sets_irq_flag() {
+            let mut cpu = cpu!(0x00, vec![0; 0x10000]);
+            cpu.irq();
+            assert!(cpu.flags.intersects(Flags::IRQ_DISABLE));
+        }
+
+        #[test]
+        fn irq_clears_b_flag() {
+            let mut cpu = cpu!(Flags::BREAK.bits(), vec![0; 0x10000]);
+            cpu.irq();
+            assert!(!cpu.flags.intersects(Flags::BREAK));
+        }
+
+        #[test]
+        fn irq_pushes_pc_to_stack() {
+            let mut cpu = cpu!(0x00, vec![0x00; 0x10000]);
+            cpu.s = 0xFF;
+            cpu.pc = 0xABCD;
+            cpu.irq();
+            assert_eq!(cpu.mem.mem[0x01FE], 0xCD);
+            assert_eq!(cpu.mem.mem[0x01FF], 0xAB);
+        }
+
+        #[test]
+        fn irq_pushes_flags_to_stack() {
+            let mut cpu = cpu!(Flags::ZERO.bits() | Flags::CARRY.bits(), vec![0; 0x10000]);
+            cpu.irq();
+            assert_eq!(
+                cpu.mem.mem[0x01FD],
+                Flags::ZERO.bits() | Flags::CARRY.bits()
+            );
+        }
+
+        #[test]
+        fn irq_sets_pc() {
+            let mut mem = vec![0; 0x10000];
+            mem[0xFFFE] = 0xCD;
+            mem[0xFFFF] = 0xAB;
+            let mut cpu = cpu!(0x00, mem);
+            cpu.irq();
+            assert_eq!(cpu.pc, 0xABCD);
+        }
+    }
+
+    mod adr {
+        use super::*;
+
+        #[test]
+        fn abs_increments_pc() {
+            let mut cpu = cpu!(0x00, vec![0; 10]);
+            cpu.abs(false);
+            assert_eq!(cpu.pc, 2);
+        }
+
+        #[test]
+        fn abs_loads_from_pc() {
+            let mut cpu = cpu!(0x00, vec![0xCD, 0xAB]);
+            let addr = cpu.abs(false);
+            assert_matches!(addr, Address::Absolute(0xABCD));
+        }
+
+        #[test]
+        fn abx_increments_pc() {
+            let mut cpu = cpu!(0x00, vec![0x00, 0x00]);
+            cpu.abx(false);
+            assert_eq!(cpu.pc, 2);
+        }
+
+        #[test]
+        fn abx_loads_from_pc() {
+            let mut cpu = cpu!(0x00, vec![0x80, 0x00]);
+            let addr = cpu.abx(false);
+            assert_matches!(addr, Address::Absolute(0x80));
+        }
+
+        #[test]
+        fn abx_adds_x_to_addr() {
+            let mut cpu = cpu!(0x00, vec![0x80, 0x80]);
+            cpu.x = 0x08;
+            let addr = cpu.abx(false);
+            assert_matches!(addr, Address::Absolute(0x8088));
+        }
+
+        #[test]
+        fn abx_wraps_word() {
+            let mut cpu = cpu!(0x00, vec![0xFF, 0xFF]);
+            cpu.x = 0x01;
+            let addr = cpu.abx(false);
+            assert_matches!(addr, Address::Absolute(0x00));
+        }
+
+        #[test]
+        fn abx_uses_extra_cycle_across_pages() {
+            let mut cpu = cpu!(0x00, vec![0xFF, 0x00]);
+            cpu.x = 0x01;
+            cpu.abx(true); // 0x00FF -> 0x0100
+            assert_eq!(cpu.cy, 3);
+        }
+
+        #[test]
+        fn abx_uses_no_cycle_in_same_page() {
+            let mut cpu = cpu!(0x00, vec![0xC0, 0x00]);
+            cpu.x = 0x0C;
+            cpu.abx(true);
+            assert_eq!(cpu.cy, 2);
+        }
+
+        #[test]
+        fn aby_increments_pc() {
+            let mut cpu = cpu!(0x00, vec![0x00, 0x00]);
+            cpu.aby(false);
+            assert_eq!(cpu.pc, 2);
+        }
+
+        #[test]
+        fn aby_loads_from_pc() {
+            let mut cpu = cpu!(0x00, vec![0x80, 0x70]);
+            let addr = cpu.aby(false);
+            assert_matches!(addr, Address::Absolute(0x7080));
+        }
+
+        #[test]
+        fn aby_adds_y_to_addr() {
+            let mut cpu = cpu!(0x00, vec![0xC0, 0xF0]);
+            cpu.y = 0x0C;
+            let addr = cpu.aby(false);
+            assert_matches!(addr, Address::Absolute(0xF0CC));
+        }
+
+        #[test]
+        fn aby_wraps_word() {
+            let mut cpu = cpu!(0x00, vec![0xFF, 0xFF]);
+            cpu.y = 0x02;
+            let addr = cpu.aby(false);
+            assert_matches!(addr, Address::Absolute(0x0001));
+        }
+
+        #[test]
+        fn aby_uses_extra_cycle_across_pages() {
+            let mut cpu = cpu!(0x00, vec![0xFF, 0x0C]);
+            cpu.y = 0xFF;
+            cpu.aby(true);
+            assert_eq!(cpu.cy, 3);
+        }
+
+        #[test]
+        fn aby_uses_no_cycle_in_same_page() {
+            let mut cpu = cpu!(0x00, vec![0x00, 0x0C]);
+            cpu.y = 0xFF;
+            cpu.aby(true);
+            assert_eq!(cpu.cy, 2);
+        }
+
+        #[test]
+        fn imm_increments_pc() {
+            let mut cpu = cpu!(0x00, vec![0x00]);
+            cpu.imm(false);
+            assert_eq!(cpu.pc, 0x0001);
+        }
+
+        #[test]
+        fn imm_loads_byte_from_pc() {
+            let mut cpu = cpu!(0x00, vec![0x01]);
+            let addr = cpu.imm(false);
+            assert_matches!(addr, Address::Immediate(0x01));
+        }
+
+        #[test]
+        fn ind_incrments_pc() {
+            let mut cpu = cpu!(0x00, vec![0; 4]);
+            cpu.ind(false);
+            assert_matches!(cpu.pc, 2);
+        }
+
+        #[test]
+        fn ind_loads_byte_from_mem() {
+            let mut cpu = cpu!(0x00, vec![0x02, 0x00]);
+            let addr = cpu.ind(false);
+            assert_matches!(addr, Address::Indirect(0x02));
+        }
+
+        #[test]
+        fn izx_increments_pc() {
+            let mut cpu = cpu!(0x00, vec![0; 2]);
+            cpu.izx(false);
+            assert_eq!(cpu.pc, 1);
+        }
+
+        #[test]
+        fn izx_loads_word_from_pc() {
+            let mut cpu = cpu!(0x00, vec![0x01, 0x01, 0x02]);
+            let addr = cpu.izx(false);
+            assert_matches!(addr, Address::Absolute(0x0201));
+        }
+
+        #[test]
+        fn izx_adds_x_to_addr() {
+            let mut cpu = cpu!(0x00, vec![0x01, 0x02, 0x03, 0x04]);
+            cpu.x = 0x01;
+            let addr = cpu.izx(false);
+            assert_matches!(addr, Address::Absolute(0x0403));
+        }
+
+        #[test]
+        fn izx_wraps_x_and_byte() {
+            let mut cpu = cpu!(0x00, vec![0x01, 0x02]);
+            cpu.x = 0xFF;
+            let addr = cpu.izx(false);
+            assert_matches!(addr, Address::Absolute(0x0201));
+        }
+
+        #[test]
+        fn izy_increments_pc() {
+            let mut cpu = cpu!(0x00, vec![0x01, 0x02, 0x03]);
+            cpu.izy(false);
+            assert_eq!(cpu.pc, 1);
+        }
+
+        #[test]
+        fn izy_loads_word_from_pc() {
+            let mut cpu = cpu!(0x00, vec![0x01, 0x02, 0x03]);
+            let addr = cpu.izy(false);
+            assert_matches!(addr, Address::Absolute(0x0302));
+        }
+
+        #[test]
+        fn izy_adds_y_to_addr() {
+            let mut cpu = cpu!(0x00, vec![0x01, 0x02, 0x03]);
+            cpu.y = 0x10;
+            let addr = cpu.izy(false);
+            assert_matches!(addr, Address::Absolute(0x0312));
+        }
+
+        #[test]
+        fn izy_wraps_y_and_byte() {
+            let mut cpu = cpu!(0x00, vec![0x01, 0xFF, 0xFF]);
+            cpu.y = 0x01;
+            let addr = cpu.izy(false);
+            assert_matches!(addr, Address::Absolute(0x00));
+        }
+
+        #[test]
+        fn izy_adds_cycle_when_crossing_pages() {
+            let mut cpu = cpu!(0x00, vec![0x01, 0xFF, 0xFF]);
+            cpu.y = 0x01;
+            cpu.izy(true);
+            assert_eq!(cpu.cy, 4);
+        }
+
+        #[test]
+        fn izy_doesnt_add_cycle_in_same_page() {
+            let mut cpu = cpu!(0x00, vec![0x01, 0xFE, 0xFF]);
+            cpu.y = 0x01;
+            cpu.izy(true);
+            assert_eq!(cpu.cy, 3);
+        }
+
+        #[test]
+        fn rel_increments_pc() {
+            let mut cpu = cpu!(0x00, vec![0; 2]);
+            cpu.rel(false);
+            assert_eq!(cpu.pc, 1);
+        }
+
+        #[test]
+        fn rel_creates_positive_delta() {
+            let mut cpu = cpu!(0x00, vec![0x01; 6]);
+            cpu.pc = 5;
+            let addr = cpu.rel(false);
+            assert_matches!(addr, Address::Absolute(0x07));
+        }
+
+        #[test]
+        fn rel_creates_negative_delta() {
+            let mut cpu = cpu!(0x00, vec![0xFE; 6]);
+            cpu.pc = 0x0005;
+            let addr = cpu.rel(false);
+            assert_matches!(addr, Address::Absolute(0x0004));
+        }
+
+        #[test]
+        fn zp0_increments_pc() {
+            let mut cpu = cpu!(0x00, vec![0x00]);
+            cpu.zp0(false);
+            assert_eq!(cpu.pc, 1);
+        }
+
+        #[test]
+        fn zp0_loads_from_pc() {
+            let mut cpu = cpu!(0x00, vec![0x10]);
+            let addr = cpu.zp0(false);
+            assert_matches!(addr, Address::Absolute(0x0010));
+        }
+
+        #[test]
+        fn zpx_increments_pc() {
+            let mut cpu = cpu!(0x00, vec![0x00]);
+            cpu.zpx(false);
+            assert_eq!(cpu.pc, 1);
+        }
+
+        #[test]
+        fn zpx_loads_from_pc() {
+            let mut cpu = cpu!(0x00, vec![0x03]);
+            let addr = cpu.zpx(false);
+            assert_matches!(addr, Address::Absolute(0x0003));
+        }
+
+        #[test]
+        fn zpx_adds_x_to_addr() {
+            let mut cpu = cpu!(0x00, vec![0x03]);
+            cpu.x = 0x02;
+            let addr = cpu.zpx(false);
+            assert_matches!(addr, Address::Absolute(0x0005));
+        }
+
+        #[test]
+        fn zpx_wraps_overflows() {
+            let mut cpu = cpu!(0x00, vec![0xFF]);
+            cpu.x = 0x02;
+            let addr = cpu.zpx(false);
+            assert_matches!(addr, Address::Absolute(0x0001));
+        }
+
+        #[test]
+        fn zpy_increments_pc() {
+            let mut cpu = cpu!(0x00, vec![0x00]);
+            cpu.zpy(false);
+            assert_eq!(cpu.pc, 1);
+        }
+
+        #[test]
+        fn zpy_loads_from_pc() {
+            let mut cpu = cpu!(0x00, vec![0x01]);
+            let addr = cpu.zpy(false);
+            assert_matches!(addr, Address::Absolute(0x0001));
+        }
+
+        #[test]
+        fn zpy_adds_y_to_addr() {
+            let mut cpu = cpu!(0x00, vec![0x01]);
+            cpu.y = 0x02;
+            let addr = cpu.zpy(false);
+            assert_matches!(addr, Address::Absolute(0x0003));
+        }
+
+        #[test]
+        fn zpy_wraps_overflows() {
+            let mut cpu = cpu!(0x00, vec![0xFF]);
+            cpu.y = 0x02;
+            let addr = cpu.zpy(false);
+            assert_matches!(addr, Address::Absolute(0x0001));
+        }
+    }
+
+    mod ops {
+        use super::*;
+
+        #[test]
+        fn adc_adds_mem() {
+            let mut cpu = cpu!(0x00, vec![]);
+            cpu.adc(Address::Immediate(0x01));
+            assert_eq!(cpu.a, 0x01);
+        }
+
+        #[test]
+        fn adc_adds_carry() {
+            let mut cpu = cpu!(0x00, vec![]);
+            cpu.flags.insert(Flags::CARRY);
+            cpu.adc(Address::Immediate(0x01));
+            assert_eq!(cpu.a, 0x02);
+        }
+
+        #[test]
+        fn adc_wraps_byte_overflow() {
+            let mut cpu = cpu!(0x00, vec![]);
+            cpu.a = 0xFF;
+            cpu.adc(Address::Immediate(0xFF));
+            assert_eq!(cpu.a, 0xFE);
+        }
+
+        #[test]
+        fn adc_sets_v_for_underflow() {
+            let mut cpu = cpu!(0x00, vec![]);
+            cpu.a = 0x80;
+            cpu.adc(Address::Immediate(0xFF));
+            assert!(cpu.flags.intersects(Flags::OVERFLOW));
+        }
+
+        #[test]
+        fn adc_sets_v_for_overflow() {
+            let mut cpu = cpu!(0x00, vec![]);
+            cpu.a = 0x7F;
+            cpu.adc(Address::Immediate(0x01));
+            assert!(cpu.flags.intersects(Flags::OVERFLOW));
+        }
+
+        #[test]
+        fn adc_clears_v_for_non_overflow() {
+            let mut cpu = cpu!(0x00, vec![]);
+            cpu.flags.insert(Flags::OVERFLOW);
+            cpu.a = 0x05;
+            cpu.adc(Address::Immediate(0x05));
+            assert!(!cpu.flags.intersects(Flags::OVERFLOW));
+        }
+
+        #[test]
+        fn adc_sets_c_for_overflow() {
+            let mut cpu = cpu!(0x00, vec![]);
+            cpu.a = 0xFF;
+            cpu.adc(Address::Immediate(0x01));
+            assert!(cpu.flags.intersects(Flags::CARRY));
+        }
+
+        #[test]
+        fn adc_clears_c_no_overflow() {
+            let mut cpu = cpu!(0x00, vec![]);
+            cpu.flags.insert(Flags::CARRY);
+            cpu.a = 0x01;
+            cpu.adc(Address::Immediate(0x01));
+            assert!(!cpu.flags.intersects(Flags::CARRY));
+        }
+
+        #[test]
+        fn adc_adds_decimal() {
+            let mut cpu = cpu!(Flags::DECIMAL.bits());
+            cpu.variant = Variant::Nmos6502;
+            cpu.a = 0x19;
+            cpu.adc(Address::Immediate(0x23));
+            assert_eq!(cpu.a, 0x42);
+            assert!(!cpu.flags.intersects(Flags::CARRY));
+        }
+
+        #[test]
+        fn adc_sets_c_for_decimal_overflow() {
+            let mut cpu = cpu!(Flags::DECIMAL.bits());
+            cpu.variant = Variant::Nmos6502;
+            cpu.a = 0x99;
+            cpu.adc(Address::Immediate(0x01));
+            assert_eq!(cpu.a, 0x00);
+            assert!(cpu.flags.intersects(Flags::CARRY));
+        }
+
+        #[test]
+        fn adc_ignores_decimal_on_2a03() {
+            let mut cpu = cpu!(Flags::DECIMAL.bits());
+            cpu.a = 0x19;
+            cpu.adc(Address::Immediate(0x23));
+            assert_eq!(cpu.a, 0x3C);
+        }
+
+        #[test]
+        fn ahx_ands_a_x_and_high_byte() {
+            let mut cpu = cpu!(0x00, vec![0; 0x0200]);
+            cpu.a = 0x03;
+            cpu.x = 0xFF;
+            cpu.ahx(Address::Absolute(0x0110));
+            assert_eq!(cpu.mem.mem[0x0110], 0x02);
+        }
+
+        #[test]
+        fn alr_ands_then_shifts() {
+            let mut cpu = cpu!();
+            cpu.a = 0x0F;
+            cpu.alr(Address::Immediate(0x05));
+            assert_eq!(cpu.a, 0x02);
+            assert!(cpu.flags.contains(Flags::CARRY));
+        }
+
+        #[test]
+        fn anc_copies_negative_to_carry() {
+            let mut cpu = cpu!();
+            cpu.a = 0xFF;
+            cpu.anc(Address::Immediate(0x80));
+            assert_eq!(cpu.a, 0x80);
+            assert!(cpu.flags.contains(Flags::CARRY | Flags::NEGATIVE));
+        }
+
+        #[test]
+        fn and_logical_ands_a() {
+            let mut cpu = cpu!(0x00, vec![]);
+            cpu.a = 0b10101010;
+            cpu.and(Address::Immediate(0b10000010));
+            assert_eq!(cpu.a, 0b10000010);
+        }
+
+        #[test]
+        fn and_sets_zero_flag() {
+            let mut cpu = cpu!(0x00, vec![]);
+            cpu.and(Address::Immediate(0xFF));
+            assert!(cpu.flags.intersects(Flags::ZERO));
+        }
+
+        #[test]
+        fn and_sets_negative_flag() {
+            let mut cpu = cpu!(0x00, vec![]);
+            cpu.a = 0x80;
+            cpu.and(Address::Immediate(0x80));
+            assert!(cpu.flags.intersects(Flags::NEGATIVE));
+        }
+
+        #[test]
+        fn arr_sets_carry_and_overflow_from_result() {
+            let mut cpu = cpu!(Flags::CARRY.bits());
+            cpu.a = 0xFF;
+            cpu.arr(Address::Immediate(0x40));
+            // (0x40 >> 1) | 0x80
+            assert_eq!(cpu.a, 0xA0);
+            assert!(!cpu.flags.contains(Flags::CARRY));
+            assert!(cpu.flags.contains(Flags::OVERFLOW));
+        }
+
+        #[test]
+        fn arr_adjusts_decimal_digits() {
+            let mut cpu = cpu!(Flags::DECIMAL.bits());
+            cpu.variant = Variant::Nmos6502;
+            cpu.a = 0xFF;
+            cpu.arr(Address::Immediate(0x99));
+            assert_eq!(cpu.a, 0xA2);
+            assert!(cpu.flags.contains(Flags::CARRY | Flags::OVERFLOW));
+        }
+
+        #[test]
+        fn asl_shifts_left() {
+            let mut cpu = cpu!(0x00, vec![]);
+            cpu.a = 0x40;
+            cpu.asl(Address::Accumulator);
+            assert_eq!(0x80, cpu.a);
+        }
+
+        #[test]
+        fn asl_sets_carry_on_overflow() {
+            let mut cpu = cpu!(0x00, vec![]);
+            cpu.a = 0x80;
+            cpu.asl(Address::Accumulator);
+            assert!(cpu.flags.intersects(Flags::CARRY));
+        }
+
+        #[test]
+        fn asl_doesnt_set_carry_flag_without_overflow() {
+            let mut cpu = cpu!(0x00, vec![]);
+            cpu.a = 0x40;
+            cpu.asl(Address::Accumulator);
+            assert!(!cpu.flags.intersects(Flags::CARRY));
+        }
+
+        #[test]
+        fn asl_sets_negative_bit() {
+            let mut cpu = cpu!(0x00, vec![]);
+            cpu.a = 0xF7;
+            cpu.asl(Address::Accumulator);
+            assert!(cpu.flags.intersects(Flags::NEGATIVE));
+        }
+
+        #[test]
+        fn asl_sets_zero_bit() {
+            let mut cpu = cpu!(0x00, vec![]);
+            cpu.a = 0x80;
+            cpu.asl(Address::Accumulator);
+            assert!(cpu.flags.intersects(Flags::ZERO));
+        }
+
+        #[test]
+        fn axs_ignores_carry() {
+            let mut cpu = cpu!();
+            cpu.a = 0x01;
+            cpu.x = 0x01;
+            cpu.axs(Address::Immediate(0x02));
+            assert_eq!(cpu.x, 0xFF);
+            assert!(!cpu.flags.contains(Flags::CARRY));
+        }
+
+        #[test]
+        fn axs_subtracts_from_a_and_x() {
+            let mut cpu = cpu!();
+            cpu.a = 0x0F;
+            cpu.x = 0xFC;
+            cpu.axs(Address::Immediate(0x02));
+            assert_eq!(cpu.x, 0x0A);
+            assert!(cpu.flags.contains(Flags::CARRY));
+        }
+
+        #[test]
+        fn bcc_jumps_when_carry_is_clear() {
+            let mut cpu = cpu!(0x00, vec![]);
+            cpu.bcc(Address::Absolute(0xABCD));
+            assert_eq!(cpu.pc, 0xABCD);
+        }
+
+        #[test]
+        fn bcc_doesnt_jump_when_carry_is_set() {
+            let mut cpu = cpu!(0x00, vec![]);
+            cpu.flags.insert(Flags::CARRY);
+            cpu.bcc(Address::Absolute(0xABCD));
+            assert_eq!(cpu.pc, 0);
+        }
+
+        #[test]
+        fn bcs_jumps_when_carry_is_set() {
+            let mut cpu = cpu!(0x00, vec![]);
+            cpu.flags.insert(Flags::CARRY);
+            cpu.bcs(Address::Absolute(0xABCD));
+            assert_eq!(cpu.pc, 0xABCD);
+        }
+
+        #[test]
+        fn bcs_doesnt_jump_when_carry_is_clear() {
+            let mut cpu = cpu!(0x00, vec![]);
+            cpu.bcs(Address::Absolute(0xABCD));
+            assert_eq!(cpu.pc, 0);
+        }
+
+        #[test]
+        fn beq_jumps_when_zero_is_set() {
+            let mut cpu = cpu!(0x00, vec![]);
+            cpu.flags.insert(Flags::ZERO);
+            cpu.beq(Address::Absolute(0xABCD));
+            assert_eq!(cpu.pc, 0xABCD);
+        }
+
+        #[test]
+        fn beq_doesnt_jump_when_zero_is_clear() {
+            let mut cpu = cpu!(0x00, vec![]);
+            cpu.beq(Address::Absolute(0xABCD));
+            assert_eq!(cpu.pc, 0);
+        }
+
+        #[test]
+        fn bit_sets_n_with_test_value_bit_7() {
+            let mut cpu = cpu!(0x00, vec![0x80]);
+            cpu.bit(Address::Absolute(0x00));
+            assert!(cpu.flags.intersects(Flags::NEGATIVE));
+        }
+
+        #[test]
+        fn bit_sets_v_with_test_value_bit_6() {
+            let mut cpu = cpu!(0x00, vec![0x40]);
+            cpu.bit(Address::Absolute(0x00));
+            assert!(cpu.flags.intersects(Flags::OVERFLOW));
+        }
+
+        #[test]
+        fn bit_sets_z_when_test_and_acc_is_zero() {
+            let mut cpu = cpu!(0x00, vec![0xFF]);
+            cpu.bit(Address::Absolute(0x00));
+            assert!(cpu.flags.intersects(Flags::ZERO));
+        }
+
+        #[test]
+        fn bit_doesnt_set_z_when_test_and_acc_isnt_zero() {
+            let mut cpu = cpu!(0x00, vec![0xFF]);
+            cpu.a = 0x80;
+            cpu.bit(Address::Absolute(0x00));
+            assert!(!cpu.flags.intersects(Flags::ZERO));
+        }
+
+        #[test]
+        fn bit_desnt_affect_a() {
+            let mut cpu = cpu!(0x00, vec![0xFF]);
+            cpu.a = 0x80;
+            cpu.bit(Address::Absolute(0x00));
+            assert_eq!(cpu.a, 0x80);
+        }
+
+        #[test]
+        fn bmi_jumps_when_negative_set() {
+            let mut cpu = cpu!(Flags::NEGATIVE.bits());
+            cpu.bmi(Address::Absolute(0xABCD));
+            assert_eq!(cpu.pc, 0xABCD);
+        }
+
+        #[test]
+        fn bmi_doesnt_jump_when_negative_not_set() {
+            let mut cpu = cpu!();
+            cpu.bmi(Address::Absolute(0xABCD));
+            assert_eq!(cpu.pc, 0);
+        }
+
+        #[test]
+        fn bne_jumps_when_zero_clear() {
+            let mut cpu = cpu!();
+            cpu.bne(Address::Absolute(0xABCD));
+            assert_eq!(cpu.pc, 0xABCD);
+        }
+
+        #[test]
+        fn bne_doesnt_jump_when_zero_set() {
+            let mut cpu = cpu!(Flags::ZERO.bits());
+            cpu.bne(Address::Absolute(0xABCD));
+            assert_eq!(cpu.pc, 0);
+        }
+
+        #[test]
+        fn bpl_jumps_when_negative_clear() {
+            let mut cpu = cpu!();
+            cpu.bpl(Address::Absolute(0xABCD));
+            assert_eq!(cpu.pc, 0xABCD);
+        }
+
+        #[test]
+        fn bpl_doesnt_jump_when_negative_set() {
+            let mut cpu = cpu!(Flags::NEGATIVE.bits());
+            cpu.bpl(Address::Absolute(0xABCD));
+            assert_eq!(cpu.pc, 0);
+        }
+
+        #[test]
+        fn brk_pushes_pc_to_stack() {
+            let mut cpu = cpu!(0x00, vec![0; 0x10000]);
+            cpu.pc = 0x0102;
+            cpu.brk(Address::Implied);
+            assert_eq!(cpu.mem.mem[0x01FE], 0x03);
+            assert_eq!(cpu.mem.mem[0x01FF], 0x01);
+        }
+
+        #[test]
+        fn brk_pushes_flags_to_stack() {
+            let flags = 0x02;
+            let mut cpu = cpu!(flags, vec![0; 0x10000]);
+            cpu.brk(Address::Implied);
+            assert!(cpu.mem.mem[0x01FD] & flags == flags);
+        }
+
+        #[test]
+        fn brk_sets_break_bit() {
+            let mut cpu = cpu!(0x00, vec![0; 0x10000]);
+            cpu.brk(Address::Implied);
+            assert_eq!(
+                cpu.mem.mem[0x01FD] & Flags::BREAK.bits(),
+                Flags::BREAK.bits()
+            );
+        }
+
+        #[test]
+        fn brk_loads_pc_from_fffe() {
+            let mut mem = vec![0; 0x10000];
+            mem[0xFFFE] = 0xCD;
+            mem[0xFFFF] = 0xAB;
+
+            let mut cpu = cpu!(0x00, mem);
+            cpu.brk(Address::Implied);
+            assert_eq!(cpu.pc, 0xABCD);
+        }
+
+        #[test]
+        fn bvc_jumps_when_overflow_clear() {
+            let mut cpu = cpu!();
+            cpu.bvc(Address::Absolute(0xABCD));
+            assert_eq!(cpu.pc, 0xABCD);
+        }
+
+        #[test]
+        fn bvc_doesnt_jump_when_overflow_set() {
+            let mut cpu = cpu!(Flags::OVERFLOW.bits());
+            cpu.bvc(Address::Absolute(0xABCD));
+            assert_eq!(cpu.pc, 0);
+        }
+
+        #[test]
+        fn bvs_jumps_when_overflow_set() {
+            let mut cpu = cpu!(Flags::OVERFLOW.bits());
+            cpu.bvs(Address::Absolute(0xABCD));
+            assert_eq!(cpu.pc, 0xABCD);
+        }
+
+        #[test]
+        fn bvs_doesnt_jump_when_overflow_clear() {
+            let mut cpu = cpu!();
+            cpu.bvs(Address::Absolute(0xABCD));
+            assert_eq!(cpu.pc, 0);
+        }
+
+        #[test]
+        fn clc_clears_carry_flag() {
+            let mut cpu = cpu!(Flags::CARRY.bits());
+            cpu.clc(Address::Implied);
+            assert!(!cpu.flags.intersects(Flags::CARRY));
+        }
+
+        #[test]
+        fn cld_clears_decimal_flag() {
+            let mut cpu = cpu!(Flags::DECIMAL.bits());
+            cpu.cld(Address::Implied);
+            assert!(!cpu.flags.intersects(Flags::DECIMAL));
+        }
+
+        #[test]
+        fn cli_clears_interrupt_disable_flag() {
+            let mut cpu = cpu!(Flags::IRQ_DISABLE.bits());
+            cpu.cli(Address::Implied);
+            assert!(!cpu.flags.intersects(Flags::IRQ_DISABLE));
+        }
+
+        #[test]
+        fn clv_clears_overflow_flag() {
+            let mut cpu = cpu!(Flags::OVERFLOW.bits());
+            cpu.clv(Address::Implied);
+            assert!(!cpu.flags.intersects(Flags::OVERFLOW));
+        }
+
+        #[test]
+        fn cmp_sets_zero_flag() {
+            let mut cpu = cpu!();
+            cpu.a = 0x80;
+            cpu.cmp(Address::Immediate(0x80));
+            assert!(cpu.flags.intersects(Flags::ZERO));
+        }
+
+        #[test]
+        fn cmp_sets_carry_when_mem_lt_acc() {
+            let mut cpu = cpu!();
+            cpu.a = 0xFF;
+            cpu.cmp(Address::Immediate(0x01));
+            assert!(cpu.flags.intersects(Flags::CARRY));
+        }
+
+        #[test]
+        fn cmp_sets_carry_when_mem_eq_acc() {
+            let mut cpu = cpu!();
+            cpu.a = 0xFF;
+            cpu.cmp(Address::Immediate(0xFF));
+            assert!(cpu.flags.intersects(Flags::CARRY));
+        }
+
+        #[test]
+        fn cmp_sets_negative_with_bit7_result() {
+            let mut cpu = cpu!();
+            cpu.a = 0xFF;
+            cpu.cmp(Address::Immediate(0x1F));
+            assert!(cpu.flags.intersects(Flags::NEGATIVE));
+        }
+
+        #[test]
+        fn cpx_sets_zero_flag() {
+            let mut cpu = cpu!();
+            cpu.x = 0x80;
+            cpu.cpx(Address::Immediate(0x80));
+            assert!(cpu.flags.intersects(Flags::ZERO));
+        }
+
+        #[test]
+        fn cpx_sets_carry_when_mem_lt_x() {
+            let mut cpu = cpu!();
+            cpu.x = 0xFF;
+            cpu.cpx(Address::Immediate(0x01));
+            assert!(cpu.flags.intersects(Flags::CARRY));
+        }
+
+        #[test]
+        fn cpx_sets_carry_when_mem_eq_x() {
+            let mut cpu = cpu!();
+            cpu.x = 0xFF;
+            cpu.cpx(Address::Immediate(0xFF));
+            assert!(cpu.flags.intersects(Flags::CARRY));
+        }
+
+        #[test]
+        fn cpx_sets_negative_with_bit7_result() {
+            let mut cpu = cpu!();
+            cpu.x = 0xFF;
+            cpu.cpx(Address::Immediate(0x1F));
+            assert!(cpu.flags.intersects(Flags::NEGATIVE));
+        }
+
+        #[test]
+        fn cpy_sets_zero_flag() {
+            let mut cpu = cpu!();
+            cpu.y = 0x80;
+            cpu.cpy(Address::Immediate(0x80));
+            assert!(cpu.flags.intersects(Flags::ZERO));
+        }
+
+        #[test]
+        fn cpy_sets_carry_when_mem_lt_y() {
+            let mut cpu = cpu!();
+            cpu.y = 0xFF;
+            cpu.cpy(Address::Immediate(0x01));
+            assert!(cpu.flags.intersects(Flags::CARRY));
+        }
+
+        #[test]
+        fn cpy_sets_carry_when_mem_eq_y() {
+            let mut cpu = cpu!();
+            cpu.y = 0xFF;
+            cpu.cpy(Address::Immediate(0xFF));
+            assert!(cpu.flags.intersects(Flags::CARRY));
+        }
+
+        #[test]
+        fn cpy_sets_negative_with_bit7_result() {
+            let mut cpu = cpu!();
+            cpu.y = 0xFF;
+            cpu.cpy(Address::Immediate(0x1F));
+            assert!(cpu.flags.intersects(Flags::NEGATIVE));
+        }
+
+        #[test]
+        fn dec_decrements_memory() {
+            let mut cpu = cpu!(0x00, vec![0x02]);
+            cpu.dec(Address::Absolute(0x00));
+            assert_eq!(cpu.mem.mem[0x00], 0x01);
+        }
+
+        #[test]
+        fn dec_sets_zero_flag() {
+            let mut cpu = cpu!(0x00, vec![0x01]);
+            cpu.dec(Address::Absolute(0x00));
+            assert!(cpu.flags.intersects(Flags::ZERO));
+        }
+
+        #[test]
+        fn dec_sets_negative_flag() {
+            let mut cpu = cpu!(0x00, vec![0xFF]);
+            cpu.dec(Address::Absolute(0x00));
+            assert!(cpu.flags.intersects(Flags::NEGATIVE));
+        }
+
+        #[test]
+        fn dec_wraps_underflows() {
+            let mut cpu = cpu!(0x00, vec![0x00]);
+            cpu.dec(Address::Absolute(0x00));
+            assert_eq!(cpu.mem.mem[0], 0xFF);
+        }
+
+        #[test]
+        fn dex_decrements_x() {
+            let mut cpu = cpu!();
+            cpu.x = 0x02;
+            cpu.dex(Address::Implied);
+            assert_eq!(cpu.x, 0x01);
+        }
+
+        #[test]
+        fn dex_sets_zero_flag() {
+            let mut cpu = cpu!();
+            cpu.x = 0x01;
+            cpu.dex(Address::Implied);
+            assert!(cpu.flags.intersects(Flags::ZERO));
+        }
+
+        #[test]
+        fn dex_sets_negative_flag() {
+            let mut cpu = cpu!();
+            cpu.x = 0xFF;
+            cpu.dex(Address::Implied);
+            assert!(cpu.flags.intersects(Flags::NEGATIVE));
+        }
+
+        #[test]
+        fn dex_wraps_underflows() {
+            let mut cpu = cpu!();
+            cpu.x = 0x00;
+            cpu.dex(Address::Implied);
+            assert_eq!(cpu.x, 0xFF);
+        }
+
+        #[test]
+        fn dey_decrements_y() {
+            let mut cpu = cpu!();
+            cpu.y = 0x02;
+            cpu.dey(Address::Implied);
+            assert_eq!(cpu.y, 0x01);
+        }
+
+        #[test]
+        fn dey_sets_zero_flag() {
+            let mut cpu = cpu!();
+            cpu.y = 0x01;
+            cpu.dey(Address::Implied);
+            assert!(cpu.flags.intersects(Flags::ZERO));
+        }
+
+        #[test]
+        fn dey_sets_negative_flag() {
+            let mut cpu = cpu!();
+            cpu.y = 0xFF;
+            cpu.dey(Address::Implied);
+            assert!(cpu.flags.intersects(Flags::NEGATIVE));
+        }
+
+        #[test]
+        fn dey_wraps_underflows() {
+            let mut cpu = cpu!();
+            cpu.y = 0x00;
+            cpu.dey(Address::Implied);
+            assert_eq!(cpu.y, 0xFF);
+        }
+
+        #[test]
+        fn eor_combines_mem_into_a() {
+            let mut cpu = cpu!();
+            cpu.a = 0b1010;
+            cpu.eor(Address::Immediate(0b1100));
+            assert_eq!(cpu.a, 0b0110);
+        }
+
+        #[test]
+        fn eor_sets_zero_flag() {
+            let mut cpu = cpu!();
+            cpu.a = 0x0F;
+            cpu.eor(Address::Immediate(0x0F));
+            assert!(cpu.flags.intersects(Flags::ZERO));
+        }
+
+        #[test]
+        fn eor_sets_negative_flag() {
+            let mut cpu = cpu!();
+            cpu.a = 0x80;
+            cpu.eor(Address::Immediate(0x08));
+            assert!(cpu.flags.intersects(Flags::NEGATIVE));
+        }
+
+        #[test]
+        fn inc_increments_memory() {
+            let mut cpu = cpu!(0x00, vec![0x02]);
+            cpu.inc(Address::Absolute(0x00));
+            assert_eq!(cpu.mem.mem[0x00], 0x03);
+        }
+
+        #[test]
+        fn inc_sets_zero_flag() {
+            let mut cpu = cpu!(0x00, vec![0xFF]);
+            cpu.inc(Address::Absolute(0x00));
+            assert!(cpu.flags.intersects(Flags::ZERO));
+        }
+
+        #[test]
+        fn inc_sets_negative_flag() {
+            let mut cpu = cpu!(0x00, vec![0x7F]);
+            cpu.inc(Address::Absolute(0x00));
+            assert!(cpu.flags.intersects(Flags::NEGATIVE));
+        }
+
+        #[test]
+        fn inc_wraps_overflows() {
+            let mut cpu = cpu!(0x00, vec![0xFF]);
+            cpu.inc(Address::Absolute(0x00));
+            assert_eq!(cpu.mem.mem[0], 0x00);
+        }
+
+        #[test]
+        fn inx_increments_x() {
+            let mut cpu = cpu!();
+            cpu.x = 0x02;
+            cpu.inx(Address::Implied);
+            assert_eq!(cpu.x, 0x03);
+        }
+
+        #[test]
+        fn inx_sets_zero_flag() {
+            let mut cpu = cpu!();
+            cpu.x = 0xFF;
+            cpu.inx(Address::Implied);
+            assert!(cpu.flags.intersects(Flags::ZERO));
+        }
+
+        #[test]
+        fn inx_sets_negative_flag() {
+            let mut cpu = cpu!();
+            cpu.x = 0x7F;
+            cpu.inx(Address::Implied);
+            assert!(cpu.flags.intersects(Flags::NEGATIVE));
+        }
+
+        #[test]
+        fn inx_wraps_underflows() {
+            let mut cpu = cpu!();
+            cpu.x = 0xFF;
+            cpu.inx(Address::Implied);
+            assert_eq!(cpu.x, 0x00);
+        }
+
+        #[test]
+        fn iny_increments_y() {
+            let mut cpu = cpu!();
+            cpu.y = 0x02;
+            cpu.iny(Address::Implied);
+            assert_eq!(cpu.y, 0x03);
+        }
+
+        #[test]
+        fn iny_sets_zero_flag() {
+            let mut cpu = cpu!();
+            cpu.y = 0xFF;
+            cpu.iny(Address::Implied);
+            assert!(cpu.flags.intersects(Flags::ZERO));
+        }
+
+        #[test]
+        fn iny_sets_negative_flag() {
+            let mut cpu = cpu!();
+            cpu.y = 0x7F;
+            cpu.iny(Address::Implied);
+            assert!(cpu.flags.intersects(Flags::NEGATIVE));
+        }
+
+        #[test]
+        fn iny_wraps_underflows() {
+            let mut cpu = cpu!();
+            cpu.y = 0xFF;
+            cpu.iny(Address::Implied);
+            assert_eq!(cpu.y, 0x00);
+        }
+
+        #[test]
+        fn jmp_sets_pc() {
+            let mut cpu = cpu!(0x00, vec![0x00, 0x01, 0x02]);
+            cpu.jmp(Address::Absolute(0x01));
+            assert_eq!(cpu.pc, 0x0001);
+        }
+
+        #[test]
+        fn jsr_sets_pc() {
+            let mut cpu = cpu!(0x00, vec![0; 0x0200]);
+            cpu.mem.mem[0x0000] = 0xAB;
+            cpu.s = 0xFF;
+
+            cpu.jsr(Address::Immediate(0xCD));
+            assert_eq!(cpu.pc, 0xABCD);
+        }
+
+        #[test]
+        fn jsr_pushes_pc_to_stack() {
+            let mut cpu = cpu!(0x00, vec![0; 0x0200]);
+            cpu.pc = 0xABCC;
+            cpu.s = 0xFF;
+            cpu.jsr(Address::Immediate(0x00));
+            assert_eq!(cpu.mem.mem[0x01FF], 0xAB);
+            assert_eq!(cpu.mem.mem[0x01FE], 0xCC);
+        }
+
+        #[test]
+        fn kil_jams_cpu() {
+            let mut cpu = cpu!(0x00, vec![0x02, 0xEA, 0xEA]);
+            cpu.step();
+            assert!(cpu.is_jammed());
+            cpu.step();
+            assert_eq!(cpu.pc, 0x0001);
+            assert_eq!(cpu.cy, 3);
+        }
+
+        #[test]
+        fn las_ands_memory_with_stack_pointer() {
+            let mut cpu = cpu!(0x00, vec![0x3C]);
+            cpu.s = 0xF0;
+            cpu.las(Address::Absolute(0x00));
+            assert_eq!(cpu.a, 0x30);
+            assert_eq!(cpu.x, 0x30);
+            assert_eq!(cpu.s, 0x30);
+        }
+
+        #[test]
+        fn lda_sets_a() {
+            let mut cpu = cpu!();
+            cpu.lda(Address::Immediate(0x42));
+            assert_eq!(cpu.a, 0x42);
+        }
+
+        #[test]
+        fn lda_sets_zero_flag() {
+            let mut cpu = cpu!();
+            cpu.lda(Address::Immediate(0x00));
+            assert!(cpu.flags.intersects(Flags::ZERO));
+        }
+
+        #[test]
+        fn lda_sets_negative_flag() {
+            let mut cpu = cpu!();
+            cpu.lda(Address::Immediate(0x80));
+            assert!(cpu.flags.intersects(Flags::NEGATIVE));
+        }
+
+        #[test]
+        fn ldx_sets_x() {
+            let mut cpu = cpu!();
+            cpu.ldx(Address::Immediate(0x42));
+            assert_eq!(cpu.x, 0x42);
+        }
+
+        #[test]
+        fn ldx_sets_zero_flag() {
+            let mut cpu = cpu!();
+            cpu.ldx(Address::Immediate(0x00));
+            assert!(cpu.flags.intersects(Flags::ZERO));
+        }
+
+        #[test]
+        fn ldx_sets_negative_flag() {
+            let mut cpu = cpu!();
+            cpu.ldx(Address::Immediate(0x80));
+            assert!(cpu.flags.intersects(Flags::NEGATIVE));
+        }
+
+        #[test]
+        fn ldy_sets_y() {
+            let mut cpu = cpu!();
+            cpu.ldy(Address::Immediate(0x42));
+            assert_eq!(cpu.y, 0x42);
+        }
+
+        #[test]
+        fn ldy_sets_zero_flag() {
+            let mut cpu = cpu!();
+            cpu.ldy(Address::Immediate(0x00));
+            assert!(cpu.flags.intersects(Flags::ZERO));
+        }
+
+        #[test]
+        fn ldy_sets_negative_flag() {
+            let mut cpu = cpu!();
+            cpu.ldy(Address::Immediate(0x80));
+            assert!(cpu.flags.intersects(Flags::NEGATIVE));
+        }
+
+        #[test]
+        fn lsr_shifts_bits_right() {
+            let mut cpu = cpu!();
+            cpu.a = 0b10101010;
+            cpu.lsr(Address::Accumulator);
+            assert_eq!(cpu.a, 0b01010101);
+        }
+
+        #[test]
+        fn lsr_sets_zero() {
+            let mut cpu = cpu!();
+            cpu.a = 0x01;
+            cpu.lsr(Address::Accumulator);
+            assert!(cpu.flags.intersects(Flags::ZERO));
+        }
+
+        #[test]
+        fn lsr_sets_carry() {
+            let mut cpu = cpu!();
+            cpu.a = 0x01;
+            cpu.lsr(Address::Accumulator);
+            assert!(cpu.flags.intersects(Flags::CARRY));
+        }
+
+        #[test]
+        fn ora_ors_bytes() {
+            let mut cpu = cpu!();
+            cpu.a = 0b11001100;
+            cpu.ora(Address::Immediate(0b10101100));
+            assert_eq!(cpu.a, 0b11101100);
+        }
+
+        #[test]
+        fn ora_sets_zero_flag() {
+            let mut cpu = cpu!();
+            cpu.a = 0x00;
+            cpu.ora(Address::Immediate(0x00));
+            assert!(cpu.flags.intersects(Flags::ZERO));
+        }
+
+        #[test]
+        fn ora_sets_negative_flag() {
+            let mut cpu = cpu!();
+            cpu.a = 0x00;
+            cpu.ora(Address::Immediate(0x80));
+            assert!(cpu.flags.intersects(Flags::NEGATIVE));
+        }
+
+        #[test]
+        fn pha_pushes_accumulator_to_stack() {
+            let mut cpu = cpu!(0x00, vec![0; 0x0200]);
+            cpu.a = 0x42;
+            cpu.pha(Address::Implied);
+            assert_eq!(cpu.mem.mem[0x01FF], 0x42);
+        }
+
+        #[test]
+        fn pha_advances_stack_pointer() {
+            let mut cpu = cpu!(0x00, vec![0; 0x0200]);
+            cpu.pha(Address::Implied);
+            assert_eq!(cpu.s, 0xFE);
+        }
+
+        #[test]
+        fn php_pushes_flags_to_stack() {
+            let mut cpu = cpu!(0x42, vec![0; 0x0200]);
+            cpu.php(Address::Implied);
+
+            // Bits 3 & 4 are always set during PHP operation
+            assert_eq!(cpu.mem.mem[0x01FF], 0x42 | 0x30);
+        }
+
+        #[test]
+        fn php_advances_stack_pointer() {
+            let mut cpu = cpu!(0x00, vec![0; 0x0200]);
+            cpu.php(Address::Implied);
+            assert_eq!(cpu.s, 0xFE);
+        }
+
+        #[test]
+        fn pla_reads_byte_from_stack() {
+            let mut mem = vec![0; 0x0200];
+            mem[0x01FF] = 0x42;
+            let mut cpu = cpu!(0x00, mem);
+            cpu.s = 0xFE;
+            cpu.pla(Address::Implied);
+            assert_eq!(cpu.a, 0x42);
+        }
+
+        #[test]
+        fn pla_advances_stack_pointer() {
+            let mut cpu = cpu!(0x00, vec![0; 0x0200]);
+            cpu.s = 0xFE;
+            cpu.pla(Address::Implied);
+            assert_eq!(cpu.s, 0xFF);
+        }
+
+        #[test]
+        fn pla_sets_zero_flag() {
+            let mut cpu = cpu!(0x00, vec![0; 0x0200]);
+            cpu.s = 0xFE;
+            cpu.pla(Address::Implied);
+            assert!(cpu.flags.intersects(Flags::ZERO));
+        }
+
+        #[test]
+        fn pla_sets_negative_flag() {
+            let mut mem = vec![0; 0x0200];
+            mem[0x01FF] = 0x80;
+            let mut cpu = cpu!(0x00, mem);
+            cpu.s = 0xFE;
+            cpu.pla(Address::Implied);
+            assert!(cpu.flags.intersects(Flags::NEGATIVE));
+        }
+
+        #[test]
+        fn plp_sets_status_register() {
+            let mut mem = vec![0; 0x0200];
+            mem[0x01FF] = 0x42;
+            let mut cpu = cpu!(0x00, mem);
+            cpu.s = 0xFE;
+            cpu.plp(Address::Implied);
+
+            // 4th bit is not modified by PLP
+            // 5th bit is always high because it isn't real in the HW
+            assert_eq!(cpu.flags, Flags::from_bits_retain(0x42 & 0xEF | 0x20));
+        }
+
+        #[test]
+        fn plp_advances_stack_pointer() {
+            let mut mem = vec![0; 0x0200];
+            mem[0x01FF] = 0x42;
+            let mut cpu = cpu!(0x00, mem);
+            cpu.s = 0xFE;
+            cpu.plp(Address::Implied);
+            assert_eq!(cpu.s, 0xFF);
+        }
+
+        #[test]
+        fn rol_shifts_bits_left() {
+            let mut cpu = cpu!();
+            cpu.a = 0b01010101;
+            cpu.rol(Address::Accumulator);
+            assert_eq!(cpu.a, 0b10101010);
+        }
+
+        #[test]
+        fn rol_fills_bit_0_with_carry_flag() {
+            let mut cpu = cpu!();
+            cpu.a = 0b11110000;
+            cpu.flags.insert(Flags::CARRY);
+            cpu.rol(Address::Accumulator);
+            assert_eq!(cpu.a, 0b11100001);
+        }
+
+        #[test]
+        fn rol_sets_zero_flag() {
+            let mut cpu = cpu!();
+            cpu.a = 0x80;
+            cpu.rol(Address::Accumulator);
+            assert!(cpu.flags.intersects(Flags::ZERO));
+        }
+
+        #[test]
+        fn rol_sets_negative_flag() {
+            let mut cpu = cpu!();
+            cpu.a = 0x40;
+            cpu.rol(Address::Accumulator);
+            assert!(cpu.flags.intersects(Flags::NEGATIVE));
+        }
+
+        #[test]
+        fn rol_sets_carry_flag() {
+            let mut cpu = cpu!();
+            cpu.a = 0x80;
+            cpu.rol(Address::Accumulator);
+            assert!(cpu.flags.intersects(Flags::CARRY));
+        }
+
+        #[test]
+        fn ror_shifts_bits_right() {
+            let mut cpu = cpu!();
+            cpu.a = 0b01010101;
+            cpu.ror(Address::Accumulator);
+            assert_eq!(cpu.a, 0b00101010);
+        }
+
+        #[test]
+        fn ror_fills_bit_7_with_carry_flag() {
+            let mut cpu = cpu!();
+            cpu.a = 0b11110000;
+            cpu.flags.insert(Flags::CARRY);
+            cpu.ror(Address::Accumulator);
+            assert_eq!(cpu.a, 0b11111000);
+        }
+
+        #[test]
+        fn ror_sets_negative_flag_with_carry() {
+            let mut cpu = cpu!();
+            cpu.flags.insert(Flags::CARRY);
+            cpu.ror(Address::Accumulator);
+            assert_eq!(cpu.a, 0x80);
+        }
+
+        #[test]
+        fn ror_sets_zero_flag() {
+            let mut cpu = cpu!();
+            cpu.a = 0x01;
+            cpu.ror(Address::Accumulator);
+            assert!(cpu.flags.intersects(Flags::ZERO));
+        }
+
+        #[test]
+        fn ror_sets_carry_flag() {
+            let mut cpu = cpu!();
+            cpu.a = 0x01;
+            cpu.ror(Address::Accumulator);
+            assert!(cpu.flags.intersects(Flags::CARRY));
+        }
+
+        #[test]
+        fn rti_restores_flags() {
+            let mut mem = vec![0; 0x0200];
+            mem[0x01FF] = 0xCD;
+            mem[0x01FE] = 0xAB;
+            mem[0x01FD] = 0x42;
+
+            let mut cpu = cpu!(0x00, mem);
+            cpu.s = 0xFC;
+            cpu.rti(Address::Implied);
+
+            // Add 0x20 to assertion since this bit is always high for emu
+            assert_eq!(cpu.flags, Flags::from_bits_retain(0x42 | 0x20));
+        }
+
+        #[test]
+        fn rti_restores_pc() {
+            let mut mem = vec![0; 0x0200];
+            mem[0x01FF] = 0xAB;
+            mem[0x01FE] = 0xCD;
+            mem[0x01FD] = 0x42;
+            let mut cpu = cpu!(0x00, mem);
+            cpu.s = 0xFC;
+            cpu.rti(Address::Implied);
+            assert_eq!(cpu.pc, 0xABCD);
+        }
+
+        #[test]
+        fn rts_restores_pc() {
+            let mut mem = vec![0; 0x0200];
+            mem[0x01FF] = 0xAB;
+            mem[0x01FE] = 0xCC;
+            let mut cpu = cpu!(0x00, mem);
+            cpu.s = 0xFD;
+            cpu.rts(Address::Implied);
+            assert_eq!(cpu.pc, 0xABCD);
+        }
+
+        #[test]
+        fn rts_advances_stack_pointer() {
+            let mut mem = vec![0; 0x0200];
+            mem[0x01FF] = 0xAB;
+            mem[0x01FE] = 0xCC;
+            let mut cpu = cpu!(0x00, mem);
+            cpu.s = 0xFD;
+            cpu.rts(Address::Implied);
+            assert_eq!(cpu.s, 0xFF);
+        }
+
+        #[test]
+        fn sbc_subtracts_mem() {
+            let mut cpu = cpu!(Flags::CARRY.bits(), vec![]);
+            cpu.a = 0x7F;
+            cpu.sbc(Address::Immediate(0x01));
+            assert_eq!(cpu.a, 0x7E);
+        }
+
+        #[test]
+        fn sbc_subtracts_borrow() {
+            let mut cpu = cpu!(0x00, vec![]);
+            cpu.a = 0x7F;
+            cpu.sbc(Address::Immediate(0x01));
+            assert_eq!(cpu.a, 0x7D);
+        }
+
+        #[test]
+        fn sbc_wraps_byte_underflow() {
+            let mut cpu = cpu!(Flags::CARRY.bits(), vec![]);
+            cpu.a = 0x00;
+            cpu.sbc(Address::Immediate(0x01));
+            assert_eq!(cpu.a, 0xFF);
+        }
+
+        #[test]
+        fn sbc_sets_v_for_overflow() {
+            let mut cpu = cpu!(Flags::CARRY.bits(), vec![]);
+            cpu.a = 0x00;
+            cpu.sbc(Address::Immediate(0x80));
+            assert!(cpu.flags.intersects(Flags::OVERFLOW));
+        }
+
+        #[test]
+        fn sbc_sets_v_for_underflow() {
+            let mut cpu = cpu!(Flags::CARRY.bits(), vec![]);
+            cpu.a = 0x80;
+            cpu.sbc(Address::Immediate(0x01));
+            assert!(cpu.flags.intersects(Flags::OVERFLOW));
+        }
+
+        #[test]
+        fn sbc_clears_v_for_non_overflow() {
+            let flags = Flags::CARRY.bits() | Flags::OVERFLOW.bits();
+            let mut cpu = cpu!(flags, vec![]);
+            cpu.a = 0x0A;
+            cpu.sbc(Address::Immediate(0x05));
+            assert!(!cpu.flags.intersects(Flags::OVERFLOW));
+        }
+
+        #[test]
+        fn sbc_clears_c_for_borrow() {
+            let mut cpu = cpu!(Flags::CARRY.bits(), vec![]);
+            cpu.a = 0x02;
+            cpu.sbc(Address::Immediate(0x05));
+            assert!(!cpu.flags.intersects(Flags::CARRY));
+        }
+
+        #[test]
+        fn sbc_sets_c_for_no_borrow() {
+            let mut cpu = cpu!(Flags::CARRY.bits(), vec![]);
+            cpu.a = 0x0A;
+            cpu.sbc(Address::Immediate(0x05));
+            assert!(cpu.flags.intersects(Flags::CARRY));
+        }
+
+        #[test]
+        fn sbc_subtracts_decimal() {
+            let mut cpu = cpu!((Flags::DECIMAL | Flags::CARRY).bits());
+            cpu.variant = Variant::Nmos6502;
+            cpu.a = 0x42;
+            cpu.sbc(Address::Immediate(0x13));
+            assert_eq!(cpu.a, 0x29);
+            assert!(cpu.flags.intersects(Flags::CARRY));
+        }
+
+        #[test]
+        fn sbc_wraps_decimal_underflow() {
+            let mut cpu = cpu!((Flags::DECIMAL | Flags::CARRY).bits());
+            cpu.variant = Variant::Nmos6502;
+            cpu.a = 0x00;
+            cpu.sbc(Address::Immediate(0x01));
+            assert_eq!(cpu.a, 0x99);
+            assert!(!cpu.flags.intersects(Flags::CARRY));
+        }
+
+        #[test]
+        fn sec_sets_carry_flag() {
+            let mut cpu = cpu!();
+            cpu.sec(Address::Implied);
+            assert!(cpu.flags.intersects(Flags::CARRY));
+        }
+
+        #[test]
+        fn sed_sets_decimal_flag() {
+            let mut cpu = cpu!();
+            cpu.sed(Address::Implied);
+            assert!(cpu.flags.intersects(Flags::DECIMAL));
+        }
+
+        #[test]
+        fn sei_sets_interrupt_flag() {
+            let mut cpu = cpu!();
+            cpu.sei(Address::Implied);
+            assert!(cpu.flags.intersects(Flags::IRQ_DISABLE));
+        }
+
+        #[test]
+        fn shx_ands_x_with_high_byte() {
+            let mut cpu = cpu!(0x00, vec![0; 0x0200]);
+            cpu.x = 0xFF;
+            cpu.y = 0x01;
+            cpu.shx(Address::Absolute(0x0110));
+            assert_eq!(cpu.mem.mem[0x0110], 0x02);
+        }
+
+        #[test]
+        fn shx_corrupts_address_across_pages() {
+            let mut cpu = cpu!(0x00, vec![0; 0x0200]);
+            cpu.x = 0x01;
+            cpu.y = 0x20;
+            // 0x00F0 + 0x20 crosses into page 1, but X & 0x01 sends the write
+            // to page 1 anyway
+            cpu.shx(Address::Absolute(0x0110));
+            assert_eq!(cpu.mem.mem[0x0110], 0x01);
+
+            cpu.x = 0x00;
+            cpu.shx(Address::Absolute(0x0110));
+            assert_eq!(cpu.mem.mem[0x0010], 0x00);
+            assert_eq!(cpu.mem.mem[0x0110], 0x01);
+        }
+
+        #[test]
+        fn shy_ands_y_with_high_byte() {
+            let mut cpu = cpu!(0x00, vec![0; 0x0200]);
+            cpu.y = 0xFF;
+            cpu.shy(Address::Absolute(0x0110));
+            assert_eq!(cpu.mem.mem[0x0110], 0x02);
+        }
+
+        #[test]
+        fn sta_stores_acc_to_memory() {
+            let mut cpu = cpu!(0x00, vec![0x00, 0x01]);
+            cpu.a = 0x42;
+            cpu.sta(Address::Absolute(0x01));
+            assert_eq!(cpu.mem.mem[0x01], 0x42);
+        }
+
+        #[test]
+        fn stx_stores_x_to_memory() {
+            let mut cpu = cpu!(0x00, vec![0x00, 0x01]);
+            cpu.x = 0x42;
+            cpu.stx(Address::Absolute(0x01));
+            assert_eq!(cpu.mem.mem[0x01], 0x42);
+        }
+
+        #[test]
+        fn sty_stores_y_to_memory() {
+            let mut cpu = cpu!(0x00, vec![0x00, 0x01]);
+            cpu.y = 0x42;
+            cpu.sty(Address::Absolute(0x01));
+            assert_eq!(cpu.mem.mem[0x01], 0x42);
+        }
+
+        #[test]
+        fn tas_sets_stack_pointer() {
+            let mut cpu = cpu!(0x00, vec![0; 0x0200]);
+            cpu.a = 0xF3;
+            cpu.x = 0x3F;
+            cpu.tas(Address::Absolute(0x0110));
+            assert_eq!(cpu.s, 0x33);
+            assert_eq!(cpu.mem.mem[0x0110], 0x02);
+        }
+
+        #[test]
+        fn tax_stores_acc_in_x() {
+            let mut cpu = cpu!();
+            cpu.a = 0x42;
+            cpu.tax(Address::Implied);
+            assert_eq!(cpu.x, 0x42);
+        }
+
+        #[test]
+        fn tax_sets_zero_flag() {
+            let mut cpu = cpu!();
+            cpu.a = 0x00;
+            cpu.tax(Address::Implied);
+            assert!(cpu.flags.intersects(Flags::ZERO));
+        }
+
+        #[test]
+        fn tax_sets_negative_flag() {
+            let mut cpu = cpu!();
+            cpu.a = 0x80;
+            cpu.tax(Address::Implied);
+            assert!(cpu.flags.intersects(Flags::NEGATIVE));
+        }
+
+        #[test]
+        fn tay_stores_acc_in_y() {
+            let mut cpu = cpu!();
+            cpu.a = 0x42;
+            cpu.tay(Address::Implied);
+            assert_eq!(cpu.y, 0x42);
+        }
+
+        #[test]
+        fn tay_sets_zero_flag() {
+            let mut cpu = cpu!();
+            cpu.a = 0x00;
+            cpu.tay(Address::Implied);
+            assert!(cpu.flags.intersects(Flags::ZERO));
+        }
+
+        #[test]
+        fn tay_sets_negative_flag() {
+            let mut cpu = cpu!();
+            cpu.a = 0x80;
+            cpu.tay(Address::Implied);
+            assert!(cpu.flags.intersects(Flags::NEGATIVE));
+        }
+
+        #[test]
+        fn tsx_sets_x_to_stack_pointer() {
+            let mut cpu = cpu!(0x00);
+            cpu.s = 0xFE;
+            cpu.tsx(Address::Implied);
+            assert_eq!(cpu.x, 0xFE);
+        }
+
+        #[test]
+        fn tsx_doesnt_update_stack_pointer() {
+            let mut cpu = cpu!(0x00, vec![0; 0x0200]);
+            cpu.s = 0xFE;
+            cpu.tsx(Address::Implied);
+            assert_eq!(cpu.s, 0xFE);
+        }
+
+        #[test]
+        fn tsx_sets_zero_flag() {
+            let mut cpu = cpu!(0x00, vec![0; 0x0200]);
+            cpu.s = 0x00;
+            cpu.tsx(Address::Implied);
+            assert!(cpu.flags.intersects(Flags::ZERO));
+        }
+
+        #[test]
+        fn tsx_sets_negative_flag() {
+            let mut mem = vec![0; 0x0200];
+            mem[0x01FF] = 0x80;
+            let mut cpu = cpu!(0x00, mem);
+            cpu.s = 0xFE;
+            cpu.tsx(Address::Implied);
+            assert!(cpu.flags.intersects(Flags::NEGATIVE));
+        }
+
+        #[test]
+        fn txa_sets_x_to_a() {
+            let mut cpu = cpu!();
+            cpu.x = 0x42;
+            cpu.txa(Address::Implied);
+            assert_eq!(cpu.a, 0x42);
+        }
+
+        #[test]
+        fn txa_sets_zero_flag() {
+            let mut cpu = cpu!();
+            cpu.txa(Address::Implied);
+            assert!(cpu.flags.intersects(Flags::ZERO));
+        }
+
+        #[test]
+        fn txa_sets_negative_flag() {
+            let mut cpu = cpu!();
+            cpu.x = 0x80;
+            cpu.txa(Address::Implied);
+            assert!(cpu.flags.intersects(Flags::NEGATIVE));
+        }
+
+        #[test]
+        fn txs_copies_x_into_stack_pointer() {
+            let mut cpu = cpu!(0x00);
+            cpu.x = 0x42;
+            cpu.txs(Address::Implied);
+            assert_eq!(cpu.s, 0x42);
+        }
+
+        #[test]
+        fn tya_sets_y_to_a() {
+            let mut cpu = cpu!();
+            cpu.y = 0x42;
+            cpu.tya(Address::Implied);
+            assert_eq!(cpu.a, 0x42);
+        }
+
+        #[test]
+        fn tya_sets_zero_flag() {
+            let mut cpu = cpu!();
+            cpu.tya(Address::Implied);
+            assert!(cpu.flags.intersects(Flags::ZERO));
+        }
+
+        #[test]
+        fn tya_sets_negative_flag() {
+            let mut cpu = cpu!();
+            cpu.y = 0x80;
+            cpu.tya(Address::Implied);
+            assert!(cpu.flags.intersects(Flags::NEGATIVE));
+        }
+
+        #[test]
+        fn xaa_ands_x_and_immediate() {
+            let mut cpu = cpu!();
+            cpu.a = 0x00;
+            cpu.x = 0xFF;
+            cpu.xaa(Address::Immediate(0x0F));
+            assert_eq!(cpu.a, 0x0E);
+        }
+
+        #[test]
+        fn lxa_ands_immediate_into_a_and_x() {
+            let mut cpu = cpu!();
+            cpu.a = 0x01;
+            cpu.x = 0x00;
+            cpu.lxa(Address::Immediate(0x8F));
+            assert_eq!(cpu.a, 0x8F);
+            assert_eq!(cpu.x, 0x8F);
+            assert!(cpu.flags.intersects(Flags::NEGATIVE));
+        }
+    }
+
+    mod timing {
+        use super::*;
+
+        /// Cycles taken by each opcode when indexing doesn't cross a page and
+        /// branches aren't taken
+        #[rustfmt::skip]
+        const CYCLES: [u64; 256] = [
+            7, 6, 2, 8, 3, 3, 5, 5, 3, 2, 2, 2, 4, 4, 6, 6,
+            2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+            6, 6, 2, 8, 3, 3, 5, 5, 4, 2, 2, 2, 4, 4, 6, 6,
+            2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+            6, 6, 2, 8, 3, 3, 5, 5, 3, 2, 2, 2, 3, 4, 6, 6,
+            2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+            6, 6, 2, 8, 3, 3, 5, 5, 4, 2, 2, 2, 5, 4, 6, 6,
+            2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+            2, 6, 2, 6, 3, 3, 3, 3, 2, 2, 2, 2, 4, 4, 4, 4,
+            2, 6, 2, 6, 4, 4, 4, 4, 2, 5, 2, 5, 5, 5, 5, 5,
+            2, 6, 2, 6, 3, 3, 3, 3, 2, 2, 2, 2, 4, 4, 4, 4,
+            2, 5, 2, 5, 4, 4, 4, 4, 2, 4, 2, 4, 4, 4, 4, 4,
+            2, 6, 2, 8, 3, 3, 5, 5, 2, 2, 2, 2, 4, 4, 6, 6,
+            2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+            2, 6, 2, 8, 3, 3, 5, 5, 2, 2, 2, 2, 4, 4, 6, 6,
+            2, 5, 2, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+        ];
+
+        fn run(op: u8, operands: [u8; 2]) -> Cpu<VecMem> {
+            let mut cpu = cpu!(0x00, vec![0; 0x10000]);
+            cpu.pc = 0x0200;
+            cpu.mem.mem[0x0200] = op;
+            cpu.mem.mem[0x0201..0x0203].copy_from_slice(&operands);
+            cpu.step();
+            cpu
+        }
+
+        #[test]
+        fn every_opcode_takes_the_right_number_of_cycles() {
+            for (op, &cycles) in CYCLES.iter().enumerate() {
+                let op = op as u8;
+                let cpu = run(op, [0x10, 0x00]);
+
+                // Branches take one more cycle when they are taken
+                let is_branch = op & 0x1F == 0x10;
+                let ok = cpu.cy == cycles || (is_branch && cpu.cy == cycles + 1);
+                assert!(ok, "opcode {:02X} took {} cycles", op, cpu.cy);
+            }
+        }
+
+        #[test]
+        fn taken_branch_across_pages_takes_two_extra_cycles() {
+            // BNE -16 from 0x0202 lands in the page before
+            let cpu = run(0xD0, [0xF0, 0x00]);
+            assert_eq!(cpu.pc, 0x01F2);
+            assert_eq!(cpu.cy, 4);
+        }
+
+        #[test]
+        fn indexed_store_always_takes_fixup_cycle() {
+            let cpu = run(0x9D, [0x10, 0x00]);
+            assert_eq!(cpu.cy, 5);
+        }
+
+        fn nop_cpu(start_cycle: u64) -> Cpu<VecMem> {
+            let mut cpu = cpu!(0x00, vec![0xEA; 0x10000]);
+            cpu.cy = start_cycle;
+            cpu
+        }
+
+        #[test]
+        fn oam_dma_takes_513_or_514_cycles() {
+            let mut stalls = Vec::new();
+
+            for start in 0..2 {
+                // STA $4014
+                let mut cpu = nop_cpu(start);
+                cpu.mem.mem[0x0000..0x0003].copy_from_slice(&[0x8D, 0x14, 0x40]);
+                cpu.mem.mem[0x03FF] = 0x42;
+                cpu.a = 0x03;
+                cpu.step();
+
+                // The DMA happens when the next instruction is fetched
+                let before = cpu.cy;
+                cpu.step();
+                stalls.push(cpu.cy - before - 2);
+                assert_eq!(cpu.mem.mem[0x2004], 0x42);
+            }
+
+            stalls.sort();
+            assert_eq!(stalls, vec![513, 514]);
+        }
+
+        #[test]
+        fn oam_dma_only_on_2a03() {
+            // STA $4014
+            let mut cpu = nop_cpu(0);
+            cpu.variant = Variant::Nmos6502;
+            cpu.mem.mem[0x0000..0x0003].copy_from_slice(&[0x8D, 0x14, 0x40]);
+            cpu.a = 0x03;
+            cpu.step();
+
+            let before = cpu.cy;
+            cpu.step();
+            assert_eq!(cpu.cy - before, 2);
+            assert_eq!(cpu.mem.mem[0x4014], 0x03);
+        }
+
+        #[test]
+        fn oam_dma_needs_the_bus_wired_up() {
+            struct Unwired(Vec<u8>);
+
+            impl Mem for Unwired {
+                fn peekb(&self, addr: u16) -> u8 {
+                    self.0[addr as usize]
+                }
+
+                fn storeb(&mut self, addr: u16, val: u8) {
+                    self.0[addr as usize] = val;
+                }
+            }
+
+            // STA $4014
+            let mut cpu = Cpu::new(Variant::Ricoh2A03, Unwired(vec![0xEA; 0x10000]));
+            cpu.mem.0[0x0000..0x0003].copy_from_slice(&[0x8D, 0x14, 0x40]);
+            cpu.a = 0x03;
+            cpu.step();
+
+            let before = cpu.cy;
+            cpu.step();
+            assert_eq!(cpu.cy - before, 2);
+            assert_eq!(cpu.mem.0[0x4014], 0x03);
+        }
+
+        #[test]
+        fn dmc_dma_takes_3_or_4_cycles() {
+            let mut stalls = Vec::new();
+
+            for start in 0..2 {
+                let mut cpu = nop_cpu(start);
+                cpu.mem.mem[0xC000] = 0x5A;
+                cpu.mem.dmc = Some(0xC000);
+                cpu.step();
+
+                stalls.push(cpu.cy - start - 2);
+                assert_eq!(cpu.mem.dmc_byte, Some(0x5A));
+            }
+
+            stalls.sort();
+            assert_eq!(stalls, vec![3, 4]);
+        }
+
+        #[test]
+        fn dmc_dma_during_oam_dma_steals_fewer_cycles() {
+            let mut cpu = nop_cpu(0);
+            cpu.mem.mem[0x0000..0x0003].copy_from_slice(&[0x8D, 0x14, 0x40]);
+            cpu.a = 0x03;
+            cpu.step();
+            cpu.mem.dmc = Some(0xC000);
+
+            let before = cpu.cy;
+            cpu.step();
+            let stall = cpu.cy - before - 2;
+
+            assert!(cpu.mem.dmc_byte.is_some());
+            assert!(stall > 514 && stall <= 516, "stalled for {}", stall);
+        }
+
+        #[test]
+        fn dmc_dma_during_controller_read_clocks_it_twice() {
+            struct Controller {
+                reads: usize,
+                dmc: bool,
+            }
+
+            impl Mem for Controller {
+                fn peekb(&self, _addr: u16) -> u8 {
+                    0
+                }
+
+                fn loadb(&mut self, addr: u16) -> u8 {
+                    if addr == 0x4016 {
+                        self.reads += 1;
+                    }
+
+                    self.peekb(addr)
+                }
+
+                fn storeb(&mut self, _addr: u16, _val: u8) {}
+
+                fn dma(&mut self) -> Option<&mut dyn Dma> {
+                    Some(self)
+                }
+            }
+
+            impl Dma for Controller {
+                fn starts_page_copy(&self, _addr: u16) -> bool {
+                    false
+                }
+
+                fn page_copy_port(&self) -> u16 {
+                    0x2004
+                }
+
+                fn fetch_addr(&self) -> Option<u16> {
+                    self.dmc.then_some(0xC000)
+                }
+
+                fn fetch_complete(&mut self, _val: u8) {
+                    self.dmc = false;
+                }
+
+                fn repeats_halted_reads(&self, addr: u16) -> bool {
+                    addr != 0x4016
+                }
+            }
+
+            // The halt cycle repeats the read, but the dummy cycles don't
+            let mut cpu = Cpu::new(
+                Variant::Ricoh2A03,
+                Controller {
+                    reads: 0,
+                    dmc: true,
+                },
+            );
+            cpu.loadb(0x4016);
+
+            assert_eq!(cpu.mem.reads, 2);
+        }
+
+        #[test]
+        fn read_modify_write_writes_value_back_first() {
+            struct WriteLog(Vec<u8>, Vec<(u16, u8)>);
+
+            impl Mem for WriteLog {
+                fn peekb(&self, addr: u16) -> u8 {
+                    self.0.get(addr as usize).copied().unwrap_or(0)
+                }
+
+                fn storeb(&mut self, addr: u16, val: u8) {
+                    self.1.push((addr, val));
+                }
+            }
+
+            // INC $0003
+            let mut cpu = Cpu::new(
+                Variant::Ricoh2A03,
+                WriteLog(vec![0xEE, 0x03, 0x00, 0x41], Vec::new()),
+            );
+            cpu.pc = 0;
+            cpu.step();
+
+            assert_eq!(cpu.mem.1, vec![(0x0003, 0x41), (0x0003, 0x42)]);
+        }
+
+        #[test]
+        fn operands_are_read_low_byte_first() {
+            struct ReadLog(Vec<u8>, Vec<u16>);
+
+            impl Mem for ReadLog {
+                fn peekb(&self, addr: u16) -> u8 {
+                    self.0.get(addr as usize).copied().unwrap_or(0)
+                }
+
+                fn loadb(&mut self, addr: u16) -> u8 {
+                    self.1.push(addr);
+                    self.peekb(addr)
+                }
+
+                fn storeb(&mut self, _addr: u16, _val: u8) {}
+            }
+
+            // LDA $0003
+            let mut cpu = Cpu::new(
+                Variant::Ricoh2A03,
+                ReadLog(vec![0xAD, 0x03, 0x00, 0x41], Vec::new()),
+            );
+            cpu.pc = 0;
+            cpu.step();
+
+            assert_eq!(cpu.mem.1, vec![0x0000, 0x0001, 0x0002, 0x0003]);
+        }
+    }
+}
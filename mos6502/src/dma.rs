@@ -0,0 +1,30 @@
+/// The DMA unit of the Ricoh 2A03. It halts the CPU and takes over the bus
+/// for two kinds of transfer: copying a whole page to a single port, and
+/// fetching single bytes on behalf of another device. On the NES these are
+/// OAM DMA and DMC DMA.
+///
+/// The CPU decides when each transfer gets the bus. The system it is wired
+/// into decides which registers start a transfer, where the bytes go, and
+/// which devices ask for them, so those are answered through this trait.
+pub trait Dma {
+    /// Whether a write to `addr` starts a page copy. The value written is the
+    /// high byte of the page's address, and the write doesn't reach the bus.
+    fn starts_page_copy(&self, addr: u16) -> bool;
+
+    /// The port that every byte of a page copy is written to
+    fn page_copy_port(&self) -> u16;
+
+    /// The address of the next byte a device is waiting on, if any. The CPU
+    /// halts to fetch the byte and hands it over with [Dma::fetch_complete].
+    fn fetch_addr(&self) -> Option<u16>;
+
+    /// Deliver a byte that the CPU fetched for a device
+    fn fetch_complete(&mut self, val: u8);
+
+    /// While halted, the CPU keeps repeating the read it was halted on.
+    /// Devices that would see each of those reads as a new access, but which
+    /// are wired to ignore them, return false here.
+    fn repeats_halted_reads(&self, _addr: u16) -> bool {
+        true
+    }
+}
@@ -0,0 +1,12 @@
+#[macro_use]
+mod byte;
+
+mod cpu;
+mod dma;
+mod mem;
+mod variant;
+
+pub use cpu::{Cpu, Flags, InternalState};
+pub use dma::Dma;
+pub use mem::Mem;
+pub use variant::Variant;
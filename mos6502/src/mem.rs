@@ -1,4 +1,31 @@
+use crate::dma::Dma;
+
 pub trait Mem {
+    /// Called by the CPU at the start of every one of its cycles, before that
+    /// cycle's read or write. Devices on the bus use this to run in lockstep
+    /// with the CPU.
+    fn tick(&mut self) {}
+
+    /// Called by the CPU at the end of every cycle. Returns true if the NMI
+    /// line has been pulled low since the last call. The CPU only reacts to
+    /// the falling edge, so each NMI should be reported once.
+    fn poll_nmi(&mut self) -> bool {
+        false
+    }
+
+    /// Whether any device is currently holding the IRQ line low. IRQs are
+    /// level triggered, so the CPU keeps servicing them for as long as this
+    /// stays true and interrupts are enabled.
+    fn irq_line(&self) -> bool {
+        false
+    }
+
+    /// How the CPU's DMA unit is wired into the system. Only variants with a
+    /// DMA unit use this, and systems without one can leave it alone.
+    fn dma(&mut self) -> Option<&mut dyn Dma> {
+        None
+    }
+
     /// For debugging only - show the current value of a byte without modifying
     /// the system state. The real hardware cannot always do this (e.g. reading
     /// from PPUSTATUS), so it should not be used in normal operations.
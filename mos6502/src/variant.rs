@@ -0,0 +1,27 @@
+/// The chips built around the 6502 core differ in a few places. The variant
+/// decides which of those behaviours the CPU emulates.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Variant {
+    /// The NMOS 6502, as found in the Apple II, the Commodore 64 (as the
+    /// 6510), and most other home computers of its era
+    Nmos6502,
+
+    /// The Ricoh 2A03 (NTSC) and 2A07 (PAL) in the NES. The decimal mode
+    /// circuitry is cut, and the chip adds a DMA unit that can halt the CPU
+    /// to take over the bus.
+    Ricoh2A03,
+}
+
+impl Variant {
+    /// Whether ADC and SBC honor the decimal flag. The flag itself can be set
+    /// and cleared on every variant.
+    pub fn has_decimal_mode(self) -> bool {
+        self == Variant::Nmos6502
+    }
+
+    /// Whether the chip has a DMA unit. How it is wired up is left to the bus,
+    /// through [crate::Mem::dma].
+    pub fn has_dma(self) -> bool {
+        self == Variant::Ricoh2A03
+    }
+}
@@ -0,0 +1,188 @@
+use mos6502::{Cpu, Flags, Mem, Variant};
+use serde::Deserialize;
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+/// Points at a checkout of the SingleStepTests 65x02 test vectors
+/// (https://github.com/SingleStepTests/65x02). The tests are ignored by
+/// default, and fail if this isn't set when they're run.
+const TESTS_ENV: &str = "SINGLE_STEP_TESTS";
+
+/// How many mismatches to print for each failing opcode
+const MAX_REPORTED: usize = 3;
+
+#[derive(Deserialize)]
+struct Test {
+    name: String,
+    initial: State,
+    #[serde(rename = "final")]
+    expected: State,
+    cycles: Vec<(u16, u8, String)>,
+}
+
+#[derive(Deserialize)]
+struct State {
+    pc: u16,
+    s: u8,
+    a: u8,
+    x: u8,
+    y: u8,
+    p: u8,
+    ram: Vec<(u16, u8)>,
+}
+
+/// 64KiB of RAM that records every access the CPU makes, one per cycle.
+/// There's no DMA unit wired up, so the 2A03 treats writes to $4014 like any
+/// other write, which is what the vectors expect.
+struct TestMem {
+    ram: Vec<u8>,
+    cycles: Vec<(u16, u8, String)>,
+}
+
+impl Mem for TestMem {
+    fn peekb(&self, addr: u16) -> u8 {
+        self.ram[addr as usize]
+    }
+
+    fn loadb(&mut self, addr: u16) -> u8 {
+        let val = self.ram[addr as usize];
+        self.cycles.push((addr, val, "read".to_string()));
+        val
+    }
+
+    fn storeb(&mut self, addr: u16, val: u8) {
+        self.ram[addr as usize] = val;
+        self.cycles.push((addr, val, "write".to_string()));
+    }
+}
+
+/// Run one test case, returning a description of everything that didn't
+/// match
+fn run_test(variant: Variant, test: &Test) -> Vec<String> {
+    let mut mem = TestMem {
+        ram: vec![0; 0x10000],
+        cycles: Vec::new(),
+    };
+
+    for &(addr, val) in &test.initial.ram {
+        mem.ram[addr as usize] = val;
+    }
+
+    let mut cpu = Cpu::new(variant, mem);
+    cpu.pc = test.initial.pc;
+    cpu.s = test.initial.s;
+    cpu.a = test.initial.a;
+    cpu.x = test.initial.x;
+    cpu.y = test.initial.y;
+    cpu.flags = Flags::from_bits_retain(test.initial.p);
+
+    cpu.step();
+
+    let expected = &test.expected;
+    let mut mismatches = Vec::new();
+    let mut check = |what: &str, actual: u16, expected: u16| {
+        if actual != expected {
+            mismatches.push(format!(
+                "{}: {} was ${:02X}, expected ${:02X}",
+                test.name, what, actual, expected
+            ));
+        }
+    };
+
+    check("PC", cpu.pc, expected.pc);
+    check("S", cpu.s.into(), expected.s.into());
+    check("A", cpu.a.into(), expected.a.into());
+    check("X", cpu.x.into(), expected.x.into());
+    check("Y", cpu.y.into(), expected.y.into());
+    check("P", cpu.flags.bits().into(), expected.p.into());
+
+    for &(addr, val) in &expected.ram {
+        let what = format!("${:04X}", addr);
+        check(&what, cpu.mem.ram[addr as usize].into(), val.into());
+    }
+
+    if cpu.mem.cycles != test.cycles {
+        mismatches.push(format!(
+            "{}: bus activity was {:?}, expected {:?}",
+            test.name, cpu.mem.cycles, test.cycles
+        ));
+    }
+
+    mismatches
+}
+
+/// Run every opcode's test file in a directory, printing a summary of the
+/// opcodes that failed. Panics if any test failed.
+fn run_dir(variant: Variant, dir: &Path) {
+    let mut failed_opcodes = 0;
+    let mut failed_tests = 0;
+    let mut total_tests = 0;
+
+    for opcode in 0..=0xFF {
+        let path = dir.join(format!("{:02x}.json", opcode));
+        let json = match fs::read_to_string(&path) {
+            Ok(json) => json,
+            Err(_) => continue,
+        };
+
+        let tests: Vec<Test> = serde_json::from_str(&json)
+            .unwrap_or_else(|err| panic!("Failed to parse {}: {}", path.display(), err));
+
+        let mut failures = tests
+            .iter()
+            .map(|test| run_test(variant, test))
+            .filter(|mismatches| !mismatches.is_empty())
+            .collect::<Vec<_>>();
+
+        total_tests += tests.len();
+
+        if failures.is_empty() {
+            continue;
+        }
+
+        failed_opcodes += 1;
+        failed_tests += failures.len();
+
+        eprintln!(
+            "${:02X}: {} of {} tests failed",
+            opcode,
+            failures.len(),
+            tests.len()
+        );
+
+        failures.truncate(MAX_REPORTED);
+        for mismatch in failures.iter().flatten() {
+            eprintln!("    {}", mismatch);
+        }
+    }
+
+    assert!(total_tests > 0, "No tests found in {}", dir.display());
+    assert!(
+        failed_tests == 0,
+        "{} of {} tests failed, across {} opcodes",
+        failed_tests,
+        total_tests,
+        failed_opcodes
+    );
+}
+
+fn tests_dir(variant_dir: &str) -> PathBuf {
+    let root = env::var_os(TESTS_ENV)
+        .unwrap_or_else(|| panic!("{} must point at the test vectors", TESTS_ENV));
+
+    Path::new(&root).join(variant_dir).join("v1")
+}
+
+#[test]
+#[ignore = "needs SINGLE_STEP_TESTS"]
+fn nes6502() {
+    run_dir(Variant::Ricoh2A03, &tests_dir("nes6502"));
+}
+
+#[test]
+#[ignore = "needs SINGLE_STEP_TESTS"]
+fn nmos6502() {
+    run_dir(Variant::Nmos6502, &tests_dir("6502"));
+}
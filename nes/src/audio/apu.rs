@@ -5,8 +5,9 @@ use super::mixer::Mixer;
 use super::noise::Noise;
 use super::square::Square;
 use super::triangle::Triangle;
-use crate::mem::Mem;
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
 use log::warn;
+use mos6502::Mem;
 
 pub struct ApuState {
     pub square1: u8,
@@ -88,6 +89,22 @@ impl Apu {
         Mixer::sample(state)
     }
 
+    /// Whether the APU is holding the CPU's IRQ line. Both the frame counter
+    /// and the DMC can raise interrupts.
+    pub fn irq(&self) -> bool {
+        self.frame_counter.irq() || self.dmc.irq()
+    }
+
+    /// The address of the next sample byte, if the DMC is waiting for one
+    pub fn dmc_dma_addr(&self) -> Option<u16> {
+        self.dmc.dma_addr()
+    }
+
+    /// Hand the DMC the sample byte it asked for
+    pub fn dmc_dma_complete(&mut self, val: u8) {
+        self.dmc.dma_complete(val);
+    }
+
     fn update_flags(&mut self, val: u8) {
         self.square1.set_enabled(bit!(val, 0));
         self.square2.set_enabled(bit!(val, 1));
@@ -115,8 +132,10 @@ impl Mem for Apu {
         let tri = if self.triangle.is_running() { 0x04 } else { 0 };
         let noise = if self.noise.is_running() { 0x08 } else { 0 };
         let dmc = if self.dmc.is_running() { 0x10 } else { 0 };
+        let frame_irq = if self.frame_counter.irq() { 0x40 } else { 0 };
+        let dmc_irq = if self.dmc.irq() { 0x80 } else { 0 };
 
-        dmc | noise | tri | sq2 | sq1
+        dmc_irq | frame_irq | dmc | noise | tri | sq2 | sq1
     }
 
     fn loadb(&mut self, addr: u16) -> u8 {
@@ -124,9 +143,11 @@ impl Mem for Apu {
             return 0;
         }
 
+        // Reading the status clears the frame interrupt, after it's been read
+        let status = self.peekb(addr);
         self.frame_counter.reset_irq();
 
-        self.peekb(addr)
+        status
     }
 
     fn storeb(&mut self, addr: u16, val: u8) {
@@ -164,3 +185,25 @@ impl Mem for Apu {
         }
     }
 }
+
+impl Snapshot for Apu {
+    fn save(&self, w: &mut StateWriter) {
+        self.dmc.save(w);
+        w.bool(self.even_cycle);
+        self.frame_counter.save(w);
+        self.noise.save(w);
+        self.square1.save(w);
+        self.square2.save(w);
+        self.triangle.save(w);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.dmc.load(r)?;
+        self.even_cycle = r.bool()?;
+        self.frame_counter.load(r)?;
+        self.noise.load(r)?;
+        self.square1.load(r)?;
+        self.square2.load(r)?;
+        self.triangle.load(r)
+    }
+}
@@ -1,3 +1,5 @@
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
 pub struct Divider {
     current: u8,
     period: u8,
@@ -29,3 +31,16 @@ impl Divider {
         }
     }
 }
+
+impl Snapshot for Divider {
+    fn save(&self, w: &mut StateWriter) {
+        w.u8(self.current);
+        w.u8(self.period);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.current = r.u8()?;
+        self.period = r.u8()?;
+        Ok(())
+    }
+}
@@ -1,5 +1,6 @@
 use super::channel::Channel;
 use super::timer::Timer;
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
 
 const RATE_LOOKUP: [u16; 16] = [
     428, 380, 340, 320, 286, 254, 226, 214,
@@ -8,22 +9,42 @@ const RATE_LOOKUP: [u16; 16] = [
 
 pub struct Dmc {
     irq_enabled: bool,
+    irq_flag: bool,
     loop_enabled: bool,
     rate: u16,
     sample_addr: u16,
     sample_len: u16,
     timer: Timer,
+
+    // Memory reader
+    current_addr: u16,
+    bytes_remaining: u16,
+    sample_buffer: Option<u8>,
+
+    // Output unit
+    shift: u8,
+    bits_remaining: u8,
+    silence: bool,
+    output_level: u8,
 }
 
 impl Dmc {
     pub fn new() -> Self {
         Self {
             irq_enabled: false,
+            irq_flag: false,
             loop_enabled: false,
             rate: 0,
             sample_addr: 0,
             sample_len: 0,
             timer: Timer::new(),
+            current_addr: 0,
+            bytes_remaining: 0,
+            sample_buffer: None,
+            shift: 0,
+            bits_remaining: 8,
+            silence: true,
+            output_level: 0,
         }
     }
 
@@ -31,12 +52,22 @@ impl Dmc {
         self.irq_enabled = bit!(val, 7);
         self.loop_enabled = bit!(val, 6);
 
+        if !self.irq_enabled {
+            self.irq_flag = false;
+        }
+
         let rate_idx = mask!(val, 0x0F) as usize;
         self.rate = RATE_LOOKUP[rate_idx];
-    }
 
-    pub fn set_counter(&mut self, _val: u8) {
+        // The rates are in CPU cycles, but the timer is clocked every other
+        // cycle
+        self.timer.set_period(self.rate / 2 - 1);
+    }
 
+    /// Load the output level directly. Games use this to play raw PCM by
+    /// writing to $4011 over and over.
+    pub fn set_counter(&mut self, val: u8) {
+        self.output_level = mask!(val, 0x7F);
     }
 
     pub fn set_sample_address(&mut self, val: u8) {
@@ -51,25 +82,225 @@ impl Dmc {
     pub fn set_sample_length(&mut self, val: u8) {
         self.sample_len = val as u16 * 16 + 1
     }
+
+    /// The address of the next sample byte, if the sample buffer is empty and
+    /// there are bytes left to play. The CPU is halted to fetch it.
+    pub fn dma_addr(&self) -> Option<u16> {
+        if self.sample_buffer.is_none() && self.bytes_remaining > 0 {
+            Some(self.current_addr)
+        } else {
+            None
+        }
+    }
+
+    /// Fill the sample buffer with the byte fetched from [Dmc::dma_addr].
+    /// After the last byte of the sample the DMC either starts over or, if
+    /// enabled, raises an interrupt.
+    pub fn dma_complete(&mut self, val: u8) {
+        self.sample_buffer = Some(val);
+
+        // The address wraps around to $8000, not $0000
+        self.current_addr = self.current_addr.checked_add(1).unwrap_or(0x8000);
+        self.bytes_remaining -= 1;
+
+        if self.bytes_remaining == 0 {
+            if self.loop_enabled {
+                self.restart();
+            } else if self.irq_enabled {
+                self.irq_flag = true;
+            }
+        }
+    }
+
+    /// Whether the DMC is holding the CPU's IRQ line
+    pub fn irq(&self) -> bool {
+        self.irq_flag
+    }
+
+    fn restart(&mut self) {
+        self.current_addr = self.sample_addr;
+        self.bytes_remaining = self.sample_len;
+    }
+
+    /// Each time the timer elapses, the output level moves up or down by 2
+    /// depending on the next bit of the sample, staying within 0-127. Every 8
+    /// bits the output unit empties the sample buffer, if there is anything in
+    /// it, which lets the memory reader fetch the next byte.
+    fn output_clock(&mut self) {
+        if !self.silence {
+            if bit!(self.shift, 0) {
+                if self.output_level <= 125 {
+                    self.output_level += 2;
+                }
+            } else if self.output_level >= 2 {
+                self.output_level -= 2;
+            }
+        }
+
+        self.shift >>= 1;
+        self.bits_remaining -= 1;
+
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+
+            match self.sample_buffer.take() {
+                Some(val) => {
+                    self.shift = val;
+                    self.silence = false;
+                }
+                None => self.silence = true,
+            }
+        }
+    }
 }
 
 impl Channel for Dmc {
     fn clock(&mut self) {
         self.timer.tick();
+
+        if self.timer.has_elapsed() {
+            self.output_clock();
+        }
     }
 
-    fn set_enabled(&mut self, _enabled: bool) {
+    /// Writing to $4015 always acknowledges the DMC's interrupt
+    fn set_enabled(&mut self, enabled: bool) {
+        self.irq_flag = false;
 
+        if !enabled {
+            self.bytes_remaining = 0;
+        } else if self.bytes_remaining == 0 {
+            self.restart();
+        }
     }
 
     fn get(&self) -> u8 {
-        0
+        self.output_level
     }
 
     fn is_running(&self) -> bool {
-        false
+        self.bytes_remaining > 0
     }
 
     fn half_frame_clock(&mut self) { }
     fn quarter_frame_clock(&mut self) { }
 }
+
+impl Snapshot for Dmc {
+    fn save(&self, w: &mut StateWriter) {
+        w.bool(self.irq_enabled);
+        w.bool(self.irq_flag);
+        w.bool(self.loop_enabled);
+        w.u16(self.rate);
+        w.u16(self.sample_addr);
+        w.u16(self.sample_len);
+        self.timer.save(w);
+        w.u16(self.current_addr);
+        w.u16(self.bytes_remaining);
+        w.bool(self.sample_buffer.is_some());
+        w.u8(self.sample_buffer.unwrap_or(0));
+        w.u8(self.shift);
+        w.u8(self.bits_remaining);
+        w.bool(self.silence);
+        w.u8(self.output_level);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.irq_enabled = r.bool()?;
+        self.irq_flag = r.bool()?;
+        self.loop_enabled = r.bool()?;
+        self.rate = r.u16()?;
+        self.sample_addr = r.u16()?;
+        self.sample_len = r.u16()?;
+        self.timer.load(r)?;
+        self.current_addr = r.u16()?;
+        self.bytes_remaining = r.u16()?;
+        let has_sample = r.bool()?;
+        let sample = r.u8()?;
+        self.sample_buffer = has_sample.then_some(sample);
+        self.shift = r.u8()?;
+        self.bits_remaining = r.u8()?;
+        self.silence = r.bool()?;
+        self.output_level = r.u8()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn direct_load_sets_7_bit_output() {
+        let mut dmc = Dmc::new();
+        dmc.set_counter(0xFF);
+
+        assert_eq!(dmc.get(), 0x7F);
+    }
+
+    #[test]
+    fn output_steps_by_2_within_range() {
+        let mut dmc = Dmc::new();
+        dmc.silence = false;
+        dmc.set_counter(126);
+        dmc.shift = 0b0000_0001;
+
+        dmc.output_clock();
+        assert_eq!(dmc.get(), 126);
+
+        dmc.output_clock();
+        assert_eq!(dmc.get(), 124);
+
+        dmc.set_counter(1);
+        dmc.output_clock();
+        assert_eq!(dmc.get(), 1);
+    }
+
+    #[test]
+    fn silence_holds_output() {
+        let mut dmc = Dmc::new();
+        dmc.set_counter(64);
+        dmc.shift = 0xFF;
+        dmc.output_clock();
+
+        assert_eq!(dmc.get(), 64);
+    }
+
+    #[test]
+    fn loop_restarts_sample() {
+        let mut dmc = Dmc::new();
+        dmc.set_irq_loop_freq(0xC0);
+        dmc.set_sample_address(0x01);
+        dmc.set_sample_length(0);
+        dmc.set_enabled(true);
+        dmc.dma_complete(0);
+
+        assert_eq!(dmc.current_addr, 0xC040);
+        assert!(dmc.is_running());
+        assert!(!dmc.irq());
+    }
+
+    #[test]
+    fn irq_set_at_end_of_sample() {
+        let mut dmc = Dmc::new();
+        dmc.set_irq_loop_freq(0x80);
+        dmc.set_sample_length(0);
+        dmc.set_enabled(true);
+        dmc.dma_complete(0);
+
+        assert!(!dmc.is_running());
+        assert!(dmc.irq());
+    }
+
+    #[test]
+    fn irq_cleared_by_enable_write_and_disable() {
+        let mut dmc = Dmc::new();
+        dmc.irq_flag = true;
+        dmc.set_enabled(false);
+        assert!(!dmc.irq());
+
+        dmc.irq_flag = true;
+        dmc.set_irq_loop_freq(0x00);
+        assert!(!dmc.irq());
+    }
+}
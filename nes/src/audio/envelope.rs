@@ -1,3 +1,5 @@
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
 struct Divider {
     current: u8,
     period: u8,
@@ -91,3 +93,26 @@ impl Envelope {
         }
     }
 }
+
+impl Snapshot for Envelope {
+    fn save(&self, w: &mut StateWriter) {
+        w.bool(self.const_vol_flag);
+        w.u8(self.decay_counter);
+        w.u8(self.divider.current);
+        w.u8(self.divider.period);
+        w.bool(self.loop_flag);
+        w.bool(self.start_flag);
+        w.u8(self.volume_period);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.const_vol_flag = r.bool()?;
+        self.decay_counter = r.u8()?;
+        self.divider.current = r.u8()?;
+        self.divider.period = r.u8()?;
+        self.loop_flag = r.bool()?;
+        self.start_flag = r.bool()?;
+        self.volume_period = r.u8()?;
+        Ok(())
+    }
+}
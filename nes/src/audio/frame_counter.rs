@@ -1,3 +1,5 @@
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
 // Cycle counts in the APU are expressed in CPU cycle counts.
 // The CPU clocks at twice the speed of the APU, but using APU
 // increments results in half step actions
@@ -146,7 +148,7 @@ impl FrameCounter {
             self.irq_flag = false;
         }
 
-        if self.cycle % 2 == 0 {
+        if self.cycle.is_multiple_of(2) {
             self.reset_countdown = 3;
         } else {
             self.reset_countdown = 4;
@@ -156,6 +158,34 @@ impl FrameCounter {
     pub fn reset_irq(&mut self) {
         self.irq_flag = false;
     }
+
+    /// Whether the frame interrupt flag is set
+    pub fn irq(&self) -> bool {
+        self.irq_flag
+    }
+}
+
+impl Snapshot for FrameCounter {
+    fn save(&self, w: &mut StateWriter) {
+        w.u16(self.cycle);
+        w.bool(self.irq_flag);
+        w.bool(self.irq_inhibit);
+        w.bool(matches!(self.mode, SequencerMode::FiveStep));
+        w.u8(self.reset_countdown);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.cycle = r.u16()?;
+        self.irq_flag = r.bool()?;
+        self.irq_inhibit = r.bool()?;
+        self.mode = if r.bool()? {
+            SequencerMode::FiveStep
+        } else {
+            SequencerMode::FourStep
+        };
+        self.reset_countdown = r.u8()?;
+        Ok(())
+    }
 }
 
 #[cfg(test)]
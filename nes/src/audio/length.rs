@@ -1,3 +1,5 @@
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
 static LENGTH_LOOKUP: [u8; 32] = [
     0x0A, 0xFE, 0x14, 0x02, 0x28, 0x04, 0x50, 0x06,
     0xA0, 0x08, 0x3C, 0x0A, 0x0E, 0x0C, 0x1A, 0x0E,
@@ -48,3 +50,16 @@ impl LengthCounter {
         self.halt = halt;
     }
 }
+
+impl Snapshot for LengthCounter {
+    fn save(&self, w: &mut StateWriter) {
+        w.bool(self.halt);
+        w.u8(self.length);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.halt = r.bool()?;
+        self.length = r.u8()?;
+        Ok(())
+    }
+}
@@ -1,3 +1,5 @@
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
 pub struct LinearCounter {
     counter: u8,
     reload_flag: bool,
@@ -37,3 +39,18 @@ impl LinearCounter {
         self.reload_flag = reload;
     }
 }
+
+impl Snapshot for LinearCounter {
+    fn save(&self, w: &mut StateWriter) {
+        w.u8(self.counter);
+        w.bool(self.reload_flag);
+        w.u8(self.reload_val);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.counter = r.u8()?;
+        self.reload_flag = r.bool()?;
+        self.reload_val = r.u8()?;
+        Ok(())
+    }
+}
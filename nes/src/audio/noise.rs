@@ -2,6 +2,7 @@ use super::channel::Channel;
 use super::envelope::Envelope;
 use super::length::LengthCounter;
 use super::timer::Timer;
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
 
 const LENGTH_LOOKUP: [u16; 16] = [
     4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
@@ -114,3 +115,21 @@ impl NoiseShift {
         bit!(self.val, 0)
     }
 }
+
+impl Snapshot for Noise {
+    fn save(&self, w: &mut StateWriter) {
+        self.envelope.save(w);
+        self.length.save(w);
+        w.bool(self.mode_flag);
+        w.u16(self.shift.val);
+        self.timer.save(w);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.envelope.load(r)?;
+        self.length.load(r)?;
+        self.mode_flag = r.bool()?;
+        self.shift.val = r.u16()?;
+        self.timer.load(r)
+    }
+}
@@ -1,3 +1,5 @@
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
 static WAVEFORM: [u8; 32] = [
     15, 14, 13, 12, 11, 10,  9,  8,  7,  6,  5,  4,  3,  2,  1,  0,
      0,  1,  2,  3,  4,  5,  6,  7,  8,  9, 10, 11, 12, 13, 14, 15,
@@ -60,3 +62,37 @@ impl TriangleSequence {
         self.cycle_idx = (self.cycle_idx + 1) % 32;
     }
 }
+
+impl Snapshot for SquareSequence {
+    fn save(&self, w: &mut StateWriter) {
+        w.u8(self.duty_cycle as u8);
+        w.u8(self.duty_idx);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.duty_cycle = r.u8()? as usize;
+        self.duty_idx = r.u8()?;
+
+        if self.duty_cycle >= DUTY_CYCLES.len() {
+            return Err(StateError::Corrupt);
+        }
+
+        Ok(())
+    }
+}
+
+impl Snapshot for TriangleSequence {
+    fn save(&self, w: &mut StateWriter) {
+        w.u8(self.cycle_idx as u8);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.cycle_idx = r.u8()? as usize;
+
+        if self.cycle_idx >= WAVEFORM.len() {
+            return Err(StateError::Corrupt);
+        }
+
+        Ok(())
+    }
+}
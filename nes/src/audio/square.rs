@@ -4,6 +4,7 @@ use super::length::LengthCounter;
 use super::sequencer::SquareSequence;
 use super::sweep::Sweep;
 use super::timer::Timer;
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
 
 pub struct Square {
     envelope: Envelope,
@@ -111,3 +112,21 @@ impl Channel for Square {
         self.envelope.clock();
     }
 }
+
+impl Snapshot for Square {
+    fn save(&self, w: &mut StateWriter) {
+        self.envelope.save(w);
+        self.length.save(w);
+        self.sequencer.save(w);
+        self.sweep.save(w);
+        self.timer.save(w);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.envelope.load(r)?;
+        self.length.load(r)?;
+        self.sequencer.load(r)?;
+        self.sweep.load(r)?;
+        self.timer.load(r)
+    }
+}
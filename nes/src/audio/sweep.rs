@@ -1,4 +1,5 @@
 use super::divider::Divider;
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
 
 pub struct Sweep {
     divider: Divider,
@@ -55,3 +56,20 @@ impl Sweep {
         }
     }
 }
+
+impl Snapshot for Sweep {
+    fn save(&self, w: &mut StateWriter) {
+        self.divider.save(w);
+        w.bool(self.enabled);
+        w.bool(self.negate);
+        w.u8(self.shift_count);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.divider.load(r)?;
+        self.enabled = r.bool()?;
+        self.negate = r.bool()?;
+        self.shift_count = r.u8()?;
+        Ok(())
+    }
+}
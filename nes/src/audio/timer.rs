@@ -1,3 +1,5 @@
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
 pub struct Timer {
     has_elapsed: bool,
     period: u16,
@@ -52,3 +54,18 @@ impl Timer {
         self.period = lo + hi;
     }
 }
+
+impl Snapshot for Timer {
+    fn save(&self, w: &mut StateWriter) {
+        w.bool(self.has_elapsed);
+        w.u16(self.period);
+        w.u16(self.current);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.has_elapsed = r.bool()?;
+        self.period = r.u16()?;
+        self.current = r.u16()?;
+        Ok(())
+    }
+}
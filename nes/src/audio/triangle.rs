@@ -3,6 +3,7 @@ use super::length::LengthCounter;
 use super::linear::LinearCounter;
 use super::sequencer::TriangleSequence;
 use super::timer::Timer;
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
 
 pub struct Triangle {
     control_flag: bool,
@@ -79,3 +80,21 @@ impl Channel for Triangle {
         }
     }
 }
+
+impl Snapshot for Triangle {
+    fn save(&self, w: &mut StateWriter) {
+        w.bool(self.control_flag);
+        self.length.save(w);
+        self.linear.save(w);
+        self.sequencer.save(w);
+        self.timer.save(w);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.control_flag = r.bool()?;
+        self.length.load(r)?;
+        self.linear.load(r)?;
+        self.sequencer.load(r)?;
+        self.timer.load(r)
+    }
+}
@@ -0,0 +1,42 @@
+use nes::{run_test_rom, Rom, DEFAULT_TEST_ROM_TIMEOUT};
+use std::{env, fs::File, process};
+
+/// Runs test ROMs that report their results at $6000, such as blargg's, and
+/// prints how each one did. Exits with an error if any of them didn't pass.
+fn main() {
+    let paths = env::args().skip(1).collect::<Vec<_>>();
+
+    if paths.is_empty() {
+        eprintln!("Usage: test_rom <rom>...");
+        process::exit(2);
+    }
+
+    let mut failures = 0;
+
+    for path in &paths {
+        let rom = File::open(path)
+            .map_err(|err| err.into())
+            .and_then(|mut file| Rom::from_path(&mut file));
+
+        let rom = match rom {
+            Ok(rom) => rom,
+            Err(err) => {
+                println!("{}: unable to load: {}", path, err);
+                failures += 1;
+                continue;
+            }
+        };
+
+        let result = run_test_rom(rom, DEFAULT_TEST_ROM_TIMEOUT);
+        println!("{}: {}", path, result);
+
+        if !result.passed() {
+            failures += 1;
+        }
+    }
+
+    if failures > 0 {
+        println!("{} of {} ROMs failed", failures, paths.len());
+        process::exit(1);
+    }
+}
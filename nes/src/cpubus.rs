@@ -1,73 +1,243 @@
-use crate::{audio::Apu, input::Input, mapper::PrgMem, mem::Mem, ppu::Ppu, ram::Ram};
+use crate::{
+    audio::Apu,
+    input::Input,
+    mapper::Mapper,
+    ppu::Ppu,
+    ram::Ram,
+    state::{Snapshot, StateError, StateReader, StateWriter},
+};
+use mos6502::{Dma, Mem};
 
 pub struct CpuBus {
     pub ram: Ram,
     pub apu: Apu,
     pub ppu: Ppu,
     pub input: Input,
-    pub mapper: PrgMem,
+    pub mapper: Box<dyn Mapper + Send>,
+
+    /// Set when the PPU signals an NMI, until the CPU services it
+    pub nmi: bool,
+
+    /// Set when the PPU finished a frame during the current CPU step
+    pub new_frame: bool,
+
+    /// Audio samples produced during the current CPU step, one per cycle
+    pub samples: Vec<f32>,
+
+    /// The last value driven on the CPU's data bus. Reads from addresses that
+    /// nothing responds to see this value instead.
+    pub open_bus: u8,
 }
 
 impl CpuBus {
-    pub fn new(ppu: Ppu, input: Input, apu: Apu, mapper: PrgMem, ram: Ram) -> Self {
+    pub fn new(ppu: Ppu, input: Input, apu: Apu, mapper: Box<dyn Mapper + Send>, ram: Ram) -> Self {
         Self {
             apu,
             ram,
             ppu,
             input,
             mapper,
+            nmi: false,
+            new_frame: false,
+            samples: Vec::new(),
+            open_bus: 0,
         }
     }
 }
 
 impl Mem for CpuBus {
+    /// Run everything else on the bus for one CPU cycle. The PPU runs three
+    /// dots for every CPU cycle.
+    fn tick(&mut self) {
+        self.apu.step();
+        self.input.step();
+
+        for _ in 0..3 {
+            let result = self.ppu.step(self.mapper.as_mut());
+            self.mapper.tick();
+
+            if result.vblank_nmi {
+                self.nmi = true;
+            }
+
+            if result.new_frame {
+                self.new_frame = true;
+            }
+        }
+
+        self.samples.push(self.apu.sample());
+    }
+
+    fn poll_nmi(&mut self) -> bool {
+        std::mem::take(&mut self.nmi)
+    }
+
+    /// The cartridge and the APU can both hold the IRQ line
+    fn irq_line(&self) -> bool {
+        self.mapper.irq() || self.apu.irq()
+    }
+
+    fn dma(&mut self) -> Option<&mut dyn Dma> {
+        Some(self)
+    }
+
     fn peekb(&self, addr: u16) -> u8 {
         if addr < 0x2000 {
             self.ram.peekb(addr)
         } else if addr < 0x4000 {
-            self.ppu.peekb(addr)
-        } else if addr < 0x4016 {
-            self.apu.peekb(addr)
-        } else if addr < 0x4018 {
-            self.input.peekb(addr)
-        } else if addr < 0x6000 {
-            0
+            self.ppu.peek_register(addr)
+        } else if addr == 0x4015 {
+            self.apu.peekb(addr) | self.open_bus & 0x20
+        } else if addr == 0x4016 || addr == 0x4017 {
+            self.input.peekb(addr) | self.open_bus & 0xE0
+        } else if addr < 0x4020 {
+            self.open_bus
         } else {
-            self.mapper.as_ref().peekb(addr)
+            self.mapper.peek_prg(addr).unwrap_or(self.open_bus)
         }
     }
 
     fn loadb(&mut self, addr: u16) -> u8 {
         if addr < 0x2000 {
-            self.ram.loadb(addr)
+            self.open_bus = self.ram.loadb(addr);
         } else if addr < 0x4000 {
-            self.ppu.loadb(addr)
-        } else if addr < 0x4016 {
-            self.apu.loadb(addr)
-        } else if addr < 0x4018 {
-            self.input.loadb(addr)
-        } else if addr < 0x6000 {
-            0
-        } else {
-            self.mapper.as_mut().loadb(addr)
+            self.open_bus = self.ppu.load_register(self.mapper.as_mut(), addr);
+        } else if addr == 0x4015 {
+            // $4015 is internal to the CPU, so the bit it doesn't drive reads
+            // back from the bus without the read changing what's on it
+            return self.apu.loadb(addr) | self.open_bus & 0x20;
+        } else if addr == 0x4016 || addr == 0x4017 {
+            // Controllers only drive the low bits of the bus
+            self.open_bus = self.input.loadb(addr) | self.open_bus & 0xE0;
+        } else if addr >= 0x4020 {
+            if let Some(val) = self.mapper.load_prg(addr) {
+                self.open_bus = val;
+            }
         }
+
+        self.open_bus
     }
 
     fn storeb(&mut self, addr: u16, val: u8) {
+        self.open_bus = val;
+
         if addr < 0x2000 {
             self.ram.storeb(addr, val)
         } else if addr < 0x4000 {
-            self.ppu.storeb(addr, val)
+            self.ppu.store_register(self.mapper.as_mut(), addr, val)
         } else if addr < 0x4016 {
             self.apu.storeb(addr, val);
         } else if addr == 0x4016 {
             self.input.storeb(addr, val)
         } else if addr == 0x4017 {
             self.apu.storeb(addr, val);
-        } else if addr < 0x6000 {
+        } else if addr < 0x4020 {
             // nop
         } else {
-            self.mapper.as_mut().storeb(addr, val);
+            self.mapper.store_prg(addr, val);
         }
     }
 }
+
+/// The 2A03's DMA unit copies sprites to OAM when $4014 is written, and
+/// fetches sample bytes for the DMC
+impl Dma for CpuBus {
+    fn starts_page_copy(&self, addr: u16) -> bool {
+        addr == 0x4014
+    }
+
+    fn page_copy_port(&self) -> u16 {
+        0x2004
+    }
+
+    fn fetch_addr(&self) -> Option<u16> {
+        self.apu.dmc_dma_addr()
+    }
+
+    fn fetch_complete(&mut self, val: u8) {
+        self.apu.dmc_dma_complete(val);
+    }
+
+    /// While halted on $4016 or $4017 the repeated reads don't clock the
+    /// controllers again, but the halt cycle does, which is why a DMC fetch
+    /// during a controller read can lose a button
+    fn repeats_halted_reads(&self, addr: u16) -> bool {
+        addr != 0x4016 && addr != 0x4017
+    }
+}
+
+impl Snapshot for CpuBus {
+    fn save(&self, w: &mut StateWriter) {
+        self.ram.save(w);
+        self.apu.save(w);
+        self.ppu.save(w);
+        self.input.save(w);
+        self.mapper.save(w);
+        w.bool(self.nmi);
+        w.u8(self.open_bus);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.ram.load(r)?;
+        self.apu.load(r)?;
+        self.ppu.load(r)?;
+        self.input.load(r)?;
+        self.mapper.load(r)?;
+        self.nmi = r.bool()?;
+        self.open_bus = r.u8()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::headless::tests::spin_rom;
+    use crate::Nes;
+    use mos6502::Mem;
+
+    fn bus() -> crate::cpubus::CpuBus {
+        Nes::with_rom(spin_rom()).cpu.mem
+    }
+
+    #[test]
+    fn unmapped_reads_return_last_bus_value() {
+        let mut bus = bus();
+        bus.storeb(0x0000, 0xA5);
+
+        assert_eq!(bus.loadb(0x5000), 0xA5);
+        assert_eq!(bus.loadb(0x4000), 0xA5);
+
+        bus.loadb(0x8000);
+        assert_eq!(bus.loadb(0x5000), 0x4C);
+    }
+
+    #[test]
+    fn controller_reads_keep_upper_bus_bits() {
+        let mut bus = bus();
+        bus.loadb(0x8001);
+
+        assert_eq!(bus.loadb(0x4016), 0x00);
+
+        bus.storeb(0x0000, 0xFF);
+        assert_eq!(bus.loadb(0x4017), 0xE0);
+    }
+
+    #[test]
+    fn apu_status_does_not_drive_bus() {
+        let mut bus = bus();
+        bus.storeb(0x0000, 0xFF);
+
+        assert_eq!(bus.loadb(0x4015) & 0x20, 0x20);
+        assert_eq!(bus.loadb(0x5000), 0xFF);
+    }
+
+    #[test]
+    fn write_only_ppu_registers_read_io_latch() {
+        let mut bus = bus();
+        bus.storeb(0x2000, 0x00);
+        bus.storeb(0x2003, 0x5A);
+
+        assert_eq!(bus.loadb(0x2000), 0x5A);
+        assert_eq!(bus.loadb(0x2002) & 0x1F, 0x1A);
+    }
+}
@@ -1,59 +1,111 @@
 use crate::{
     frame_buffer::{Frame, FrameBuffer},
+    headless::{AudioSink, FrameSink, Headless, CPU_FREQUENCY},
     log::log,
-    mem::Mem,
+    movie::is_fm2,
     signals::{
-        ControlMessage, ControlRequest, ControlResponse, PaletteState, PatternTableContents,
-        PpuState, RegisterState,
+        ControlMessage, ControlRequest, ControlResponse, EmulationState, PaletteState,
+        PatternTableContents, PpuState, RegisterState, VideoMessage,
     },
-    InputState, Nes, Rgb, Rom,
+    Movie, MovieFrame, MoviePlayer, Nes, Rewind, Rgb, Rom,
 };
 use cpal::{
     traits::{DeviceTrait, HostTrait},
     Device, SampleFormat, SampleRate, Stream, StreamConfig, SupportedStreamConfigRange,
 };
 use dasp::{interpolate::linear::Linear, Signal};
-use log::info;
+use log::{error, info};
 use std::{
+    collections::VecDeque,
     mem,
+    path::PathBuf,
     sync::mpsc::{Receiver, Sender},
 };
 
 const AUDIO_SAMPLE_RATE: u32 = 44_100;
-const CPU_FREQUENCY: f64 = 1_789_773.0;
-
-#[derive(Debug)]
-pub enum VideoMessage {
-    ControlResponse(ControlResponse),
-    CpuStep(String),
-    FrameAvailable(Frame),
-    StateChanged(EmulationState),
+
+/// Sends finished frames to the debugger, replacing them with recycled ones
+struct ChannelSink {
+    on_frame: Sender<VideoMessage>,
+    frame_buffer: FrameBuffer,
 }
 
-#[derive(Debug, Eq, PartialEq, Copy, Clone)]
-pub enum EmulationState {
-    /// The emulation has been ended by the user
-    Kill,
+impl FrameSink for ChannelSink {
+    fn frame(&mut self, frame: &mut Frame) {
+        let frame = mem::replace(frame, self.frame_buffer.get());
+        let message = VideoMessage::FrameAvailable(frame);
+
+        // Discard send errors - if the other end of the channel hung up
+        // we are most likely shutting down
+        let _ = self.on_frame.send(message);
+    }
+}
 
-    /// The emulation should step forward by one CPU tick, then transition into the Pause state
-    Step,
+/// Holds on to the samples from the last instruction until the audio stream
+/// asks for them, one at a time
+struct SampleQueue(VecDeque<f32>);
 
-    /// The emulation should remain paused forever
-    Pause,
+impl AudioSink for SampleQueue {
+    fn sample(&mut self, sample: f32) {
+        self.0.push_back(sample);
+    }
+}
 
-    /// The emulation should run uninterrupted until a user changes the state
-    Run(Option<usize>),
+/// Everything that records or replays emulation over time
+#[derive(Default)]
+struct Timeline {
+    rewind: Rewind,
+    movie: MoviePlayer,
+    /// Where the movie being recorded is written once it stops
+    movie_path: Option<PathBuf>,
+}
+
+impl Timeline {
+    /// Call each time the PPU finishes a frame
+    fn end_frame(&mut self, nes: &mut Nes) {
+        self.rewind.frame(nes);
+
+        if let Some(movie) = self.movie.end_frame(nes) {
+            info!("Movie finished after {} frames", movie.frames.len());
+        }
+    }
+
+    /// Stop the running movie, writing it out if it was being recorded
+    fn stop_movie(&mut self, nes: &mut Nes) {
+        let recording = self.movie.is_recording();
+        let movie = match self.movie.stop(nes) {
+            Some(movie) => movie,
+            None => return,
+        };
+
+        if let (true, Some(path)) = (recording, self.movie_path.take()) {
+            let result = if is_fm2(&path) {
+                let rom_name = path.file_stem().unwrap_or_default().to_string_lossy();
+                movie.save_fm2_file(&path, &rom_name)
+            } else {
+                movie.save_file(&path)
+            };
+
+            match result {
+                Ok(()) => info!("Wrote {} frame movie to {:?}", movie.frames.len(), path),
+                Err(err) => error!("Unable to write movie {:?}: {}", path, err),
+            }
+        }
+    }
 }
 
 fn handle_control_message(
     message: ControlMessage,
-    nes: &mut Nes,
+    emulator: &mut Headless,
+    video: &mut ChannelSink,
+    timeline: &mut Timeline,
     state: &mut EmulationState,
-    on_frame: &Sender<VideoMessage>,
-    frame_buffer: &mut FrameBuffer,
     logging_enabled: &mut bool,
     step_limit: &mut Option<usize>,
 ) {
+    let nes = &mut emulator.nes;
+    let on_frame = &video.on_frame;
+
     match message {
         ControlMessage::SetState(s) => {
             if s != *state {
@@ -71,12 +123,60 @@ fn handle_control_message(
             *logging_enabled = enabled;
         }
         ControlMessage::ControllerInput { gamepad1, gamepad2 } => {
-            nes.cpu.mem.input.set(&InputState {
-                gamepad1: Some(gamepad1),
-                gamepad2: Some(gamepad2),
-            });
+            let input = MovieFrame { gamepad1, gamepad2 };
+            timeline.movie.set_input(nes, input);
+        }
+        ControlMessage::RecycleFrame(frame) => video.frame_buffer.put(frame),
+        ControlMessage::LoadSaveData(data) => nes.load_save_data(&data),
+        ControlMessage::SaveState(path) => match nes.save_state_file(&path) {
+            Ok(()) => info!("Saved state to {:?}", path),
+            Err(err) => error!("Unable to save state to {:?}: {}", path, err),
+        },
+        ControlMessage::LoadState(path) => match nes.load_state_file(&path) {
+            Ok(()) => info!("Loaded state from {:?}", path),
+            Err(err) => error!("Unable to load state from {:?}: {}", path, err),
+        },
+        ControlMessage::StepBack => {
+            // Only while paused, since the history is still being written
+            // to while running. Movies would desync if time went backwards.
+            let movie_running = timeline.movie.frame().is_some();
+
+            if *state == EmulationState::Pause
+                && !movie_running
+                && !timeline.rewind.step_back(emulator, video)
+            {
+                info!("No more rewind history");
+            }
         }
-        ControlMessage::RecycleFrame(frame) => frame_buffer.put(frame),
+        ControlMessage::RecordMovie {
+            path,
+            from_power_on,
+        } => {
+            timeline.stop_movie(nes);
+            timeline.movie.record(nes, from_power_on);
+            timeline.rewind.clear();
+            info!("Recording movie to {:?}", path);
+            timeline.movie_path = Some(path);
+        }
+        ControlMessage::PlayMovie(path) => {
+            timeline.stop_movie(nes);
+
+            let movie = if is_fm2(&path) {
+                Movie::load_fm2_file(&path, nes.rom_hash(), nes.rom_md5())
+            } else {
+                Movie::load_file(&path)
+            };
+
+            let result = movie.and_then(|movie| timeline.movie.play(nes, movie));
+            match result {
+                Ok(()) => {
+                    timeline.rewind.clear();
+                    info!("Playing movie {:?}", path);
+                }
+                Err(err) => error!("Unable to play movie {:?}: {}", path, err),
+            }
+        }
+        ControlMessage::StopMovie => timeline.stop_movie(nes),
         ControlMessage::SetProgramCounter(pc) => nes.cpu.pc = pc,
         ControlMessage::SetCpuCycles(cy) => {
             nes.cpu.cy = cy;
@@ -86,12 +186,20 @@ fn handle_control_message(
         }
         ControlMessage::ControlRequest(req) => match req {
             ControlRequest::RomContents => {
-                let rom = nes.cpu.mem.mapper.as_ref();
-                let buf = (0x4020..=0xFFFF).map(|addr| rom.peekb(addr)).collect();
+                let rom = &nes.cpu.mem.mapper;
+                let buf = (0x4020..=0xFFFF)
+                    .map(|addr| rom.peek_prg(addr).unwrap_or(0))
+                    .collect();
 
                 let res = ControlResponse::RomContents(buf);
                 let _ = on_frame.send(VideoMessage::ControlResponse(res));
             }
+            ControlRequest::SaveData => {
+                let data = timeline.movie.save_data(nes).map(|data| data.to_vec());
+
+                let res = ControlResponse::SaveData(data);
+                let _ = on_frame.send(VideoMessage::ControlResponse(res));
+            }
             ControlRequest::PaletteState => {
                 let mut background = Vec::with_capacity(16);
                 let mut sprites = Vec::with_capacity(16);
@@ -99,14 +207,14 @@ fn handle_control_message(
 
                 for x in 0..16 {
                     let addr = 0x3F00 + x;
-                    let val = vram.peekb(addr);
+                    let val = vram.peek_palette(addr);
                     let rgb = *Rgb::from_byte(val);
                     background.push(rgb);
                 }
 
                 for x in 0..16 {
                     let addr = 0x3F10 + x;
-                    let val = vram.peekb(addr);
+                    let val = vram.peek_palette(addr);
                     let rgb = *Rgb::from_byte(val);
                     sprites.push(rgb);
                 }
@@ -134,7 +242,7 @@ fn handle_control_message(
                 let _ = on_frame.send(VideoMessage::ControlResponse(res));
             }
             ControlRequest::PatternTableContents => {
-                let vram = &nes.cpu.mem.ppu.vram;
+                let mapper = &nes.cpu.mem.mapper;
 
                 let mut table1 = vec![0; 128 * 128 * 3];
                 let mut table2 = vec![0; 128 * 128 * 3];
@@ -142,8 +250,8 @@ fn handle_control_message(
                 for i in 0..256 {
                     for row in 0..8 {
                         let addr = i * 16 + row;
-                        let pattern_lo = vram.peekb(addr);
-                        let pattern_hi = vram.peekb(addr + 8);
+                        let pattern_lo = mapper.peek_chr(addr);
+                        let pattern_hi = mapper.peek_chr(addr + 8);
 
                         for col in 0..8 {
                             let lo = bitn!(pattern_lo, 7 - col);
@@ -165,8 +273,8 @@ fn handle_control_message(
                 for i in 0..256 {
                     for row in 0..8 {
                         let addr = 1000 + i * 16 + row;
-                        let pattern_lo = vram.peekb(addr);
-                        let pattern_hi = vram.peekb(addr + 8);
+                        let pattern_lo = mapper.peek_chr(addr);
+                        let pattern_hi = mapper.peek_chr(addr + 8);
 
                         for col in 0..8 {
                             let lo = bitn!(pattern_lo, 7 - col);
@@ -223,43 +331,63 @@ pub fn run(
     on_frame: Sender<VideoMessage>,
     on_control: Receiver<ControlMessage>,
 ) -> Stream {
-    let mut nes = Nes::with_rom(rom);
+    let mut emulator = Headless::new(rom);
     let mut state = EmulationState::Pause;
-    let mut frame_buffer = FrameBuffer::new();
     let mut logging_enabled = false;
     let mut step_limit = None;
+    let mut video = ChannelSink {
+        on_frame,
+        frame_buffer: FrameBuffer::new(),
+    };
+    let mut audio = SampleQueue(VecDeque::new());
+    let mut timeline = Timeline::default();
 
     let signal = dasp::signal::gen_mut(move || {
         for message in on_control.try_iter() {
             handle_control_message(
                 message,
-                &mut nes,
+                &mut emulator,
+                &mut video,
+                &mut timeline,
                 &mut state,
-                &on_frame,
-                &mut frame_buffer,
                 &mut logging_enabled,
                 &mut step_limit,
             );
         }
 
         match state {
-            EmulationState::Pause | EmulationState::Kill => 0.0f32,
+            // Let the last instruction's samples finish playing
+            EmulationState::Pause | EmulationState::Kill => audio.0.pop_front().unwrap_or(0.0),
             EmulationState::Run(_) | EmulationState::Step => {
-                let step_result = nes.step();
-                let sample = nes.cpu.mem.apu.sample();
+                // Each instruction produces a sample per cycle, so only run
+                // the next one once the stream has used up the last
+                if audio.0.is_empty() {
+                    let was_jammed = emulator.nes.cpu.is_jammed();
 
-                if logging_enabled && !nes.cpu.is_busy() {
-                    let log_str = log(&nes);
-                    let _ = on_frame.send(VideoMessage::CpuStep(log_str));
-                }
+                    if emulator.step(&mut video, &mut audio).new_frame {
+                        timeline.end_frame(&mut emulator.nes);
+                    }
 
-                if state == EmulationState::Step && !nes.cpu.is_busy() {
-                    state = EmulationState::Pause;
-                    let _ = on_frame.send(VideoMessage::StateChanged(state));
-                }
+                    let nes = &emulator.nes;
+                    let on_frame = &video.on_frame;
+
+                    if !was_jammed && nes.cpu.is_jammed() {
+                        error!("CPU jammed at ${:04X}", nes.cpu.pc.wrapping_sub(1));
+                        state = EmulationState::Pause;
+                        let _ = on_frame.send(VideoMessage::StateChanged(state));
+                    }
+
+                    if logging_enabled {
+                        let log_str = log(nes);
+                        let _ = on_frame.send(VideoMessage::CpuStep(log_str));
+                    }
 
-                if let EmulationState::Run(_) = state {
-                    if !nes.cpu.is_busy() {
+                    if state == EmulationState::Step {
+                        state = EmulationState::Pause;
+                        let _ = on_frame.send(VideoMessage::StateChanged(state));
+                    }
+
+                    if let EmulationState::Run(_) = state {
                         if let Some(n) = step_limit.as_mut() {
                             if *n <= 1 {
                                 state = EmulationState::Pause;
@@ -271,16 +399,7 @@ pub fn run(
                     }
                 }
 
-                if step_result.new_frame {
-                    let frame = mem::replace(&mut nes.cpu.mem.ppu.screen, frame_buffer.get());
-                    let message = VideoMessage::FrameAvailable(frame);
-
-                    // Discard send errors - if the other end of the channel hung up
-                    // we are most likely shutting down
-                    let _ = on_frame.send(message);
-                }
-
-                sample
+                audio.0.pop_front().unwrap_or(0.0)
             }
         }
     });
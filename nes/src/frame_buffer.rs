@@ -26,6 +26,12 @@ impl AsRef<[u8; SCREEN_BYTES_RGB]> for Frame {
     }
 }
 
+impl AsMut<[u8; SCREEN_BYTES_RGB]> for Frame {
+    fn as_mut(&mut self) -> &mut [u8; SCREEN_BYTES_RGB] {
+        self.frame.as_mut()
+    }
+}
+
 impl Index<usize> for Frame {
     type Output = u8;
 
@@ -40,10 +46,12 @@ impl IndexMut<usize> for Frame {
     }
 }
 
+#[cfg(feature = "audio")]
 pub struct FrameBuffer {
     frames: Vec<Frame>,
 }
 
+#[cfg(feature = "audio")]
 impl FrameBuffer {
     pub fn new() -> Self {
         let frames = vec![Frame::new(), Frame::new(), Frame::new()];
@@ -55,9 +63,6 @@ impl FrameBuffer {
     }
 
     pub fn get(&mut self) -> Frame {
-        match self.frames.pop() {
-            Some(frame) => frame,
-            None => Frame::new(),
-        }
+        self.frames.pop().unwrap_or_default()
     }
 }
@@ -0,0 +1,187 @@
+use crate::{frame_buffer::Frame, nes::StepResult, Nes, Rom};
+
+/// The rate at which the APU produces samples: one per CPU cycle
+pub const CPU_FREQUENCY: f64 = 1_789_773.0;
+
+/// Receives each frame as the PPU finishes drawing it
+pub trait FrameSink {
+    /// The frame is the PPU's own buffer. Sinks that want to keep it may swap
+    /// in a different frame to be drawn over next.
+    fn frame(&mut self, frame: &mut Frame);
+}
+
+/// Receives the APU's output, one sample per CPU cycle (see [CPU_FREQUENCY])
+pub trait AudioSink {
+    fn sample(&mut self, sample: f32);
+}
+
+/// Discards all output
+impl FrameSink for () {
+    fn frame(&mut self, _frame: &mut Frame) {}
+}
+
+/// Discards all output
+impl AudioSink for () {
+    fn sample(&mut self, _sample: f32) {}
+}
+
+/// Collects a copy of every frame
+impl FrameSink for Vec<Frame> {
+    fn frame(&mut self, frame: &mut Frame) {
+        self.push(frame.clone());
+    }
+}
+
+/// Collects every sample
+impl AudioSink for Vec<f32> {
+    fn sample(&mut self, sample: f32) {
+        self.push(sample);
+    }
+}
+
+/// Drives the emulator without any connection to real audio or video
+/// hardware, as fast as the host allows. Output is handed to caller-provided
+/// sinks.
+pub struct Headless {
+    pub nes: Nes,
+}
+
+impl Headless {
+    pub fn new(rom: Rom) -> Self {
+        let nes = Nes::with_rom(rom);
+        Self { nes }
+    }
+
+    /// Progress emulation by one CPU instruction
+    pub fn step(&mut self, video: &mut dyn FrameSink, audio: &mut dyn AudioSink) -> StepResult {
+        let result = self.nes.step();
+
+        for &sample in self.nes.samples() {
+            audio.sample(sample);
+        }
+
+        if result.new_frame {
+            video.frame(&mut self.nes.cpu.mem.ppu.screen);
+        }
+
+        result
+    }
+
+    /// Run until the PPU has finished `frames` more frames
+    pub fn run_frames(
+        &mut self,
+        frames: usize,
+        video: &mut dyn FrameSink,
+        audio: &mut dyn AudioSink,
+    ) {
+        let mut remaining = frames;
+
+        while remaining > 0 {
+            if self.step(video, audio).new_frame {
+                remaining -= 1;
+            }
+        }
+    }
+
+    /// Run until `done` returns true. The condition is checked after every CPU
+    /// instruction, so it always sees a consistent CPU state.
+    pub fn run_until<F>(
+        &mut self,
+        mut done: F,
+        video: &mut dyn FrameSink,
+        audio: &mut dyn AudioSink,
+    ) where
+        F: FnMut(&Nes) -> bool,
+    {
+        loop {
+            self.step(video, audio);
+
+            if done(&self.nes) {
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::StateError;
+
+    /// An NROM image that spins in a `JMP $8000` loop forever
+    pub(crate) fn spin_image() -> Vec<u8> {
+        let mut image = b"NES\x1a\x01\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00".to_vec();
+        let mut prg = vec![0; 0x4000];
+        prg[..3].copy_from_slice(&[0x4C, 0x00, 0x80]);
+        prg[0x3FFC..0x3FFE].copy_from_slice(&[0x00, 0x80]);
+        image.extend(prg);
+        image.extend(vec![0; 0x2000]);
+        image
+    }
+
+    pub(crate) fn spin_rom() -> Rom {
+        Rom::from_path(&mut spin_image().as_slice()).unwrap()
+    }
+
+    #[test]
+    fn run_frames_produces_frames_and_samples() {
+        let mut emulator = Headless::new(spin_rom());
+        let mut frames: Vec<Frame> = Vec::new();
+        let mut samples: Vec<f32> = Vec::new();
+
+        emulator.run_frames(2, &mut frames, &mut samples);
+
+        assert_eq!(frames.len(), 2);
+        assert!(samples.len() > 2 * 29_000);
+    }
+
+    #[test]
+    fn run_until_stops_between_instructions() {
+        let mut emulator = Headless::new(spin_rom());
+        let mut instructions = 0;
+
+        emulator.run_until(
+            |_| {
+                instructions += 1;
+                instructions == 10
+            },
+            &mut (),
+            &mut (),
+        );
+
+        assert_eq!(emulator.nes.cpu.pc, 0x8000);
+        assert_eq!(instructions, 10);
+    }
+
+    #[test]
+    fn save_state_round_trip() {
+        let mut emulator = Headless::new(spin_rom());
+        emulator.run_frames(1, &mut (), &mut ());
+        let state = emulator.nes.save_state();
+
+        emulator.run_frames(1, &mut (), &mut ());
+        let expected = emulator.nes.save_state();
+
+        emulator.nes.load_state(&state).unwrap();
+        emulator.run_frames(1, &mut (), &mut ());
+
+        assert_eq!(emulator.nes.save_state(), expected);
+    }
+
+    #[test]
+    fn load_state_failure_leaves_machine_untouched() {
+        let mut emulator = Headless::new(spin_rom());
+        emulator.run_frames(1, &mut (), &mut ());
+        let before = emulator.nes.save_state();
+        let truncated = &before[..before.len() / 2];
+
+        emulator.run_frames(1, &mut (), &mut ());
+        let current = emulator.nes.save_state();
+
+        assert!(matches!(
+            emulator.nes.load_state(truncated),
+            Err(StateError::Corrupt)
+        ));
+        assert_eq!(emulator.nes.save_state(), current);
+    }
+}
@@ -1,6 +1,7 @@
-use crate::mem::Mem;
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
 use bitflags::bitflags;
 use log::{error, warn};
+use mos6502::Mem;
 
 const STROBE_STATE_A: u8 = 0;
 const STROBE_STATE_B: u8 = 1;
@@ -18,7 +19,7 @@ pub struct InputState {
 }
 
 bitflags! {
-    #[derive(Default, Debug, Copy, Clone)]
+    #[derive(Default, Debug, Copy, Clone, Eq, PartialEq)]
     pub struct Buttons: u8 {
         const LEFT = 1 << 0;
         const RIGHT = 1 << 1;
@@ -120,3 +121,30 @@ impl Mem for Input {
         }
     }
 }
+
+impl Snapshot for Strobe {
+    fn save(&self, w: &mut StateWriter) {
+        w.u8(self.step);
+        w.u8(self.buttons.bits());
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.step = r.u8()?;
+        self.buttons = Buttons::from_bits_retain(r.u8()?);
+        Ok(())
+    }
+}
+
+impl Snapshot for Input {
+    fn save(&self, w: &mut StateWriter) {
+        w.bool(self.strobe_flag);
+        self.port1.save(w);
+        self.port2.save(w);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.strobe_flag = r.bool()?;
+        self.port1.load(r)?;
+        self.port2.load(r)
+    }
+}
@@ -4,27 +4,43 @@ mod byte;
 mod audio;
 mod cpu;
 mod cpubus;
+#[cfg(feature = "audio")]
 mod emulation;
 mod frame_buffer;
+mod headless;
 mod input;
 mod log;
 mod mapper;
-mod mem;
+mod movie;
 mod nes;
 mod ppu;
 mod ram;
+mod rewind;
 mod rom;
+mod screenshot;
 mod signals;
+mod state;
+mod test_rom;
 
-pub use crate::nes::Nes;
-pub use cpu::{Cpu, Flags};
-pub use emulation::{run, EmulationState, VideoMessage};
+pub use crate::log::log as trace;
+pub use crate::nes::{Nes, StepResult};
+#[cfg(feature = "audio")]
+pub use emulation::run;
 pub use frame_buffer::Frame;
+pub use headless::{AudioSink, FrameSink, Headless, CPU_FREQUENCY};
 pub use input::{Buttons, InputState};
-pub use mem::Mem;
+pub use mos6502::{Cpu, Flags, Mem, Variant};
+pub use movie::{Movie, MovieError, MovieFrame, MoviePlayer, MovieStart, MOVIE_VERSION};
 pub use ppu::{Ppu, PpuControl, PpuMask, PpuStatus, Rgb};
-pub use rom::{INesHeader, Rom, RomLoadError};
+pub use rewind::{Rewind, DEFAULT_REWIND_CAPACITY, DEFAULT_REWIND_INTERVAL};
+pub use rom::{ConsoleType, INesHeader, NametableMirror, Rom, RomLoadError, TimingMode};
+pub use screenshot::{
+    capture_frames, hash_frame, parse_manifest, write_manifest, ScreenshotError,
+    ScreenshotMismatch, ScreenshotTest,
+};
 pub use signals::{
-    ControlMessage, ControlRequest, ControlResponse, PaletteState, PatternTableContents, PpuState,
-    RegisterState,
+    ControlMessage, ControlRequest, ControlResponse, EmulationState, PaletteState,
+    PatternTableContents, PpuState, RegisterState, VideoMessage,
 };
+pub use state::{StateError, STATE_VERSION};
+pub use test_rom::{run_test_rom, TestRomResult, TestRomStatus, DEFAULT_TEST_ROM_TIMEOUT};
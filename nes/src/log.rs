@@ -1,6 +1,8 @@
 use crate::{Cpu, Mem, Nes};
 use disasm::{Address, Op};
 
+/// Format the state of the CPU as one line of a Nintendulator trace, the
+/// format used by nestest's golden log
 pub fn log(nes: &Nes) -> String {
     let cpu = &nes.cpu;
     let ppu = &cpu.mem.ppu;
@@ -160,3 +162,93 @@ fn fmt_op_extra<M: Mem + Send>(op: &Op, cpu: &Cpu<M>) -> String {
         _ => "".to_owned(),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Rom;
+    use std::{fs, path::Path};
+
+    /// How many matching lines to show before the first divergence
+    const CONTEXT_LINES: usize = 5;
+
+    /// The first lines of nestest.log, for checking the trace format and the
+    /// power on state without the full ROM
+    const GOLDEN_START: &str = "\
+C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7
+C5F5  A2 00     LDX #$00                        A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 30 CYC:10
+C5F7  86 00     STX $00 = 00                    A:00 X:00 Y:00 P:26 SP:FD PPU:  0, 36 CYC:12
+C5F9  86 10     STX $10 = 00                    A:00 X:00 Y:00 P:26 SP:FD PPU:  0, 45 CYC:15
+C5FB  86 11     STX $11 = 00                    A:00 X:00 Y:00 P:26 SP:FD PPU:  0, 54 CYC:18
+C5FD  20 2D C7  JSR $C72D                       A:00 X:00 Y:00 P:26 SP:FD PPU:  0, 63 CYC:21
+C72D  EA        NOP                             A:00 X:00 Y:00 P:26 SP:FB PPU:  0, 81 CYC:27";
+
+    /// Step through `golden`, panicking at the first line the trace doesn't
+    /// match
+    fn check_trace(nes: &mut Nes, golden: &str) {
+        let golden = golden
+            .lines()
+            .map(|line| line.trim_end())
+            .collect::<Vec<_>>();
+
+        for (i, expected) in golden.iter().enumerate() {
+            let actual = log(nes);
+
+            if actual != *expected {
+                let context = golden[i.saturating_sub(CONTEXT_LINES)..i].join("\n");
+                panic!(
+                    "Trace diverged at line {}\n{}\nexpected: {}\nactual:   {}",
+                    i + 1,
+                    context,
+                    expected,
+                    actual
+                );
+            }
+
+            nes.step();
+        }
+    }
+
+    #[test]
+    fn trace_matches_start_of_golden_log() {
+        let mut image = b"NES\x1a\x01\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00".to_vec();
+        let mut prg = vec![0; 0x4000];
+        prg[0x0000..0x0003].copy_from_slice(&[0x4C, 0xF5, 0xC5]);
+        prg[0x05F5..0x0600].copy_from_slice(&[
+            0xA2, 0x00, 0x86, 0x00, 0x86, 0x10, 0x86, 0x11, 0x20, 0x2D, 0xC7,
+        ]);
+        prg[0x072D] = 0xEA;
+        image.extend(prg);
+        image.extend(vec![0; 0x2000]);
+
+        let rom = Rom::from_path(&mut image.as_slice()).unwrap();
+        let mut nes = Nes::with_rom(rom);
+        nes.cpu.pc = 0xC000;
+
+        check_trace(&mut nes, GOLDEN_START);
+    }
+
+    /// Runs nestest in automation mode and compares the trace against a
+    /// Nintendulator log of the same run
+    #[test]
+    #[ignore = "needs nestest.nes and nestest.log in test-roms"]
+    fn nestest_matches_golden_log() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("test-roms");
+        let read = |name: &str| {
+            let path = dir.join(name);
+            fs::read(&path).unwrap_or_else(|e| panic!("{}: {}", path.display(), e))
+        };
+
+        let rom = read("nestest.nes");
+        let golden = String::from_utf8(read("nestest.log")).unwrap();
+
+        let rom = Rom::from_path(&mut rom.as_slice()).unwrap();
+        let mut nes = Nes::with_rom(rom);
+
+        // Automation mode starts at $C000 rather than the reset vector, which
+        // runs every test without needing a screen or a controller
+        nes.cpu.pc = 0xC000;
+
+        check_trace(&mut nes, &golden);
+    }
+}
@@ -1,45 +1,82 @@
+mod chr;
+mod mmc1;
+mod mmc3;
 mod nrom;
+mod prg_ram;
 mod uxrom;
 
-use crate::{rom::Rom, Mem};
-use nrom::nrom;
-use uxrom::uxrom;
+use crate::{
+    rom::{NametableMirror, Rom},
+    state::Snapshot,
+};
+use mmc1::Mmc1;
+use mmc3::Mmc3;
+use nrom::Nrom;
+use uxrom::Uxrom;
 
-pub struct PrgMem(pub Box<dyn Mem + Send>);
+/// A cartridge, as seen from both the CPU and the PPU. The mapper sees every
+/// access either processor makes to cartridge space, so it can keep a single
+/// set of bank registers, interrupt counters and mirroring state.
+pub trait Mapper: Snapshot {
+    /// For debugging only - read a byte from the CPU's side of the cartridge
+    /// ($4020-$FFFF) without modifying the system state. Returns `None` if
+    /// the cartridge doesn't drive the data bus at that address.
+    fn peek_prg(&self, addr: u16) -> Option<u8>;
 
-impl AsRef<dyn Mem + Send> for PrgMem {
-    fn as_ref(&self) -> &(dyn Mem + Send + 'static) {
-        self.0.as_ref()
+    /// Load a byte from the CPU's side of the cartridge ($4020-$FFFF).
+    /// Returns `None` if the cartridge doesn't drive the data bus at that
+    /// address, leaving it open.
+    fn load_prg(&mut self, addr: u16) -> Option<u8> {
+        self.peek_prg(addr)
     }
-}
 
-impl AsMut<dyn Mem + Send> for PrgMem {
-    fn as_mut(&mut self) -> &mut (dyn Mem + Send + 'static) {
-        self.0.as_mut()
-    }
-}
+    /// Store a byte to the CPU's side of the cartridge ($4020-$FFFF). This is
+    /// typically how games write to bank registers.
+    fn store_prg(&mut self, addr: u16, val: u8);
 
-pub struct ChrMem(pub Box<dyn Mem + Send>);
+    /// For debugging only - read a byte from pattern table space ($0000-$1FFF)
+    /// without modifying the system state
+    fn peek_chr(&self, addr: u16) -> u8;
 
-impl AsRef<dyn Mem + Send> for ChrMem {
-    fn as_ref(&self) -> &(dyn Mem + Send + 'static) {
-        self.0.as_ref()
+    /// Load a byte from pattern table space ($0000-$1FFF)
+    fn load_chr(&mut self, addr: u16) -> u8 {
+        self.peek_chr(addr)
     }
-}
 
-impl AsMut<dyn Mem + Send> for ChrMem {
-    fn as_mut(&mut self) -> &mut (dyn Mem + Send + 'static) {
-        self.0.as_mut()
+    /// Store a byte to pattern table space ($0000-$1FFF)
+    fn store_chr(&mut self, addr: u16, val: u8);
+
+    /// The cartridge's PRG RAM, mapped at $6000-$7FFF. On carts with a battery
+    /// this is what gets written to a save file.
+    fn prg_ram(&self) -> &[u8];
+
+    /// Mutable access to the cartridge's PRG RAM, e.g. to restore a save file
+    fn prg_ram_mut(&mut self) -> &mut [u8];
+
+    /// The nametable arrangement currently selected by the cartridge
+    fn mirroring(&self) -> NametableMirror;
+
+    /// Called once for every PPU cycle
+    fn tick(&mut self) {}
+
+    /// Whether the cartridge is currently asserting the CPU's IRQ line
+    fn irq(&self) -> bool {
+        false
     }
 }
 
-type SplitRom = (ChrMem, PrgMem);
+/// Whether there is a mapper implementation for the given mapper number
+pub fn is_supported(mapper: u16) -> bool {
+    matches!(mapper, 0 | 1 | 2 | 4)
+}
 
 /// Given a ROM, create a mapper that can read & write data with it
-pub fn create_mapper(rom: Rom) -> SplitRom {
+pub fn create_mapper(rom: Rom) -> Box<dyn Mapper + Send> {
     match rom.header.mapper() {
-        0 => nrom(rom),
-        2 => uxrom(rom),
+        0 => Box::new(Nrom::new(rom)),
+        1 => Box::new(Mmc1::new(rom)),
+        2 => Box::new(Uxrom::new(rom)),
+        4 => Box::new(Mmc3::new(rom)),
         x => panic!("Unsupported mapper {}", x),
     }
 }
@@ -0,0 +1,67 @@
+use crate::{
+    rom::{INesHeader, CHR_ROM_BANK_SIZE},
+    state::{Snapshot, StateError, StateReader, StateWriter},
+};
+
+/// Pattern table memory on the cartridge. Carts without any CHR ROM have CHR
+/// RAM instead, which the CPU fills in through PPUDATA.
+pub struct Chr {
+    data: Vec<u8>,
+    writable: bool,
+}
+
+impl Chr {
+    /// Use the cart's CHR ROM, or allocate the CHR RAM described by the header
+    /// if there isn't any
+    pub fn new(rom: Vec<u8>, header: &INesHeader) -> Self {
+        if !rom.is_empty() {
+            return Self {
+                data: rom,
+                writable: false,
+            };
+        }
+
+        // NES 2.0 headers are allowed to leave this out, in which case
+        // assume the usual 8KiB chip
+        let byte_size = match header.chr_ram_bytes() + header.chr_nvram_bytes() {
+            0 => CHR_ROM_BANK_SIZE,
+            n => n,
+        };
+
+        Self {
+            data: vec![0; byte_size],
+            writable: true,
+        }
+    }
+
+    /// Read the byte at an offset into CHR memory, mirroring if the offset is
+    /// past the end
+    pub fn peekb(&self, offset: usize) -> u8 {
+        self.data[offset % self.data.len()]
+    }
+
+    /// Write to CHR RAM. Writes to CHR ROM are ignored.
+    pub fn storeb(&mut self, offset: usize, val: u8) {
+        if self.writable {
+            let len = self.data.len();
+            self.data[offset % len] = val;
+        }
+    }
+}
+
+/// Only CHR RAM is saved, since CHR ROM can't change
+impl Snapshot for Chr {
+    fn save(&self, w: &mut StateWriter) {
+        if self.writable {
+            w.bytes(&self.data);
+        }
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        if self.writable {
+            r.bytes(&mut self.data)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,469 @@
+use super::{chr::Chr, prg_ram::PrgRam, Mapper};
+use crate::rom::{NametableMirror, Rom};
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+use mos6502::Mem;
+
+const PRG_ROM_BANK_LEN: usize = 0x4000; // 16KiB
+const CHR_BANK_LEN: usize = 0x1000; // 4KiB
+const DOTS_PER_CPU_CYCLE: u8 = 3;
+
+/// The internal registers of the MMC1, which are loaded one bit at a time from
+/// the CPU
+struct Registers {
+    shift: u8,
+    shift_count: u8,
+    /// CPPMM
+    ///
+    /// * C: CHR ROM bank mode (0: switch 8KiB at a time, 1: two 4KiB banks)
+    /// * P: PRG ROM bank mode (0, 1: 32KiB, 2: fix first bank, 3: fix last bank)
+    /// * M: Mirroring (0: one-screen lower, 1: one-screen upper, 2: vertical,
+    ///   3: horizontal)
+    control: u8,
+    chr_bank0: u8,
+    chr_bank1: u8,
+    /// RPPPP
+    ///
+    /// * R: PRG RAM chip enable (0: enabled)
+    /// * P: 16KiB PRG ROM bank
+    prg_bank: u8,
+    /// PPU dots since the last write, saturating. Writes on back to back CPU
+    /// cycles only count once, which is how games get away with INC on a
+    /// register even though it writes twice.
+    dots_since_write: u8,
+}
+
+impl Registers {
+    fn new() -> Self {
+        Self {
+            shift: 0,
+            shift_count: 0,
+            // The MMC1 powers on with the last PRG bank fixed at $C000
+            control: 0x0C,
+            chr_bank0: 0,
+            chr_bank1: 0,
+            prg_bank: 0,
+            dots_since_write: u8::MAX,
+        }
+    }
+
+    /// Load one bit into the shift register. Once five bits have been
+    /// written, the register selected by bits 13 and 14 of the address is
+    /// updated.
+    fn write(&mut self, addr: u16, val: u8) {
+        let back_to_back = self.dots_since_write <= DOTS_PER_CPU_CYCLE;
+        self.dots_since_write = 0;
+
+        // Resets go through even straight after another write
+        if bit!(val, 7) {
+            self.shift = 0;
+            self.shift_count = 0;
+            self.control |= 0x0C;
+            return;
+        }
+
+        if back_to_back {
+            return;
+        }
+
+        self.shift = (self.shift >> 1) | ((val & 0x01) << 4);
+        self.shift_count += 1;
+
+        if self.shift_count < 5 {
+            return;
+        }
+
+        let val = self.shift;
+        self.shift = 0;
+        self.shift_count = 0;
+
+        match addr & 0xE000 {
+            0x8000 => self.control = val,
+            0xA000 => self.chr_bank0 = val,
+            0xC000 => self.chr_bank1 = val,
+            0xE000 => self.prg_bank = val,
+            _ => unreachable!(),
+        }
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        !bit!(self.prg_bank, 4)
+    }
+
+    fn prg_mode(&self) -> u8 {
+        (self.control >> 2) & 0x03
+    }
+
+    fn chr_4k_mode(&self) -> bool {
+        bit!(self.control, 4)
+    }
+
+    fn mirroring(&self) -> NametableMirror {
+        match self.control & 0x03 {
+            0 => NametableMirror::SingleScreenLower,
+            1 => NametableMirror::SingleScreenUpper,
+            2 => NametableMirror::Vertical,
+            3 => NametableMirror::Horizontal,
+            _ => unreachable!(),
+        }
+    }
+}
+
+pub struct Mmc1 {
+    regs: Registers,
+    prg_rom: Vec<u8>,
+    prg_ram: PrgRam,
+    chr: Chr,
+}
+
+impl Mmc1 {
+    pub fn new(rom: Rom) -> Self {
+        let prg_ram = PrgRam::from_rom(&rom);
+        let Rom {
+            header, prg, chr, ..
+        } = rom;
+
+        let chr = Chr::new(chr, &header);
+
+        Self {
+            regs: Registers::new(),
+            prg_rom: prg,
+            prg_ram,
+            chr,
+        }
+    }
+
+    fn prg_addr(&self, addr: u16) -> usize {
+        let regs = &self.regs;
+        let num_banks = self.prg_rom.len() / PRG_ROM_BANK_LEN;
+        let addr = addr as usize;
+
+        // SUROM boards use bit 4 of the CHR bank register to select which
+        // 256KiB half of PRG ROM is visible
+        let outer = if num_banks > 16 {
+            (regs.chr_bank0 & 0x10) as usize
+        } else {
+            0
+        };
+        let bank = (regs.prg_bank & 0x0F) as usize;
+
+        let bank = match regs.prg_mode() {
+            0 | 1 => (bank & 0x0E) + (addr - 0x8000) / PRG_ROM_BANK_LEN,
+            2 if addr < 0xC000 => 0,
+            2 => bank,
+            3 if addr < 0xC000 => bank,
+            3 => 0x0F,
+            _ => unreachable!(),
+        };
+
+        ((outer | bank) % num_banks) * PRG_ROM_BANK_LEN + (addr & 0x3FFF)
+    }
+
+    fn chr_addr(&self, addr: u16) -> usize {
+        let regs = &self.regs;
+        let addr = addr as usize & 0x1FFF;
+
+        let bank = if regs.chr_4k_mode() {
+            if addr < 0x1000 {
+                regs.chr_bank0 as usize
+            } else {
+                regs.chr_bank1 as usize
+            }
+        } else {
+            // In 8KiB mode the low bit of the bank number is ignored
+            (regs.chr_bank0 & 0x1E) as usize + addr / CHR_BANK_LEN
+        };
+
+        bank * CHR_BANK_LEN + (addr & 0x0FFF)
+    }
+}
+
+impl Mapper for Mmc1 {
+    fn peek_prg(&self, addr: u16) -> Option<u8> {
+        if addr < 0x6000 {
+            None
+        } else if addr < 0x8000 {
+            if self.regs.prg_ram_enabled() {
+                Some(self.prg_ram.peekb(addr))
+            } else {
+                None
+            }
+        } else {
+            Some(self.prg_rom[self.prg_addr(addr)])
+        }
+    }
+
+    fn store_prg(&mut self, addr: u16, val: u8) {
+        if addr < 0x6000 {
+            return;
+        }
+
+        if addr < 0x8000 {
+            if self.regs.prg_ram_enabled() {
+                self.prg_ram.storeb(addr, val);
+            }
+        } else {
+            self.regs.write(addr, val);
+        }
+    }
+
+    fn peek_chr(&self, addr: u16) -> u8 {
+        self.chr.peekb(self.chr_addr(addr))
+    }
+
+    fn store_chr(&mut self, addr: u16, val: u8) {
+        let addr = self.chr_addr(addr);
+        self.chr.storeb(addr, val);
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        self.prg_ram.as_slice()
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        self.prg_ram.as_mut_slice()
+    }
+
+    fn mirroring(&self) -> NametableMirror {
+        self.regs.mirroring()
+    }
+
+    fn tick(&mut self) {
+        self.regs.dots_since_write = self.regs.dots_since_write.saturating_add(1);
+    }
+}
+
+impl Snapshot for Registers {
+    fn save(&self, w: &mut StateWriter) {
+        w.u8(self.shift);
+        w.u8(self.shift_count);
+        w.u8(self.control);
+        w.u8(self.chr_bank0);
+        w.u8(self.chr_bank1);
+        w.u8(self.prg_bank);
+        w.u8(self.dots_since_write);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.shift = r.u8()?;
+        self.shift_count = r.u8()?;
+        self.control = r.u8()?;
+        self.chr_bank0 = r.u8()?;
+        self.chr_bank1 = r.u8()?;
+        self.prg_bank = r.u8()?;
+        self.dots_since_write = r.u8()?;
+        Ok(())
+    }
+}
+
+impl Snapshot for Mmc1 {
+    fn save(&self, w: &mut StateWriter) {
+        self.regs.save(w);
+        self.prg_ram.save(w);
+        self.chr.save(w);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.regs.load(r)?;
+        self.prg_ram.load(r)?;
+        self.chr.load(r)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Nes;
+
+    /// An MMC1 cart with 128KiB each of PRG and CHR ROM. Every byte holds
+    /// the number of the bank it's in.
+    fn mmc1_image() -> Vec<u8> {
+        let mut image = b"NES\x1a\x08\x10\x10\x00\x00\x00\x00\x00\x00\x00\x00\x00".to_vec();
+        image.extend((0..0x20000).map(|i| (i / PRG_ROM_BANK_LEN) as u8));
+        image.extend((0..0x20000).map(|i| (i / CHR_BANK_LEN) as u8));
+        image
+    }
+
+    fn mmc1() -> Mmc1 {
+        Mmc1::new(Rom::from_path(&mut mmc1_image().as_slice()).unwrap())
+    }
+
+    fn run_cycles(mapper: &mut Mmc1, cycles: usize) {
+        for _ in 0..cycles * DOTS_PER_CPU_CYCLE as usize {
+            mapper.tick();
+        }
+    }
+
+    /// Write to the mapper the way STA does, a few cycles after the last
+    /// write
+    fn store(mapper: &mut Mmc1, addr: u16, val: u8) {
+        run_cycles(mapper, 4);
+        mapper.store_prg(addr, val);
+    }
+
+    /// Load a register the way games do, one bit at a time starting with the
+    /// lowest
+    fn write_reg(mapper: &mut Mmc1, addr: u16, val: u8) {
+        for i in 0..5 {
+            store(mapper, addr, val >> i);
+        }
+    }
+
+    #[test]
+    fn shift_register_takes_5_writes() {
+        let mut mapper = mmc1();
+
+        for i in 0..4 {
+            store(&mut mapper, 0xE000, 3 >> i);
+        }
+        assert_eq!(mapper.peek_prg(0x8000), Some(0));
+
+        store(&mut mapper, 0xE000, 0);
+        assert_eq!(mapper.peek_prg(0x8000), Some(3));
+    }
+
+    #[test]
+    fn reset_write_clears_shift_register() {
+        let mut mapper = mmc1();
+        store(&mut mapper, 0xE000, 1);
+        store(&mut mapper, 0xE000, 1);
+        store(&mut mapper, 0xE000, 0x80);
+        write_reg(&mut mapper, 0xE000, 2);
+
+        assert_eq!(mapper.peek_prg(0x8000), Some(2));
+    }
+
+    #[test]
+    fn back_to_back_writes_count_once() {
+        let mut mapper = mmc1();
+
+        // Like INC, write the old value and then the new one on the next
+        // cycle
+        for i in 0..5 {
+            store(&mut mapper, 0xE000, 3 >> i);
+            run_cycles(&mut mapper, 1);
+            mapper.store_prg(0xE000, (3 >> i) ^ 1);
+        }
+
+        assert_eq!(mapper.peek_prg(0x8000), Some(3));
+    }
+
+    #[test]
+    fn inc_writes_register_once() {
+        let mut image = mmc1_image();
+        let last_bank = 0x10 + 0x1C000;
+
+        // INC $E000 five times from $C000. $E000 holds 7, so each INC writes
+        // 7 and then 8 on the next cycle.
+        for i in 0..5 {
+            image[last_bank + i * 3..last_bank + i * 3 + 3].copy_from_slice(&[0xEE, 0x00, 0xE0]);
+        }
+        image[last_bank + 0x3FFC..last_bank + 0x3FFE].copy_from_slice(&[0x00, 0xC0]);
+
+        let mut nes = Nes::with_rom(Rom::from_path(&mut image.as_slice()).unwrap());
+        for _ in 0..5 {
+            nes.step();
+        }
+
+        assert_eq!(nes.cpu.mem.mapper.peek_prg(0x9000), Some(7));
+    }
+
+    #[test]
+    fn reset_write_right_after_another_write() {
+        let mut mapper = mmc1();
+        store(&mut mapper, 0xE000, 1);
+        run_cycles(&mut mapper, 1);
+        mapper.store_prg(0xE000, 0x80);
+        write_reg(&mut mapper, 0xE000, 2);
+
+        assert_eq!(mapper.peek_prg(0x8000), Some(2));
+    }
+
+    #[test]
+    fn reset_write_fixes_last_prg_bank() {
+        let mut mapper = mmc1();
+        write_reg(&mut mapper, 0x8000, 0x00);
+        store(&mut mapper, 0x8000, 0x80);
+        write_reg(&mut mapper, 0xE000, 2);
+
+        assert_eq!(mapper.peek_prg(0x8000), Some(2));
+        assert_eq!(mapper.peek_prg(0xC000), Some(7));
+    }
+
+    #[test]
+    fn prg_32k_mode_ignores_low_bit() {
+        let mut mapper = mmc1();
+        write_reg(&mut mapper, 0x8000, 0x00);
+        write_reg(&mut mapper, 0xE000, 3);
+
+        assert_eq!(mapper.peek_prg(0x8000), Some(2));
+        assert_eq!(mapper.peek_prg(0xC000), Some(3));
+    }
+
+    #[test]
+    fn prg_mode_2_fixes_first_bank() {
+        let mut mapper = mmc1();
+        write_reg(&mut mapper, 0x8000, 0x08);
+        write_reg(&mut mapper, 0xE000, 5);
+
+        assert_eq!(mapper.peek_prg(0x8000), Some(0));
+        assert_eq!(mapper.peek_prg(0xC000), Some(5));
+    }
+
+    #[test]
+    fn prg_mode_3_fixes_last_bank() {
+        let mut mapper = mmc1();
+        write_reg(&mut mapper, 0x8000, 0x0C);
+        write_reg(&mut mapper, 0xE000, 5);
+
+        assert_eq!(mapper.peek_prg(0x8000), Some(5));
+        assert_eq!(mapper.peek_prg(0xC000), Some(7));
+    }
+
+    #[test]
+    fn prg_ram_can_be_disabled() {
+        let mut mapper = mmc1();
+        mapper.store_prg(0x6000, 0x42);
+        assert_eq!(mapper.peek_prg(0x6000), Some(0x42));
+
+        write_reg(&mut mapper, 0xE000, 0x10);
+        assert_eq!(mapper.peek_prg(0x6000), None);
+    }
+
+    #[test]
+    fn chr_8k_mode_ignores_low_bit() {
+        let mut mapper = mmc1();
+        write_reg(&mut mapper, 0x8000, 0x00);
+        write_reg(&mut mapper, 0xA000, 5);
+        write_reg(&mut mapper, 0xC000, 9);
+
+        assert_eq!(mapper.peek_chr(0x0000), 4);
+        assert_eq!(mapper.peek_chr(0x1000), 5);
+    }
+
+    #[test]
+    fn chr_4k_mode_switches_halves_separately() {
+        let mut mapper = mmc1();
+        write_reg(&mut mapper, 0x8000, 0x10);
+        write_reg(&mut mapper, 0xA000, 5);
+        write_reg(&mut mapper, 0xC000, 9);
+
+        assert_eq!(mapper.peek_chr(0x0000), 5);
+        assert_eq!(mapper.peek_chr(0x1000), 9);
+    }
+
+    #[test]
+    fn mirroring_from_control() {
+        let mut mapper = mmc1();
+        let modes = [
+            NametableMirror::SingleScreenLower,
+            NametableMirror::SingleScreenUpper,
+            NametableMirror::Vertical,
+            NametableMirror::Horizontal,
+        ];
+
+        for (val, mode) in modes.iter().enumerate() {
+            write_reg(&mut mapper, 0x8000, val as u8);
+            assert_eq!(mapper.mirroring(), *mode);
+        }
+    }
+}
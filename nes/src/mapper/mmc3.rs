@@ -0,0 +1,464 @@
+use super::{chr::Chr, prg_ram::PrgRam, Mapper};
+use crate::rom::{NametableMirror, Rom};
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+use mos6502::Mem;
+
+const PRG_ROM_BANK_LEN: usize = 0x2000; // 8KiB
+const CHR_BANK_LEN: usize = 0x0400; // 1KiB
+
+/// The number of PPU cycles that A12 must stay low before a rising edge is
+/// counted. The real chip filters on M2 (the CPU clock), which ignores the short
+/// low periods between the sprite fetches of a single scanline.
+const A12_FILTER_CYCLES: u32 = 10;
+
+/// The bank registers and scanline counter of the MMC3
+struct Registers {
+    /// CPxxxRRR
+    ///
+    /// * C: CHR A12 inversion
+    /// * P: PRG ROM bank mode
+    /// * R: Which bank register is updated on the next bank data write
+    bank_select: u8,
+    banks: [u8; 8],
+    mirroring: NametableMirror,
+    /// EWxxxxxx
+    ///
+    /// * E: PRG RAM chip enable
+    /// * W: Deny writes to PRG RAM
+    prg_ram_protect: u8,
+
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enabled: bool,
+    irq_pending: bool,
+
+    a12_high: bool,
+    a12_low_cycles: u32,
+}
+
+impl Registers {
+    fn new(mirroring: NametableMirror) -> Self {
+        Self {
+            bank_select: 0,
+            banks: [0, 2, 4, 5, 6, 7, 0, 1],
+            mirroring,
+            prg_ram_protect: 0x80,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            irq_pending: false,
+            a12_high: false,
+            a12_low_cycles: 0,
+        }
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        let even = addr & 0x01 == 0;
+
+        match (addr & 0xE000, even) {
+            (0x8000, true) => self.bank_select = val,
+            (0x8000, false) => self.banks[(self.bank_select & 0x07) as usize] = val,
+            // Boards wired for four-screen VRAM ignore the mirroring register
+            (0xA000, true) if self.mirroring == NametableMirror::FourScreen => {}
+            (0xA000, true) if bit!(val, 0) => self.mirroring = NametableMirror::Horizontal,
+            (0xA000, true) => self.mirroring = NametableMirror::Vertical,
+            (0xA000, false) => self.prg_ram_protect = val,
+            (0xC000, true) => self.irq_latch = val,
+            (0xC000, false) => {
+                self.irq_counter = 0;
+                self.irq_reload = true;
+            }
+            (0xE000, true) => {
+                self.irq_enabled = false;
+                self.irq_pending = false;
+            }
+            (0xE000, false) => self.irq_enabled = true,
+            _ => unreachable!(),
+        }
+    }
+
+    fn prg_ram_readable(&self) -> bool {
+        bit!(self.prg_ram_protect, 7)
+    }
+
+    fn prg_ram_writable(&self) -> bool {
+        self.prg_ram_readable() && !bit!(self.prg_ram_protect, 6)
+    }
+
+    /// Watch the PPU address bus for rising edges of A12, which happen once
+    /// per scanline when backgrounds and sprites use different pattern tables
+    fn observe(&mut self, addr: u16) {
+        let high = bit!(addr, 12);
+
+        if high && !self.a12_high && self.a12_low_cycles >= A12_FILTER_CYCLES {
+            self.clock_irq_counter();
+        }
+
+        if high {
+            self.a12_low_cycles = 0;
+        }
+
+        self.a12_high = high;
+    }
+
+    fn clock_irq_counter(&mut self) {
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+            self.irq_reload = false;
+        } else {
+            self.irq_counter -= 1;
+        }
+
+        if self.irq_counter == 0 && self.irq_enabled {
+            self.irq_pending = true;
+        }
+    }
+}
+
+pub struct Mmc3 {
+    regs: Registers,
+    prg_rom: Vec<u8>,
+    prg_ram: PrgRam,
+    chr: Chr,
+}
+
+impl Mmc3 {
+    pub fn new(rom: Rom) -> Self {
+        let regs = Registers::new(rom.header.mirroring());
+        let prg_ram = PrgRam::from_rom(&rom);
+        let Rom {
+            header, prg, chr, ..
+        } = rom;
+
+        let chr = Chr::new(chr, &header);
+
+        Self {
+            regs,
+            prg_rom: prg,
+            prg_ram,
+            chr,
+        }
+    }
+
+    fn prg_addr(&self, addr: u16) -> usize {
+        let regs = &self.regs;
+        let num_banks = self.prg_rom.len() / PRG_ROM_BANK_LEN;
+        let second_last = num_banks.saturating_sub(2);
+        let swap = bit!(regs.bank_select, 6);
+
+        let bank = match (addr & 0xE000, swap) {
+            (0x8000, false) => regs.banks[6] as usize,
+            (0x8000, true) => second_last,
+            (0xA000, _) => regs.banks[7] as usize,
+            (0xC000, false) => second_last,
+            (0xC000, true) => regs.banks[6] as usize,
+            (0xE000, _) => num_banks - 1,
+            _ => unreachable!(),
+        };
+
+        (bank % num_banks) * PRG_ROM_BANK_LEN + (addr as usize & 0x1FFF)
+    }
+
+    fn chr_addr(&self, addr: u16) -> usize {
+        let regs = &self.regs;
+        let addr = addr as usize & 0x1FFF;
+
+        // With A12 inversion the two 2KiB banks move to $1000
+        let slot = if bit!(regs.bank_select, 7) {
+            (addr ^ 0x1000) / CHR_BANK_LEN
+        } else {
+            addr / CHR_BANK_LEN
+        };
+
+        let bank = match slot {
+            0 => regs.banks[0] & 0xFE,
+            1 => regs.banks[0] | 0x01,
+            2 => regs.banks[1] & 0xFE,
+            3 => regs.banks[1] | 0x01,
+            n => regs.banks[n - 2],
+        } as usize;
+
+        bank * CHR_BANK_LEN + (addr & 0x03FF)
+    }
+}
+
+impl Mapper for Mmc3 {
+    fn peek_prg(&self, addr: u16) -> Option<u8> {
+        if addr < 0x6000 {
+            None
+        } else if addr < 0x8000 {
+            if self.regs.prg_ram_readable() {
+                Some(self.prg_ram.peekb(addr))
+            } else {
+                None
+            }
+        } else {
+            Some(self.prg_rom[self.prg_addr(addr)])
+        }
+    }
+
+    fn store_prg(&mut self, addr: u16, val: u8) {
+        if addr < 0x6000 {
+            return;
+        }
+
+        if addr < 0x8000 {
+            if self.regs.prg_ram_writable() {
+                self.prg_ram.storeb(addr, val);
+            }
+        } else {
+            self.regs.write(addr, val);
+        }
+    }
+
+    fn peek_chr(&self, addr: u16) -> u8 {
+        self.chr.peekb(self.chr_addr(addr))
+    }
+
+    fn load_chr(&mut self, addr: u16) -> u8 {
+        self.regs.observe(addr);
+        self.peek_chr(addr)
+    }
+
+    fn store_chr(&mut self, addr: u16, val: u8) {
+        self.regs.observe(addr);
+
+        let addr = self.chr_addr(addr);
+        self.chr.storeb(addr, val);
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        self.prg_ram.as_slice()
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        self.prg_ram.as_mut_slice()
+    }
+
+    fn mirroring(&self) -> NametableMirror {
+        self.regs.mirroring
+    }
+
+    fn tick(&mut self) {
+        if !self.regs.a12_high {
+            self.regs.a12_low_cycles = self.regs.a12_low_cycles.saturating_add(1);
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.regs.irq_pending
+    }
+}
+
+impl Snapshot for Registers {
+    fn save(&self, w: &mut StateWriter) {
+        w.u8(self.bank_select);
+        w.bytes(&self.banks);
+        self.mirroring.save(w);
+        w.u8(self.prg_ram_protect);
+        w.u8(self.irq_latch);
+        w.u8(self.irq_counter);
+        w.bool(self.irq_reload);
+        w.bool(self.irq_enabled);
+        w.bool(self.irq_pending);
+        w.bool(self.a12_high);
+        w.u32(self.a12_low_cycles);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.bank_select = r.u8()?;
+        r.bytes(&mut self.banks)?;
+        self.mirroring.load(r)?;
+        self.prg_ram_protect = r.u8()?;
+        self.irq_latch = r.u8()?;
+        self.irq_counter = r.u8()?;
+        self.irq_reload = r.bool()?;
+        self.irq_enabled = r.bool()?;
+        self.irq_pending = r.bool()?;
+        self.a12_high = r.bool()?;
+        self.a12_low_cycles = r.u32()?;
+        Ok(())
+    }
+}
+
+impl Snapshot for Mmc3 {
+    fn save(&self, w: &mut StateWriter) {
+        self.regs.save(w);
+        self.prg_ram.save(w);
+        self.chr.save(w);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.regs.load(r)?;
+        self.prg_ram.load(r)?;
+        self.chr.load(r)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An NES 2.0 MMC3 cart with 2^`prg_exponent` bytes of PRG ROM and 32KiB
+    /// of CHR ROM. Every byte holds the number of the bank it's in.
+    fn mmc3(prg_exponent: u8) -> Mmc3 {
+        let mut image = b"NES\x1a\x00\x04\x40\x08\x00\x0F\x07\x00\x00\x00\x00\x00".to_vec();
+        image[4] = prg_exponent << 2;
+        image.extend((0..1usize << prg_exponent).map(|i| (i / PRG_ROM_BANK_LEN) as u8));
+        image.extend((0..0x8000).map(|i| (i / CHR_BANK_LEN) as u8));
+
+        Mmc3::new(Rom::from_path(&mut image.as_slice()).unwrap())
+    }
+
+    fn set_bank(mapper: &mut Mmc3, select: u8, bank: u8) {
+        mapper.store_prg(0x8000, select);
+        mapper.store_prg(0x8001, bank);
+    }
+
+    /// Hold A12 low long enough to get through the filter, then raise it
+    fn clock_a12(mapper: &mut Mmc3) {
+        mapper.load_chr(0x0000);
+        for _ in 0..A12_FILTER_CYCLES {
+            mapper.tick();
+        }
+        mapper.load_chr(0x1000);
+    }
+
+    #[test]
+    fn prg_mode_0_fixes_second_last_bank_at_c000() {
+        let mut mapper = mmc3(16);
+        set_bank(&mut mapper, 6, 2);
+        set_bank(&mut mapper, 7, 3);
+
+        assert_eq!(mapper.peek_prg(0x8000), Some(2));
+        assert_eq!(mapper.peek_prg(0xA000), Some(3));
+        assert_eq!(mapper.peek_prg(0xC000), Some(6));
+        assert_eq!(mapper.peek_prg(0xE000), Some(7));
+    }
+
+    #[test]
+    fn prg_mode_1_fixes_second_last_bank_at_8000() {
+        let mut mapper = mmc3(16);
+        set_bank(&mut mapper, 0x46, 2);
+
+        assert_eq!(mapper.peek_prg(0x8000), Some(6));
+        assert_eq!(mapper.peek_prg(0xC000), Some(2));
+        assert_eq!(mapper.peek_prg(0xE000), Some(7));
+    }
+
+    #[test]
+    fn single_prg_bank_fills_every_window() {
+        let mut mapper = mmc3(13);
+
+        for mode in [0x06, 0x46] {
+            set_bank(&mut mapper, mode, 3);
+
+            for addr in [0x8000, 0xA000, 0xC000, 0xE000] {
+                assert_eq!(mapper.peek_prg(addr), Some(0));
+            }
+        }
+    }
+
+    #[test]
+    fn chr_2k_banks_ignore_low_bit() {
+        let mut mapper = mmc3(16);
+        set_bank(&mut mapper, 0, 5);
+        set_bank(&mut mapper, 2, 9);
+
+        assert_eq!(mapper.peek_chr(0x0000), 4);
+        assert_eq!(mapper.peek_chr(0x0400), 5);
+        assert_eq!(mapper.peek_chr(0x1000), 9);
+    }
+
+    #[test]
+    fn chr_a12_inversion_swaps_halves() {
+        let mut mapper = mmc3(16);
+        set_bank(&mut mapper, 0x80, 4);
+        set_bank(&mut mapper, 0x82, 9);
+
+        assert_eq!(mapper.peek_chr(0x0000), 9);
+        assert_eq!(mapper.peek_chr(0x1000), 4);
+        assert_eq!(mapper.peek_chr(0x1400), 5);
+    }
+
+    #[test]
+    fn mirroring_writes() {
+        let mut mapper = mmc3(16);
+
+        mapper.store_prg(0xA000, 0x00);
+        assert_eq!(mapper.mirroring(), NametableMirror::Vertical);
+
+        mapper.store_prg(0xA000, 0x01);
+        assert_eq!(mapper.mirroring(), NametableMirror::Horizontal);
+    }
+
+    #[test]
+    fn four_screen_ignores_mirroring_writes() {
+        let mut mapper = mmc3(16);
+        mapper.regs.mirroring = NametableMirror::FourScreen;
+        mapper.store_prg(0xA000, 0x01);
+
+        assert_eq!(mapper.mirroring(), NametableMirror::FourScreen);
+    }
+
+    #[test]
+    fn irq_counter_reloads_then_counts_down() {
+        let mut mapper = mmc3(16);
+        mapper.store_prg(0xC000, 2);
+        mapper.store_prg(0xE001, 0);
+
+        clock_a12(&mut mapper);
+        assert_eq!(mapper.regs.irq_counter, 2);
+        clock_a12(&mut mapper);
+        assert!(!mapper.irq());
+        clock_a12(&mut mapper);
+        assert!(mapper.irq());
+    }
+
+    #[test]
+    fn irq_reload_happens_on_next_clock() {
+        let mut mapper = mmc3(16);
+        mapper.store_prg(0xC000, 5);
+        clock_a12(&mut mapper);
+        clock_a12(&mut mapper);
+        assert_eq!(mapper.regs.irq_counter, 4);
+
+        mapper.store_prg(0xC001, 0);
+        assert_eq!(mapper.regs.irq_counter, 0);
+
+        clock_a12(&mut mapper);
+        assert_eq!(mapper.regs.irq_counter, 5);
+    }
+
+    #[test]
+    fn irq_disable_acknowledges() {
+        let mut mapper = mmc3(16);
+        mapper.store_prg(0xC000, 1);
+        mapper.store_prg(0xE001, 0);
+        clock_a12(&mut mapper);
+        clock_a12(&mut mapper);
+        assert!(mapper.irq());
+
+        mapper.store_prg(0xE000, 0);
+        assert!(!mapper.irq());
+
+        clock_a12(&mut mapper);
+        clock_a12(&mut mapper);
+        assert!(!mapper.irq());
+    }
+
+    #[test]
+    fn a12_filter_ignores_short_low_periods() {
+        let mut mapper = mmc3(16);
+        mapper.store_prg(0xC000, 3);
+        clock_a12(&mut mapper);
+
+        mapper.load_chr(0x0000);
+        mapper.tick();
+        mapper.load_chr(0x1000);
+
+        assert_eq!(mapper.regs.irq_counter, 3);
+    }
+}
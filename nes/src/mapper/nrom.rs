@@ -1,37 +1,89 @@
-use super::{ChrMem, PrgMem};
-use crate::{rom::Rom, Mem};
+use super::{chr::Chr, prg_ram::PrgRam, Mapper};
+use crate::rom::{NametableMirror, Rom};
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+use mos6502::Mem;
 
 const PRG_ROM_BANK_LEN: usize = 0x4000; // 16KiB
 
-pub struct Chr(Vec<u8>);
+pub struct Nrom {
+    prg: Vec<u8>,
+    prg_ram: PrgRam,
+    chr: Chr,
+    mirroring: NametableMirror,
+}
 
-impl Mem for Chr {
-    fn peekb(&self, addr: u16) -> u8 {
-        self.0[addr as usize]
-    }
+impl Nrom {
+    pub fn new(rom: Rom) -> Self {
+        let mirroring = rom.header.mirroring();
+        let prg_ram = PrgRam::from_rom(&rom);
+        let Rom {
+            header, prg, chr, ..
+        } = rom;
+        let chr = Chr::new(chr, &header);
 
-    fn storeb(&mut self, _addr: u16, _val: u8) {}
+        Self {
+            prg,
+            prg_ram,
+            chr,
+            mirroring,
+        }
+    }
 }
 
-pub struct Prog(Vec<u8>);
-
-impl Mem for Prog {
-    fn peekb(&self, addr: u16) -> u8 {
-        if addr < 0x8000 {
-            0
-        } else if self.0.len() > PRG_ROM_BANK_LEN {
+impl Mapper for Nrom {
+    fn peek_prg(&self, addr: u16) -> Option<u8> {
+        let val = if addr < 0x6000 {
+            return None;
+        } else if addr < 0x8000 {
+            // Family BASIC is the only NROM board with PRG RAM, but it costs
+            // nothing to provide it everywhere
+            self.prg_ram.peekb(addr)
+        } else if self.prg.len() > PRG_ROM_BANK_LEN {
             // This nrom cart has 2 banks of PRG ROM (32KiB)
-            self.0[addr as usize & 0x7FFF]
+            self.prg[addr as usize & 0x7FFF]
         } else {
             // This nrom cart has 1 bank of PRG ROM (16KiB)
-            self.0[addr as usize & 0x3FFF]
+            self.prg[addr as usize & 0x3FFF]
+        };
+
+        Some(val)
+    }
+
+    fn store_prg(&mut self, addr: u16, val: u8) {
+        if (0x6000..0x8000).contains(&addr) {
+            self.prg_ram.storeb(addr, val);
         }
     }
 
-    fn storeb(&mut self, _addr: u16, _val: u8) {}
+    fn peek_chr(&self, addr: u16) -> u8 {
+        self.chr.peekb(addr as usize)
+    }
+
+    fn store_chr(&mut self, addr: u16, val: u8) {
+        self.chr.storeb(addr as usize, val);
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        self.prg_ram.as_slice()
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        self.prg_ram.as_mut_slice()
+    }
+
+    fn mirroring(&self) -> NametableMirror {
+        self.mirroring
+    }
 }
 
-pub fn nrom(rom: Rom) -> (ChrMem, PrgMem) {
-    let Rom { prg, chr, .. } = rom;
-    (ChrMem(Box::new(Chr(chr))), PrgMem(Box::new(Prog(prg))))
+impl Snapshot for Nrom {
+    fn save(&self, w: &mut StateWriter) {
+        self.prg_ram.save(w);
+        self.chr.save(w);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.prg_ram.load(r)?;
+        self.chr.load(r)
+    }
 }
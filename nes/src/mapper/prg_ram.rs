@@ -0,0 +1,71 @@
+use crate::{
+    rom::Rom,
+    state::{Snapshot, StateError, StateReader, StateWriter},
+};
+use mos6502::Mem;
+
+/// Work RAM on the cartridge, mapped at $6000-$7FFF. Chips smaller than 8KiB
+/// are mirrored to fill the window, and carts without any RAM read back 0.
+pub struct PrgRam(Vec<u8>);
+
+impl PrgRam {
+    /// Allocate both the volatile and battery-backed PRG RAM described by a
+    /// ROM header, and copy in the trainer if there is one
+    pub fn from_rom(rom: &Rom) -> Self {
+        let header = &rom.header;
+        let mut byte_size = header.prg_ram_bytes() + header.prg_nvram_bytes();
+
+        // The trainer lives at $7000, so make sure there's RAM to hold it
+        if rom.trainer.is_some() {
+            byte_size = byte_size.max(0x2000);
+        }
+
+        let mut ram = Self(vec![0; byte_size]);
+
+        if let Some(trainer) = &rom.trainer {
+            for (addr, &val) in (0x7000..).zip(trainer) {
+                ram.storeb(addr, val);
+            }
+        }
+
+        ram
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        &mut self.0
+    }
+
+    fn index(&self, addr: u16) -> Option<usize> {
+        if self.0.is_empty() {
+            None
+        } else {
+            Some((addr as usize & 0x1FFF) % self.0.len())
+        }
+    }
+}
+
+impl Mem for PrgRam {
+    fn peekb(&self, addr: u16) -> u8 {
+        self.index(addr).map_or(0, |i| self.0[i])
+    }
+
+    fn storeb(&mut self, addr: u16, val: u8) {
+        if let Some(i) = self.index(addr) {
+            self.0[i] = val;
+        }
+    }
+}
+
+impl Snapshot for PrgRam {
+    fn save(&self, w: &mut StateWriter) {
+        w.bytes(&self.0);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        r.bytes(&mut self.0)
+    }
+}
@@ -1,71 +1,105 @@
-use super::{ChrMem, PrgMem};
-use crate::{Mem, Rom};
-use log::error;
+use super::{chr::Chr, prg_ram::PrgRam, Mapper};
+use crate::rom::{NametableMirror, Rom};
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+use mos6502::Mem;
 
 const PRG_ROM_BANK_LEN: usize = 0x4000; // 16KiB
 
-struct Chr(Vec<u8>);
-
-impl Mem for Chr {
-    fn peekb(&self, addr: u16) -> u8 {
-        self.0[addr as usize]
-    }
-
-    fn storeb(&mut self, addr: u16, val: u8) {
-        let addr = (addr as usize) & 0x1FFF;
-        self.0[addr] = val;
-    }
-}
-
-struct Prg {
+pub struct Uxrom {
     num_banks: usize,
     bank: usize,
-    bytes: Vec<u8>,
+    prg: Vec<u8>,
+    prg_ram: PrgRam,
+    chr: Chr,
+    mirroring: NametableMirror,
 }
 
-impl Prg {
-    fn new(bytes: Vec<u8>, num_banks: usize) -> Self {
+impl Uxrom {
+    pub fn new(rom: Rom) -> Self {
+        let mirroring = rom.header.mirroring();
+        let prg_ram = PrgRam::from_rom(&rom);
+        let Rom {
+            header, prg, chr, ..
+        } = rom;
+        let num_banks = prg.len() / PRG_ROM_BANK_LEN;
+        let chr = Chr::new(chr, &header);
         let bank = 0;
+
         Self {
-            bytes,
-            bank,
             num_banks,
+            bank,
+            prg,
+            prg_ram,
+            chr,
+            mirroring,
         }
     }
 }
 
-impl Mem for Prg {
-    fn peekb(&self, addr: u16) -> u8 {
+impl Mapper for Uxrom {
+    fn peek_prg(&self, addr: u16) -> Option<u8> {
+        if addr < 0x6000 {
+            return None;
+        }
+
         if addr < 0x8000 {
-            error!("Load from PRG 0x{:04X}", addr);
-            return 0;
+            return Some(self.prg_ram.peekb(addr));
         }
 
         let addr = addr as usize;
         let bank_number = if addr >= 0xC000 {
-            // Read from mapped bank
+            // Always reads from last bank
             self.num_banks - 1
         } else {
-            // Always reads from last bank
+            // Read from mapped bank
             self.bank
         };
 
         let base_addr = bank_number * PRG_ROM_BANK_LEN;
         let prg_addr = base_addr + (addr & 0x3FFF);
 
-        self.bytes[prg_addr]
+        Some(self.prg[prg_addr])
     }
 
-    fn storeb(&mut self, _addr: u16, val: u8) {
-        self.bank = (val & 0b0000_0111) as usize;
+    fn store_prg(&mut self, addr: u16, val: u8) {
+        if addr >= 0x8000 {
+            self.bank = (val & 0b0000_0111) as usize;
+        } else if addr >= 0x6000 {
+            self.prg_ram.storeb(addr, val);
+        }
+    }
+
+    fn peek_chr(&self, addr: u16) -> u8 {
+        self.chr.peekb(addr as usize)
+    }
+
+    fn store_chr(&mut self, addr: u16, val: u8) {
+        self.chr.storeb(addr as usize & 0x1FFF, val);
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        self.prg_ram.as_slice()
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        self.prg_ram.as_mut_slice()
+    }
+
+    fn mirroring(&self) -> NametableMirror {
+        self.mirroring
     }
 }
 
-pub fn uxrom(rom: Rom) -> (ChrMem, PrgMem) {
-    let num_banks = rom.header.prg_banks();
-    let Rom { prg, .. } = rom;
-    let chr = vec![0; 0x2000];
-    let chr = Box::new(Chr(chr));
-    let prg = Box::new(Prg::new(prg, num_banks));
-    (ChrMem(chr), PrgMem(prg))
+impl Snapshot for Uxrom {
+    fn save(&self, w: &mut StateWriter) {
+        w.u8(self.bank as u8);
+        self.prg_ram.save(w);
+        self.chr.save(w);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.bank = r.u8()? as usize;
+        self.prg_ram.load(r)?;
+        self.chr.load(r)
+    }
 }
@@ -0,0 +1,512 @@
+mod fm2;
+
+use crate::{input::Buttons, state::StateError, InputState, Nes};
+use std::{
+    error::Error,
+    fmt::Display,
+    fs,
+    io::{self, Read, Write},
+    path::Path,
+};
+
+/// The version of the movie file format
+pub const MOVIE_VERSION: u16 = 1;
+
+const MOVIE_MAGIC: [u8; 4] = *b"NESM";
+
+#[derive(Debug)]
+pub enum MovieError {
+    /// IO Error while reading or writing a movie file
+    IoError(io::Error),
+    /// The data is not a movie
+    BadMagic,
+    /// The movie was written by a different version of the emulator
+    UnsupportedVersion(u16),
+    /// The movie was recorded while a different ROM was loaded
+    RomMismatch,
+    /// The movie ended early or contained a value that can't be played back
+    Corrupt,
+    /// The save state the movie starts from couldn't be loaded
+    State(StateError),
+    /// A line of a text movie couldn't be understood
+    Parse { line: usize, message: String },
+    /// The movie uses a feature this emulator or file format can't handle
+    Unsupported(&'static str),
+}
+
+impl Display for MovieError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::IoError(err) => write!(f, "unable to access movie: {}", err),
+            Self::BadMagic => write!(f, "not a movie"),
+            Self::UnsupportedVersion(v) => write!(
+                f,
+                "movie version {} is not supported (expected {})",
+                v, MOVIE_VERSION
+            ),
+            Self::RomMismatch => write!(f, "movie was recorded with a different ROM"),
+            Self::Corrupt => write!(f, "movie is corrupt"),
+            Self::State(err) => write!(f, "unable to load movie start: {}", err),
+            Self::Parse { line, message } => write!(f, "line {}: {}", line, message),
+            Self::Unsupported(feature) => write!(f, "unsupported: {}", feature),
+        }
+    }
+}
+
+impl Error for MovieError {}
+
+impl From<io::Error> for MovieError {
+    fn from(err: io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::UnexpectedEof => MovieError::Corrupt,
+            _ => MovieError::IoError(err),
+        }
+    }
+}
+
+impl From<StateError> for MovieError {
+    fn from(err: StateError) -> Self {
+        MovieError::State(err)
+    }
+}
+
+/// The buttons held on both controllers for the length of one frame
+#[derive(Default, Debug, Copy, Clone, Eq, PartialEq)]
+pub struct MovieFrame {
+    pub gamepad1: Buttons,
+    pub gamepad2: Buttons,
+}
+
+impl From<MovieFrame> for InputState {
+    fn from(frame: MovieFrame) -> Self {
+        Self {
+            gamepad1: Some(frame.gamepad1),
+            gamepad2: Some(frame.gamepad2),
+        }
+    }
+}
+
+/// Where playback of a movie begins
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum MovieStart {
+    /// A freshly powered on console
+    PowerOn,
+    /// A save state captured when recording began
+    SaveState(Vec<u8>),
+}
+
+/// A recording of controller input, one entry per frame, which plays back
+/// identically every time from the same starting point
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Movie {
+    pub rom_hash: u64,
+    pub start: MovieStart,
+    pub frames: Vec<MovieFrame>,
+}
+
+impl Movie {
+    pub fn new(rom_hash: u64, start: MovieStart) -> Self {
+        let frames = Vec::new();
+
+        Self {
+            rom_hash,
+            start,
+            frames,
+        }
+    }
+
+    pub fn write<W: Write>(&self, w: &mut W) -> Result<(), MovieError> {
+        w.write_all(&MOVIE_MAGIC)?;
+        w.write_all(&MOVIE_VERSION.to_le_bytes())?;
+        w.write_all(&self.rom_hash.to_le_bytes())?;
+
+        match &self.start {
+            MovieStart::PowerOn => w.write_all(&[0])?,
+            MovieStart::SaveState(state) => {
+                w.write_all(&[1])?;
+                w.write_all(&(state.len() as u32).to_le_bytes())?;
+                w.write_all(state)?;
+            }
+        }
+
+        w.write_all(&(self.frames.len() as u32).to_le_bytes())?;
+        for frame in &self.frames {
+            w.write_all(&[frame.gamepad1.bits(), frame.gamepad2.bits()])?;
+        }
+
+        Ok(())
+    }
+
+    pub fn read<R: Read>(r: &mut R) -> Result<Self, MovieError> {
+        let mut magic = [0; 4];
+        r.read_exact(&mut magic).map_err(|_| MovieError::BadMagic)?;
+        if magic != MOVIE_MAGIC {
+            return Err(MovieError::BadMagic);
+        }
+
+        let version = u16::from_le_bytes(read_array(r)?);
+        if version != MOVIE_VERSION {
+            return Err(MovieError::UnsupportedVersion(version));
+        }
+
+        let rom_hash = u64::from_le_bytes(read_array(r)?);
+
+        let start = match read_array::<_, 1>(r)? {
+            [0] => MovieStart::PowerOn,
+            [1] => {
+                // Lengths come from the file, so don't trust them enough to
+                // allocate up front
+                let len = u32::from_le_bytes(read_array(r)?) as u64;
+                let mut state = Vec::new();
+                r.take(len).read_to_end(&mut state)?;
+                if state.len() as u64 != len {
+                    return Err(MovieError::Corrupt);
+                }
+                MovieStart::SaveState(state)
+            }
+            _ => return Err(MovieError::Corrupt),
+        };
+
+        let len = u32::from_le_bytes(read_array(r)?) as usize;
+        let mut frames = Vec::new();
+        for _ in 0..len {
+            let [gamepad1, gamepad2] = read_array(r)?;
+            frames.push(MovieFrame {
+                gamepad1: Buttons::from_bits_retain(gamepad1),
+                gamepad2: Buttons::from_bits_retain(gamepad2),
+            });
+        }
+
+        Ok(Self {
+            rom_hash,
+            start,
+            frames,
+        })
+    }
+
+    pub fn save_file<P: AsRef<Path>>(&self, path: P) -> Result<(), MovieError> {
+        let mut buf = Vec::new();
+        self.write(&mut buf)?;
+        fs::write(path, buf)?;
+        Ok(())
+    }
+
+    pub fn load_file<P: AsRef<Path>>(path: P) -> Result<Self, MovieError> {
+        let buf = fs::read(path)?;
+        Self::read(&mut buf.as_slice())
+    }
+
+    /// Read a movie recorded by FCEUX. FCEUX identifies the ROM by the MD5 of
+    /// its PRG and CHR data, so the movie is checked against `rom_md5` and
+    /// then tied to the ROM identified by `rom_hash`.
+    pub fn from_fm2(text: &str, rom_hash: u64, rom_md5: &[u8; 16]) -> Result<Self, MovieError> {
+        fm2::parse(text, rom_hash, rom_md5)
+    }
+
+    /// Convert the movie to FCEUX's format. `rom_name` is informational only.
+    pub fn to_fm2(&self, rom_name: &str) -> Result<String, MovieError> {
+        fm2::write(self, rom_name)
+    }
+
+    pub fn load_fm2_file<P: AsRef<Path>>(
+        path: P,
+        rom_hash: u64,
+        rom_md5: &[u8; 16],
+    ) -> Result<Self, MovieError> {
+        let text = fs::read_to_string(path)?;
+        Self::from_fm2(&text, rom_hash, rom_md5)
+    }
+
+    pub fn save_fm2_file<P: AsRef<Path>>(&self, path: P, rom_name: &str) -> Result<(), MovieError> {
+        fs::write(path, self.to_fm2(rom_name)?)?;
+        Ok(())
+    }
+}
+
+/// Movies are stored in this emulator's own format unless they have FCEUX's
+/// .fm2 extension
+pub(crate) fn is_fm2(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("fm2"))
+}
+
+fn read_array<R: Read, const N: usize>(r: &mut R) -> Result<[u8; N], MovieError> {
+    let mut buf = [0; N];
+    r.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+enum Mode {
+    Idle,
+    Recording(Movie),
+    Playing { movie: Movie, frame: usize },
+}
+
+/// Controls when controller input reaches the console. With no movie
+/// running, input is passed straight through. While recording or playing,
+/// input only changes at the start of a frame, which makes runs
+/// reproducible.
+///
+/// Movies that start from power on run with battery-backed RAM cleared, the
+/// same as FCEUX, so they don't depend on the player's save file. The save
+/// data is put aside while the movie runs and restored when it stops.
+pub struct MoviePlayer {
+    mode: Mode,
+    live_input: MovieFrame,
+    save_data: Option<Vec<u8>>,
+}
+
+impl MoviePlayer {
+    pub fn new() -> Self {
+        let mode = Mode::Idle;
+        let live_input = MovieFrame::default();
+
+        Self {
+            mode,
+            live_input,
+            save_data: None,
+        }
+    }
+
+    /// The player's own battery-backed RAM. While a movie from power on is
+    /// running this is the data that was put aside, not what the movie has
+    /// written to the cartridge.
+    pub fn save_data<'a>(&'a self, nes: &'a Nes) -> Option<&'a [u8]> {
+        match &self.save_data {
+            Some(data) => Some(data),
+            None => nes.save_data(),
+        }
+    }
+
+    /// Power cycle for a movie, putting the player's save data aside first
+    fn power_on(&mut self, nes: &mut Nes) {
+        if self.save_data.is_none() {
+            self.save_data = nes.save_data().map(|data| data.to_vec());
+        }
+
+        nes.power_cycle();
+    }
+
+    pub fn is_recording(&self) -> bool {
+        matches!(self.mode, Mode::Recording(_))
+    }
+
+    pub fn is_playing(&self) -> bool {
+        matches!(self.mode, Mode::Playing { .. })
+    }
+
+    /// The frame of the movie being played or recorded
+    pub fn frame(&self) -> Option<usize> {
+        match &self.mode {
+            Mode::Idle => None,
+            Mode::Recording(movie) => Some(movie.frames.len()),
+            Mode::Playing { frame, .. } => Some(*frame),
+        }
+    }
+
+    /// Input from the user. Ignored during playback.
+    pub fn set_input(&mut self, nes: &mut Nes, input: MovieFrame) {
+        self.live_input = input;
+
+        if let Mode::Idle = self.mode {
+            nes.cpu.mem.input.set(&input.into());
+        }
+    }
+
+    /// Start recording, either from power on or from the current state of
+    /// the machine. Any movie already running is stopped.
+    pub fn record(&mut self, nes: &mut Nes, from_power_on: bool) {
+        let start = if from_power_on {
+            self.power_on(nes);
+            MovieStart::PowerOn
+        } else {
+            MovieStart::SaveState(nes.save_state())
+        };
+
+        let mut movie = Movie::new(nes.rom_hash(), start);
+        movie.frames.push(self.live_input);
+        nes.cpu.mem.input.set(&self.live_input.into());
+
+        self.mode = Mode::Recording(movie);
+    }
+
+    /// Rewind the machine to the start of a movie and begin playing it back
+    pub fn play(&mut self, nes: &mut Nes, movie: Movie) -> Result<(), MovieError> {
+        if movie.rom_hash != nes.rom_hash() {
+            return Err(MovieError::RomMismatch);
+        }
+
+        match &movie.start {
+            MovieStart::PowerOn => self.power_on(nes),
+            MovieStart::SaveState(state) => nes.load_state(state)?,
+        }
+
+        let input = movie.frames.first().copied().unwrap_or_default();
+        nes.cpu.mem.input.set(&input.into());
+
+        self.mode = Mode::Playing { movie, frame: 0 };
+        Ok(())
+    }
+
+    /// Stop playing or recording, returning the movie. Save data put aside
+    /// for the movie is restored.
+    pub fn stop(&mut self, nes: &mut Nes) -> Option<Movie> {
+        nes.cpu.mem.input.set(&self.live_input.into());
+
+        if let Some(data) = self.save_data.take() {
+            nes.load_save_data(&data);
+        }
+
+        match std::mem::replace(&mut self.mode, Mode::Idle) {
+            Mode::Idle => None,
+            Mode::Recording(movie) | Mode::Playing { movie, .. } => Some(movie),
+        }
+    }
+
+    /// Call each time the PPU finishes a frame to latch the input for the
+    /// next one. Returns the movie if playback just finished.
+    pub fn end_frame(&mut self, nes: &mut Nes) -> Option<Movie> {
+        match &mut self.mode {
+            Mode::Idle => None,
+            Mode::Recording(movie) => {
+                movie.frames.push(self.live_input);
+                nes.cpu.mem.input.set(&self.live_input.into());
+                None
+            }
+            Mode::Playing { movie, frame } => {
+                *frame += 1;
+
+                match movie.frames.get(*frame) {
+                    Some(&input) => {
+                        nes.cpu.mem.input.set(&input.into());
+                        None
+                    }
+                    None => self.stop(nes),
+                }
+            }
+        }
+    }
+}
+
+impl Default for MoviePlayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headless::tests::{spin_image, spin_rom};
+
+    fn sample_movie() -> Movie {
+        let mut movie = Movie::new(0x1234, MovieStart::SaveState(vec![1, 2, 3]));
+        movie.frames.push(MovieFrame {
+            gamepad1: Buttons::A | Buttons::RIGHT,
+            gamepad2: Buttons::empty(),
+        });
+        movie.frames.push(MovieFrame {
+            gamepad1: Buttons::empty(),
+            gamepad2: Buttons::START,
+        });
+        movie
+    }
+
+    #[test]
+    fn file_round_trip() {
+        let movie = sample_movie();
+        let mut buf = Vec::new();
+        movie.write(&mut buf).unwrap();
+
+        assert_eq!(Movie::read(&mut buf.as_slice()).unwrap(), movie);
+    }
+
+    #[test]
+    fn truncated_file_is_corrupt() {
+        let mut buf = Vec::new();
+        sample_movie().write(&mut buf).unwrap();
+        buf.pop();
+
+        let err = Movie::read(&mut buf.as_slice()).err().unwrap();
+        assert!(matches!(err, MovieError::Corrupt));
+    }
+
+    #[test]
+    fn huge_lengths_are_corrupt() {
+        let mut buf = Vec::new();
+        sample_movie().write(&mut buf).unwrap();
+
+        // The save state's length, right after the start type
+        let mut state_len = buf.clone();
+        state_len[15..19].copy_from_slice(&u32::MAX.to_le_bytes());
+        let err = Movie::read(&mut state_len.as_slice()).err().unwrap();
+        assert!(matches!(err, MovieError::Corrupt));
+
+        // The frame count, right after the 3 byte state
+        let mut frame_count = buf;
+        frame_count[22..26].copy_from_slice(&u32::MAX.to_le_bytes());
+        let err = Movie::read(&mut frame_count.as_slice()).err().unwrap();
+        assert!(matches!(err, MovieError::Corrupt));
+    }
+
+    #[test]
+    fn playback_reproduces_recording() {
+        let mut emulator = crate::Headless::new(spin_rom());
+        let mut player = MoviePlayer::new();
+        emulator.run_frames(3, &mut (), &mut ());
+
+        player.record(&mut emulator.nes, true);
+        for i in 0..5 {
+            let input = MovieFrame {
+                gamepad1: Buttons::from_bits_retain(i),
+                gamepad2: Buttons::empty(),
+            };
+            player.set_input(&mut emulator.nes, input);
+            emulator.run_frames(1, &mut (), &mut ());
+            player.end_frame(&mut emulator.nes);
+        }
+        let recorded_state = emulator.nes.save_state();
+        let movie = player.stop(&mut emulator.nes).unwrap();
+        assert_eq!(movie.frames.len(), 6);
+        assert_eq!(movie.start, MovieStart::PowerOn);
+
+        player.play(&mut emulator.nes, movie).unwrap();
+        for _ in 0..5 {
+            emulator.run_frames(1, &mut (), &mut ());
+            player.end_frame(&mut emulator.nes);
+        }
+
+        assert_eq!(emulator.nes.save_state(), recorded_state);
+        assert!(player.is_playing());
+        assert_eq!(player.frame(), Some(5));
+    }
+
+    #[test]
+    fn power_on_movie_ignores_save_data() {
+        let mut image = spin_image();
+        image[6] |= 0x02;
+        let rom = crate::Rom::from_path(&mut image.as_slice()).unwrap();
+        let mut emulator = crate::Headless::new(rom);
+        let mut player = MoviePlayer::new();
+        let first_save = vec![0xA5; 0x2000];
+        emulator.nes.load_save_data(&first_save);
+
+        player.record(&mut emulator.nes, true);
+        assert_eq!(player.save_data(&emulator.nes), Some(first_save.as_slice()));
+        emulator.run_frames(2, &mut (), &mut ());
+        player.end_frame(&mut emulator.nes);
+        let recorded_state = emulator.nes.save_state();
+        let movie = player.stop(&mut emulator.nes).unwrap();
+        assert_eq!(emulator.nes.save_data(), Some(first_save.as_slice()));
+
+        let second_save = vec![0x5A; 0x2000];
+        emulator.nes.load_save_data(&second_save);
+        player.play(&mut emulator.nes, movie).unwrap();
+        emulator.run_frames(2, &mut (), &mut ());
+
+        assert_eq!(emulator.nes.save_state(), recorded_state);
+
+        player.stop(&mut emulator.nes);
+        assert_eq!(emulator.nes.save_data(), Some(second_save.as_slice()));
+    }
+}
@@ -0,0 +1,350 @@
+use super::{Movie, MovieError, MovieFrame, MovieStart};
+use crate::input::Buttons;
+use log::warn;
+use std::{convert::TryInto, fmt::Write};
+
+/// FM2 button order, from the leftmost character to the rightmost
+const BUTTON_ORDER: [Buttons; 8] = [
+    Buttons::RIGHT,
+    Buttons::LEFT,
+    Buttons::DOWN,
+    Buttons::UP,
+    Buttons::START,
+    Buttons::SELECT,
+    Buttons::B,
+    Buttons::A,
+];
+const BUTTON_CHARS: &[u8; 8] = b"RLDUTSBA";
+
+const PORT_NONE: u8 = 0;
+const PORT_GAMEPAD: u8 = 1;
+
+const COMMAND_SOFT_RESET: u8 = 1 << 0;
+const COMMAND_HARD_RESET: u8 = 1 << 1;
+
+fn parse_error<S: Into<String>>(line: usize, message: S) -> MovieError {
+    MovieError::Parse {
+        line,
+        message: message.into(),
+    }
+}
+
+/// Parse an FCEUX FM2 movie. A text header of `key value` lines is followed
+/// by one line of input per frame:
+///
+/// ```text
+/// |0|RLDUTSBA|........||
+/// ```
+///
+/// The first field holds commands such as resets, followed by a field per
+/// port. Gamepads are 8 characters, one per button, where `.` or a space
+/// means the button is up.
+///
+/// The `romChecksum` header holds the MD5 of the ROM the movie was recorded
+/// with. If it doesn't match `rom_md5` the movie would desync, so it's
+/// rejected. Movies without a checksum are assumed to belong to `rom_hash`.
+pub fn parse(text: &str, rom_hash: u64, rom_md5: &[u8; 16]) -> Result<Movie, MovieError> {
+    let mut movie = Movie::new(rom_hash, MovieStart::PowerOn);
+    let mut ports = [PORT_GAMEPAD, PORT_GAMEPAD];
+
+    for (i, line) in text.lines().enumerate() {
+        let line_num = i + 1;
+        let line = line.trim_end_matches('\r');
+
+        if line.starts_with('|') {
+            let frame = parse_frame(line, line_num, movie.frames.is_empty(), ports)?;
+            movie.frames.push(frame);
+            continue;
+        }
+
+        let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+        match key {
+            "binary" if value != "0" => {
+                return Err(parse_error(line_num, "binary FM2 input is not supported"))
+            }
+            "fourscore" if value != "0" => {
+                return Err(parse_error(line_num, "four score input is not supported"))
+            }
+            "savestate" => {
+                return Err(parse_error(
+                    line_num,
+                    "movies starting from an FCEUX save state are not supported",
+                ))
+            }
+            "port0" | "port1" => {
+                let port = match value.parse() {
+                    Ok(port @ (PORT_NONE | PORT_GAMEPAD)) => port,
+                    _ => return Err(parse_error(line_num, "only gamepads are supported")),
+                };
+                ports[(key == "port1") as usize] = port;
+            }
+            "romChecksum" => {
+                let checksum = parse_checksum(value)
+                    .ok_or_else(|| parse_error(line_num, "bad ROM checksum"))?;
+                if checksum != *rom_md5 {
+                    return Err(MovieError::RomMismatch);
+                }
+            }
+            "palFlag" if value != "0" => warn!("Playing a PAL movie on an NTSC console"),
+            _ => {}
+        }
+    }
+
+    Ok(movie)
+}
+
+fn parse_frame(
+    line: &str,
+    line_num: usize,
+    first: bool,
+    ports: [u8; 2],
+) -> Result<MovieFrame, MovieError> {
+    let mut fields = line.split('|').skip(1);
+    let mut next_field = || {
+        fields
+            .next()
+            .ok_or_else(|| parse_error(line_num, "missing input field"))
+    };
+
+    let commands = next_field()?
+        .trim()
+        .parse::<u8>()
+        .map_err(|_| parse_error(line_num, "bad command field"))?;
+
+    // Every movie starts from power on anyway, so a hard reset on the first
+    // frame is a no-op
+    let allowed = if first { COMMAND_HARD_RESET } else { 0 };
+    if commands & !allowed != 0 {
+        let message = if commands & (COMMAND_SOFT_RESET | COMMAND_HARD_RESET) != 0 {
+            "resets during a movie are not supported"
+        } else {
+            "unsupported command"
+        };
+
+        return Err(parse_error(line_num, message));
+    }
+
+    let gamepad1 = parse_gamepad(next_field()?, ports[0], line_num)?;
+    let gamepad2 = parse_gamepad(next_field()?, ports[1], line_num)?;
+
+    Ok(MovieFrame { gamepad1, gamepad2 })
+}
+
+fn parse_gamepad(field: &str, port: u8, line_num: usize) -> Result<Buttons, MovieError> {
+    if port == PORT_NONE {
+        return Ok(Buttons::empty());
+    }
+
+    if field.len() != BUTTON_ORDER.len() {
+        return Err(parse_error(line_num, "gamepad input must be 8 characters"));
+    }
+
+    let buttons = field
+        .bytes()
+        .zip(BUTTON_ORDER.iter())
+        .filter(|(c, _)| *c != b'.' && *c != b' ')
+        .fold(Buttons::empty(), |acc, (_, &button)| acc | button);
+
+    Ok(buttons)
+}
+
+/// FCEUX writes checksums as `base64:` followed by the encoded MD5, but also
+/// reads them as plain hex
+fn parse_checksum(value: &str) -> Option<[u8; 16]> {
+    let bytes = match value.strip_prefix("base64:") {
+        Some(encoded) => decode_base64(encoded)?,
+        None => decode_hex(value.strip_prefix("0x").unwrap_or(value))?,
+    };
+
+    bytes.try_into().ok()
+}
+
+fn decode_base64(text: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let (mut acc, mut bits) = (0u32, 0);
+
+    for c in text.trim_end_matches('=').bytes() {
+        let val = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        };
+
+        acc = (acc << 6) | val as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((acc >> bits) as u8);
+        }
+    }
+
+    Some(bytes)
+}
+
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    // An odd length leaves half a byte at the end, which get() rejects
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Write a movie as FM2, for playback in FCEUX. Only movies that start from
+/// power on can be written, since FM2 can't hold this emulator's states.
+pub fn write(movie: &Movie, rom_name: &str) -> Result<String, MovieError> {
+    if movie.start != MovieStart::PowerOn {
+        return Err(MovieError::Unsupported(
+            "FM2 movies must start from power on",
+        ));
+    }
+
+    let mut out = String::new();
+    out.push_str("version 3\n");
+    out.push_str("emuVersion 22020\n");
+    out.push_str("rerecordCount 0\n");
+    out.push_str("palFlag 0\n");
+    let _ = writeln!(out, "romFilename {}", rom_name);
+    out.push_str("fourscore 0\n");
+    out.push_str("microphone 0\n");
+    let _ = writeln!(out, "port0 {}", PORT_GAMEPAD);
+    let _ = writeln!(out, "port1 {}", PORT_GAMEPAD);
+    let _ = writeln!(out, "port2 {}", PORT_NONE);
+    out.push_str("FDS 0\n");
+    out.push_str("NewPPU 0\n");
+
+    for frame in &movie.frames {
+        let _ = writeln!(
+            out,
+            "|0|{}|{}||",
+            format_gamepad(frame.gamepad1),
+            format_gamepad(frame.gamepad2)
+        );
+    }
+
+    Ok(out)
+}
+
+fn format_gamepad(buttons: Buttons) -> String {
+    BUTTON_ORDER
+        .iter()
+        .zip(BUTTON_CHARS.iter())
+        .map(|(&button, &c)| {
+            if buttons.contains(button) {
+                c as char
+            } else {
+                '.'
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MOVIE: &str = "version 3
+emuVersion 22020
+rerecordCount 12
+palFlag 0
+romFilename smb
+romChecksum base64:jjYwGG411HcjG/j9UOVM3Q==
+guid 0A5F2F5C-1B5E-4B4B-9D0C-4B2A5C1F0E1D
+fourscore 0
+microphone 0
+port0 1
+port1 1
+port2 0
+FDS 0
+NewPPU 0
+comment author QA
+|2|........|........||
+|0|....T...|........||
+|0|R......A|.L....B.||
+";
+
+    /// The MD5 in MOVIE's romChecksum
+    const SMB_MD5: [u8; 16] = [
+        0x8E, 0x36, 0x30, 0x18, 0x6E, 0x35, 0xD4, 0x77, 0x23, 0x1B, 0xF8, 0xFD, 0x50, 0xE5, 0x4C,
+        0xDD,
+    ];
+
+    #[test]
+    fn parse_frames() {
+        let movie = parse(MOVIE, 7, &SMB_MD5).unwrap();
+
+        assert_eq!(movie.rom_hash, 7);
+        assert_eq!(movie.start, MovieStart::PowerOn);
+        assert_eq!(movie.frames.len(), 3);
+        assert_eq!(movie.frames[0], MovieFrame::default());
+        assert_eq!(movie.frames[1].gamepad1, Buttons::START);
+        assert_eq!(movie.frames[2].gamepad1, Buttons::RIGHT | Buttons::A);
+        assert_eq!(movie.frames[2].gamepad2, Buttons::LEFT | Buttons::B);
+    }
+
+    #[test]
+    fn write_then_parse() {
+        let movie = parse(MOVIE, 7, &SMB_MD5).unwrap();
+        let text = write(&movie, "smb").unwrap();
+
+        assert!(text.contains("|0|R......A|.L....B.||\n"));
+        assert_eq!(parse(&text, 7, &SMB_MD5).unwrap(), movie);
+    }
+
+    #[test]
+    fn other_rom_is_rejected() {
+        let err = parse(MOVIE, 7, &[0; 16]).err().unwrap();
+
+        assert!(matches!(err, MovieError::RomMismatch));
+    }
+
+    #[test]
+    fn hex_rom_checksum() {
+        let text = "romChecksum 8e3630186e35d477231bf8fd50e54cdd\n";
+
+        assert!(parse(text, 0, &SMB_MD5).is_ok());
+        assert!(parse(text, 0, &[0; 16]).is_err());
+    }
+
+    #[test]
+    fn bad_rom_checksum() {
+        let err = parse("romChecksum base64:!!\n", 0, &SMB_MD5).err().unwrap();
+
+        assert!(matches!(err, MovieError::Parse { line: 1, .. }));
+    }
+
+    #[test]
+    fn unplugged_port_is_ignored() {
+        let text = "port1 0\n|0|........|||\n";
+        let movie = parse(text, 0, &SMB_MD5).unwrap();
+
+        assert_eq!(movie.frames[0].gamepad2, Buttons::empty());
+    }
+
+    #[test]
+    fn reset_mid_movie_is_rejected() {
+        let text = "|0|........|........||\n|1|........|........||\n";
+        let err = parse(text, 0, &SMB_MD5).err().unwrap();
+
+        assert!(matches!(err, MovieError::Parse { line: 2, .. }));
+    }
+
+    #[test]
+    fn zapper_is_rejected() {
+        let err = parse("port1 2\n", 0, &SMB_MD5).err().unwrap();
+
+        assert!(matches!(err, MovieError::Parse { line: 1, .. }));
+    }
+
+    #[test]
+    fn state_movies_cannot_be_written() {
+        let movie = Movie::new(0, MovieStart::SaveState(vec![]));
+
+        assert!(matches!(
+            write(&movie, "smb"),
+            Err(MovieError::Unsupported(_))
+        ));
+    }
+}
@@ -1,7 +1,16 @@
 use crate::{
-    audio::Apu, cpu::Cpu, cpubus::CpuBus, frame_buffer::Frame, input::Input, mapper::create_mapper,
-    ppu::Ppu, ram::Ram, rom::Rom,
+    audio::Apu,
+    cpubus::CpuBus,
+    frame_buffer::Frame,
+    input::Input,
+    mapper::create_mapper,
+    ppu::Ppu,
+    ram::Ram,
+    rom::Rom,
+    state::{fnv1a, Snapshot, StateError, StateReader, StateWriter},
 };
+use mos6502::{Cpu, Mem, Variant};
+use std::{fs, path::Path};
 
 const WRAM_BYTE_SIZE: usize = 0x0800;
 
@@ -11,46 +20,147 @@ pub struct StepResult {
 
 pub struct Nes {
     pub cpu: Cpu<CpuBus>,
+    battery: bool,
+    rom_hash: u64,
+    rom_md5: [u8; 16],
+    power_on: Vec<u8>,
 }
 
 impl Nes {
     pub fn with_rom(rom: Rom) -> Self {
-        let (chr, prg) = create_mapper(rom);
+        let battery = rom.header.has_battery_save();
+        let rom_hash = fnv1a(&[rom.prg.as_slice(), rom.chr.as_slice()].concat());
+        let mut md5 = md5::Context::new();
+        md5.consume(&rom.prg);
+        md5.consume(&rom.chr);
+        let rom_md5 = md5.compute().0;
+        let mapper = create_mapper(rom);
         let frame_buffer = Frame::new();
-        let ppu = Ppu::new(chr, frame_buffer);
+        let ppu = Ppu::new(frame_buffer);
         let apu = Apu::new();
         let ram = Ram::new(WRAM_BYTE_SIZE);
         let input = Input::default();
-        let cpu_bus = CpuBus::new(ppu, input, apu, prg, ram);
+        let cpu_bus = CpuBus::new(ppu, input, apu, mapper, ram);
 
-        let mut cpu = Cpu::new(cpu_bus);
+        let mut cpu = Cpu::new(Variant::Ricoh2A03, cpu_bus);
         cpu.reset();
 
-        Self { cpu }
+        let mut nes = Self {
+            cpu,
+            battery,
+            rom_hash,
+            rom_md5,
+            power_on: Vec::new(),
+        };
+
+        nes.power_on = nes.save_state();
+        nes
     }
 
-    /// Progress emulation by 1 CPU tick
-    pub fn step(&mut self) -> StepResult {
-        self.cpu.step();
-        self.cpu.mem.apu.step();
-        self.cpu.mem.input.step();
+    /// A hash of the loaded ROM, used to tie saved data to the game it came
+    /// from
+    pub fn rom_hash(&self) -> u64 {
+        self.rom_hash
+    }
+
+    /// The MD5 of the loaded ROM's PRG and CHR data, which is how FCEUX
+    /// identifies games
+    pub fn rom_md5(&self) -> &[u8; 16] {
+        &self.rom_md5
+    }
+
+    /// Put the whole machine back the way it was when the ROM was loaded,
+    /// including cartridge RAM. Battery-backed RAM is cleared too, so it's up
+    /// to the caller to keep hold of save data it wants to keep.
+    pub fn power_cycle(&mut self) {
+        let mut r = StateReader::new(&self.power_on, self.rom_hash)
+            .expect("power on state is always valid");
+        self.cpu
+            .load(&mut r)
+            .expect("power on state is always valid");
+    }
+
+    /// Press the reset button. The CPU starts over from the reset vector and
+    /// the APU is silenced, but RAM and the cartridge keep their contents.
+    pub fn reset(&mut self) {
+        self.cpu.mem.apu.storeb(0x4015, 0);
+        self.cpu.reset();
+    }
+
+    /// The contents of the cartridge's battery-backed PRG RAM, or None if the
+    /// cartridge has no battery
+    pub fn save_data(&self) -> Option<&[u8]> {
+        if self.battery {
+            Some(self.cpu.mem.mapper.prg_ram())
+        } else {
+            None
+        }
+    }
+
+    /// Restore battery-backed PRG RAM, e.g. from a .sav file. If the data is
+    /// a different size than the cartridge's RAM, only the overlapping bytes
+    /// are copied.
+    pub fn load_save_data(&mut self, data: &[u8]) {
+        if !self.battery {
+            return;
+        }
 
-        let mut new_frame = false;
+        let ram = self.cpu.mem.mapper.prg_ram_mut();
+        let len = ram.len().min(data.len());
+        ram[..len].copy_from_slice(&data[..len]);
+    }
+
+    /// Capture the state of the whole machine. The state can only be loaded
+    /// back into an emulator running the same ROM.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut w = StateWriter::new(self.rom_hash);
+        self.cpu.save(&mut w);
+        w.into_inner()
+    }
 
-        for _ in 0..3 {
-            let result = self.cpu.mem.ppu.step();
+    /// Restore a state captured by [Nes::save_state]. If the state can't be
+    /// loaded the machine is left as it was.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let backup = self.save_state();
 
-            if result.vblank_nmi {
-                self.cpu.nmi();
-            } else if result.scanline_irq {
-                self.cpu.irq();
-            }
+        let result = StateReader::new(data, self.rom_hash).and_then(|mut r| {
+            self.cpu.load(&mut r)?;
+            r.finish()
+        });
 
-            if result.new_frame {
-                new_frame = true;
-            }
+        if result.is_err() {
+            let mut r = StateReader::new(&backup, self.rom_hash)?;
+            self.cpu.load(&mut r)?;
         }
 
-        StepResult { new_frame }
+        result
+    }
+
+    pub fn save_state_file<P: AsRef<Path>>(&self, path: P) -> Result<(), StateError> {
+        fs::write(path, self.save_state())?;
+        Ok(())
+    }
+
+    pub fn load_state_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), StateError> {
+        let data = fs::read(path)?;
+        self.load_state(&data)
+    }
+
+    /// Progress emulation by one CPU instruction, or by servicing one pending
+    /// interrupt. The rest of the machine runs alongside the CPU, one cycle
+    /// per bus access.
+    pub fn step(&mut self) -> StepResult {
+        self.cpu.mem.samples.clear();
+        self.cpu.mem.new_frame = false;
+        self.cpu.step();
+
+        StepResult {
+            new_frame: self.cpu.mem.new_frame,
+        }
+    }
+
+    /// The audio samples produced by the last step, one per CPU cycle
+    pub fn samples(&self) -> &[f32] {
+        &self.cpu.mem.samples
     }
 }
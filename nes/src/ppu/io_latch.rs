@@ -0,0 +1,79 @@
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
+/// How many frames a bit of the latch holds its value once nothing drives it.
+/// On real hardware the charge leaks away after roughly 600ms.
+const DECAY_FRAMES: u64 = 36;
+
+/// The PPU's I/O latch, which sits on the data bus between the CPU and the
+/// PPU's registers. Reading a write-only register, or the unused bits of a
+/// readable one, returns whatever was last left in the latch. Each bit decays
+/// to 0 unless it is driven again.
+#[derive(Default)]
+pub struct IoLatch {
+    val: u8,
+    refreshed: [u64; 8],
+}
+
+impl IoLatch {
+    /// Drive the bits selected by `mask` to the matching bits of `val`
+    pub fn set(&mut self, val: u8, mask: u8, frame: u64) {
+        self.val = self.val & !mask | val & mask;
+
+        for (bit, refreshed) in self.refreshed.iter_mut().enumerate() {
+            if mask & (1 << bit) != 0 {
+                *refreshed = frame;
+            }
+        }
+    }
+
+    /// The value of the latch as of the given frame
+    pub fn get(&self, frame: u64) -> u8 {
+        self.refreshed
+            .iter()
+            .enumerate()
+            .filter(|(_, &refreshed)| frame.saturating_sub(refreshed) < DECAY_FRAMES)
+            .fold(0, |acc, (bit, _)| acc | self.val & (1 << bit))
+    }
+}
+
+impl Snapshot for IoLatch {
+    fn save(&self, w: &mut StateWriter) {
+        w.u8(self.val);
+        for refreshed in self.refreshed.iter() {
+            w.u64(*refreshed);
+        }
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.val = r.u8()?;
+        for refreshed in self.refreshed.iter_mut() {
+            *refreshed = r.u64()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn holds_driven_bits() {
+        let mut latch = IoLatch::default();
+        latch.set(0xFF, 0xFF, 0);
+        latch.set(0x00, 0x0F, 0);
+
+        assert_eq!(latch.get(0), 0xF0);
+    }
+
+    #[test]
+    fn bits_decay_unless_refreshed() {
+        let mut latch = IoLatch::default();
+        latch.set(0xFF, 0xFF, 0);
+        latch.set(0xFF, 0x0F, 30);
+
+        assert_eq!(latch.get(35), 0xFF);
+        assert_eq!(latch.get(40), 0x0F);
+        assert_eq!(latch.get(70), 0x00);
+    }
+}
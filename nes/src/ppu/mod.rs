@@ -1,3 +1,4 @@
+mod io_latch;
 mod oam;
 mod registers;
 mod rgb;
@@ -6,9 +7,10 @@ mod sprite;
 mod vram;
 
 use crate::frame_buffer::Frame;
-use crate::mapper::ChrMem;
-use crate::mem::Mem;
-use log::warn;
+use crate::mapper::Mapper;
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+use io_latch::IoLatch;
+use mos6502::Mem;
 use oam::Oam;
 use registers::Vtwx;
 pub use registers::{PpuControl, PpuMask, PpuStatus};
@@ -20,7 +22,6 @@ use vram::Vram;
 #[derive(Default)]
 pub struct PpuResult {
     pub new_frame: bool,
-    pub scanline_irq: bool,
     pub vblank_nmi: bool,
 }
 
@@ -171,6 +172,7 @@ pub struct Ppu {
 
     pos: PpuPosition,
     ppu_data_buffer: u8,
+    io_latch: IoLatch,
     vtwx: Vtwx,
     shifter: PatternShifter,
     sprite_shifters: [SpriteShift; 8],
@@ -178,7 +180,7 @@ pub struct Ppu {
 }
 
 impl Ppu {
-    pub fn new(mapper: ChrMem, frame_buffer: Frame) -> Self {
+    pub fn new(frame_buffer: Frame) -> Self {
         Self {
             pos: PpuPosition::default(),
 
@@ -186,8 +188,9 @@ impl Ppu {
             ppumask: PpuMask::default(),
             ppustatus: PpuStatus::default(),
             ppu_data_buffer: 0,
+            io_latch: IoLatch::default(),
 
-            vram: Vram::new(mapper),
+            vram: Vram::new(),
             oam: Oam::new(),
             vtwx: Vtwx::new(),
             shifter: PatternShifter::default(),
@@ -224,7 +227,7 @@ impl Ppu {
         self.vtwx.fine_y() as u8
     }
 
-    pub fn step(&mut self) -> PpuResult {
+    pub fn step(&mut self, mapper: &mut dyn Mapper) -> PpuResult {
         let mut result = PpuResult::default();
 
         if self.immediate_nmi {
@@ -275,18 +278,18 @@ impl Ppu {
 
             // background fetches
             if nt_cycle(px) {
-                let nt_byte = self.fetch_nametable();
+                let nt_byte = self.fetch_nametable(mapper);
                 self.shifter.store_nametable(nt_byte);
             } else if garbage_nt_cycle(px) {
-                self.fetch_nametable();
+                self.fetch_nametable(mapper);
             } else if at_cycle(px) {
-                let at_byte = self.fetch_attribute_table();
+                let at_byte = self.fetch_attribute_table(mapper);
                 self.shifter.store_attribute(at_byte);
             } else if pat_lo_cycle(px) {
-                let lo = self.fetch_bg_lo();
+                let lo = self.fetch_bg_lo(mapper);
                 self.shifter.load_pattern_lo(lo);
             } else if pat_hi_cycle(px) {
-                let hi = self.fetch_bg_hi();
+                let hi = self.fetch_bg_hi(mapper);
                 self.shifter.load_pattern_hi(hi);
             }
 
@@ -308,22 +311,23 @@ impl Ppu {
             if is_render_line && px == 1 {
                 self.oam.reset_oam2();
             } else if is_render_line && px > 64 && px <= 256 {
-                let overflow = self.oam.sprite_eval(line);
+                let height = self.ppuctrl.sprite_size() as u16;
+                let overflow = self.oam.sprite_eval(line, height);
                 if overflow {
                     self.ppustatus.set_sprite_overflow(true);
                 }
             } else if sprite_nt_cycle(px) {
-                self.fetch_nametable();
+                self.fetch_nametable(mapper);
             } else if sprite_at_cycle(px) {
-                self.fetch_attribute_table();
+                self.fetch_attribute_table(mapper);
             } else if sprite_lo_cycle(px) {
-                let lo = self.fetch_sprite_lo(line, px);
+                let lo = self.fetch_sprite_lo(mapper, line, px);
                 let sprite_idx = (px / 8 - 32) as usize;
                 let is_dummy_read = self.oam.sprite_y(sprite_idx) as u16 > 0xEE;
                 let lo = if is_dummy_read { 0x00 } else { lo };
                 self.sprite_shifters[sprite_idx].set_pattern_lo(lo);
             } else if sprite_hi_cycle(px) {
-                let hi = self.fetch_sprite_hi(line, px);
+                let hi = self.fetch_sprite_hi(mapper, line, px);
                 let sprite_idx = (px / 8 - 32) as usize;
                 let is_dummy_read = self.oam.sprite_y(sprite_idx) as u16 > 0xEE;
                 let hi = if is_dummy_read { 0x00 } else { hi };
@@ -359,15 +363,15 @@ impl Ppu {
         self.pos.cycle = ppu_cycle;
     }
 
-    fn fetch_nametable(&mut self) -> u8 {
+    fn fetch_nametable(&mut self, mapper: &mut dyn Mapper) -> u8 {
         let tile_addr = self.vtwx.tile_addr();
-        self.vram.loadb(tile_addr)
+        self.vram.loadb(mapper, tile_addr)
     }
 
-    fn fetch_attribute_table(&mut self) -> u8 {
+    fn fetch_attribute_table(&mut self, mapper: &mut dyn Mapper) -> u8 {
         let attr_addr = self.vtwx.attr_addr();
         let quadrant = self.vtwx.attr_quadrant();
-        let attr_byte = self.vram.loadb(attr_addr);
+        let attr_byte = self.vram.loadb(mapper, attr_addr);
 
         match quadrant {
             0x00 => attr_byte & 0x03,        // top left
@@ -378,26 +382,26 @@ impl Ppu {
         }
     }
 
-    fn fetch_bg_lo(&mut self) -> u8 {
+    fn fetch_bg_lo(&mut self, mapper: &mut dyn Mapper) -> u8 {
         let addr = self.pattern_addr(0);
-        self.vram.loadb(addr)
+        self.vram.loadb(mapper, addr)
     }
 
-    fn fetch_bg_hi(&mut self) -> u8 {
+    fn fetch_bg_hi(&mut self, mapper: &mut dyn Mapper) -> u8 {
         let addr = self.pattern_addr(8);
-        self.vram.loadb(addr)
+        self.vram.loadb(mapper, addr)
     }
 
-    fn fetch_sprite_lo(&mut self, line: u16, px: u16) -> u8 {
+    fn fetch_sprite_lo(&mut self, mapper: &mut dyn Mapper, line: u16, px: u16) -> u8 {
         let sprite_idx = px / 8 - 32;
         let addr = self.sprite_pattern_addr(sprite_idx as usize, line, 0);
-        self.vram.loadb(addr)
+        self.vram.loadb(mapper, addr)
     }
 
-    fn fetch_sprite_hi(&mut self, line: u16, px: u16) -> u8 {
+    fn fetch_sprite_hi(&mut self, mapper: &mut dyn Mapper, line: u16, px: u16) -> u8 {
         let sprite_idx = px / 8 - 32;
         let addr = self.sprite_pattern_addr(sprite_idx as usize, line, 8);
-        self.vram.loadb(addr)
+        self.vram.loadb(mapper, addr)
     }
 
     fn pattern_addr(&self, bit_plane: u16) -> u16 {
@@ -412,20 +416,31 @@ impl Ppu {
     fn sprite_pattern_addr(&self, sprite_idx: usize, line: u16, bit_plane: u16) -> u16 {
         let line = if line == 261 { 0 } else { line };
         let sprite_y = self.oam.sprite_y(sprite_idx) as u16;
-        let pattern_table_index = (self.oam.sprite_addr(sprite_idx) as u16) << 4;
+        let tile = self.oam.sprite_addr(sprite_idx) as u16;
         let mirror_y = bit!(self.oam.sprite_attr(sprite_idx), 7);
+        let height = self.ppuctrl.sprite_size() as u16;
 
-        let fine_y = if sprite_y > line {
+        let row = if sprite_y > line {
             0
         } else if mirror_y {
             // TODO: should this be saturating? Does it matter?
-            7u16.saturating_sub(line - sprite_y)
+            (height - 1).saturating_sub(line - sprite_y)
         } else {
             line - sprite_y
         };
 
-        let pattern_table = self.ppuctrl.sprite_pattern_table_address();
-        pattern_table | pattern_table_index | bit_plane | fine_y
+        if height == 16 {
+            // 8x16 sprites take their pattern table from bit 0 of the tile
+            // index, and the bottom half is drawn from the following tile
+            let pattern_table = (tile & 0x01) << 12;
+            let pattern_table_index = ((tile & 0xFE) + row / 8) << 4;
+            let fine_y = row % 8;
+            pattern_table | pattern_table_index | bit_plane | fine_y
+        } else {
+            let pattern_table = self.ppuctrl.sprite_pattern_table_address();
+            let pattern_table_index = tile << 4;
+            pattern_table | pattern_table_index | bit_plane | row
+        }
     }
 
     fn sprite_pixel(&mut self, px: u16) -> Option<(&'static Rgb, SpritePriority, usize)> {
@@ -454,7 +469,7 @@ impl Ppu {
                     let priority = shifter.priority();
                     let palette_addr = 0x3F10 | (attr << 2) as u16 | pattern as u16;
 
-                    let color = self.vram.loadb(palette_addr);
+                    let color = self.vram.peek_palette(palette_addr);
                     let color = Rgb::from_byte(color);
 
                     ret = Some((color, priority, idx));
@@ -478,14 +493,14 @@ impl Ppu {
         } else {
             let attr = self.shifter.attr_val(fine_x);
             let palette_addr = 0x3F00 | (attr << 2) as u16 | pattern as u16;
-            let color = self.vram.loadb(palette_addr);
+            let color = self.vram.peek_palette(palette_addr);
 
             Some(Rgb::from_byte(color))
         }
     }
 
     fn global_bg(&mut self) -> &'static Rgb {
-        let color = self.vram.loadb(0x3F00);
+        let color = self.vram.peek_palette(0x3F00);
         Rgb::from_byte(color)
     }
 
@@ -526,12 +541,16 @@ impl Ppu {
     }
 }
 
-impl Mem for Ppu {
-    fn peekb(&self, addr: u16) -> u8 {
+impl Ppu {
+    /// For debugging only - show the value of a PPU register ($2000-$3FFF)
+    /// without any of the side effects of reading it
+    pub fn peek_register(&self, addr: u16) -> u8 {
+        let latch = self.io_latch.get(self.pos.frame);
+
         match addr & 0x07 {
             0x00 => *self.ppuctrl,
             0x01 => *self.ppumask,
-            0x02 => self.ppustatus.get(),
+            0x02 => self.ppustatus.get() | latch & 0x1F,
             0x03 => self.oam.addr() as u8,
             0x04 => self.oam.peekb(self.oam.addr()),
             0x05 => 0,
@@ -541,37 +560,26 @@ impl Mem for Ppu {
         }
     }
 
-    fn loadb(&mut self, addr: u16) -> u8 {
+    /// Read a PPU register ($2000-$3FFF) from the CPU
+    ///
+    /// Write-only registers, and the bits of a read that the PPU doesn't
+    /// drive, return the decaying value held in the I/O latch.
+    pub fn load_register(&mut self, mapper: &mut dyn Mapper, addr: u16) -> u8 {
+        let frame = self.pos.frame;
+
         match addr & 0x07 {
-            0x00 => {
-                warn!("Read from PPUCTRL");
-                0
-            }
-            0x01 => {
-                warn!("Read from PPUMASK");
-                0
-            }
             0x02 => {
-                let ret = self.ppustatus.get();
+                let ret = self.ppustatus.get() | self.io_latch.get(frame) & 0x1F;
                 self.ppustatus.set_vblank_started(false);
                 self.vtwx.reset_latch();
+                self.io_latch.set(ret, 0xE0, frame);
                 ret
             }
-            0x03 => {
-                warn!("Read from OAMADDR");
-                0
-            }
             0x04 => {
                 let addr = self.oam.addr();
-                self.oam.loadb(addr)
-            }
-            0x05 => {
-                warn!("Read from PPUSCROLL");
-                0
-            }
-            0x06 => {
-                warn!("Read from PPUADDR");
-                0
+                let ret = self.oam.loadb(addr);
+                self.io_latch.set(ret, 0xFF, frame);
+                ret
             }
             0x07 => {
                 let addr = self.vtwx.addr();
@@ -579,22 +587,28 @@ impl Mem for Ppu {
 
                 if addr <= 0x3EFF {
                     let ret = self.ppu_data_buffer;
-                    self.ppu_data_buffer = self.vram.loadb(addr);
+                    self.ppu_data_buffer = self.vram.loadb(mapper, addr);
                     self.vtwx.increment_addr(increment);
+                    self.io_latch.set(ret, 0xFF, frame);
 
                     ret
                 } else {
-                    self.ppu_data_buffer = self.vram.loadb(addr);
+                    // Palette entries are only 6 bits wide; the top two bits
+                    // come from the latch
+                    self.ppu_data_buffer = self.vram.loadb(mapper, addr);
                     self.vtwx.increment_addr(increment);
-                    self.ppu_data_buffer
+                    let ret = self.ppu_data_buffer & 0x3F | self.io_latch.get(frame) & 0xC0;
+                    self.io_latch.set(ret, 0x3F, frame);
+                    ret
                 }
             }
-            _ => unreachable!(),
+            _ => self.io_latch.get(frame),
         }
     }
 
-    fn storeb(&mut self, addr: u16, val: u8) {
-        self.ppustatus.set_last_ppu_write(val);
+    /// Write a PPU register ($2000-$3FFF) from the CPU
+    pub fn store_register(&mut self, mapper: &mut dyn Mapper, addr: u16, val: u8) {
+        self.io_latch.set(val, 0xFF, self.pos.frame);
 
         match addr & 0x07 {
             0x00 => {
@@ -602,10 +616,6 @@ impl Mem for Ppu {
                     return;
                 }
 
-                if bit!(val, 5) {
-                    panic!("Sprite size set to 8x16");
-                }
-
                 if bit!(val, 6) {
                     panic!("PPU secondary mode selected");
                 }
@@ -631,10 +641,58 @@ impl Mem for Ppu {
                 let inc = self.ppuctrl.vram_addr_increment();
                 let addr = self.vtwx.ppu_addr();
 
-                self.vram.storeb(addr, val);
+                self.vram.storeb(mapper, addr, val);
                 self.vtwx.increment_addr(inc);
             }
             _ => unreachable!(),
         }
     }
 }
+
+impl Snapshot for Ppu {
+    fn save(&self, w: &mut StateWriter) {
+        self.ppuctrl.save(w);
+        self.ppumask.save(w);
+        self.ppustatus.save(w);
+        self.vram.save(w);
+        self.oam.save(w);
+
+        w.u64(self.pos.cycle);
+        w.u64(self.pos.frame);
+        w.u16(self.pos.pixel);
+        w.u16(self.pos.scanline);
+
+        w.u8(self.ppu_data_buffer);
+        self.io_latch.save(w);
+        self.vtwx.save(w);
+        self.shifter.save(w);
+        for shifter in self.sprite_shifters.iter() {
+            shifter.save(w);
+        }
+        w.bool(self.immediate_nmi);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.ppuctrl.load(r)?;
+        self.ppumask.load(r)?;
+        self.ppustatus.load(r)?;
+        self.vram.load(r)?;
+        self.oam.load(r)?;
+
+        self.pos.cycle = r.u64()?;
+        self.pos.frame = r.u64()?;
+        self.pos.pixel = r.u16()?;
+        self.pos.scanline = r.u16()?;
+
+        self.ppu_data_buffer = r.u8()?;
+        self.io_latch.load(r)?;
+        self.vtwx.load(r)?;
+        self.shifter.load(r)?;
+        for shifter in self.sprite_shifters.iter_mut() {
+            shifter.load(r)?;
+        }
+        self.immediate_nmi = r.bool()?;
+
+        Ok(())
+    }
+}
@@ -1,4 +1,5 @@
-use crate::mem::Mem;
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+use mos6502::Mem;
 
 #[derive(Debug)]
 enum TickState {
@@ -82,12 +83,14 @@ impl Oam {
         self.n = 0;
     }
 
-    fn in_range(current_scanline: u16, value: u8) -> bool {
+    fn in_range(current_scanline: u16, value: u8, height: u16) -> bool {
         let value = value as u16;
-        current_scanline >= value && current_scanline < value + 8
+        current_scanline >= value && current_scanline < value + height
     }
 
-    pub fn sprite_eval(&mut self, current_scanline: u16) -> bool {
+    /// Run one cycle of sprite evaluation for sprites that are `height`
+    /// pixels tall (8 or 16)
+    pub fn sprite_eval(&mut self, current_scanline: u16, height: u16) -> bool {
         // return true if sprite overflow is detected
         let mut ret = false;
 
@@ -102,7 +105,7 @@ impl Oam {
                     self.tick_state = TickState::IncrementN;
                 }
 
-                let in_range = Oam::in_range(current_scanline, y_pos);
+                let in_range = Oam::in_range(current_scanline, y_pos, height);
                 let addr = self.oam2_index * 4;
                 self.oam2[addr] = y_pos;
 
@@ -155,12 +158,12 @@ impl Oam {
                 }
 
                 // This bookkeeping does not take a cycle
-                self.sprite_eval(current_scanline);
+                self.sprite_eval(current_scanline, height);
             }
             TickState::OverflowDetection(m) => {
                 let addr = self.addr + self.n * 4 + m;
                 let buggy_y_pos = self.oam[addr];
-                if Oam::in_range(current_scanline, buggy_y_pos) {
+                if Oam::in_range(current_scanline, buggy_y_pos, height) {
                     // set sprite overflow
                     ret = true;
                     self.tick_state = TickState::Phase3ExtraRead(1);
@@ -203,3 +206,74 @@ impl Mem for Oam {
         self.oam[addr as usize] = val
     }
 }
+
+impl Snapshot for TickState {
+    fn save(&self, w: &mut StateWriter) {
+        let (tag, val) = match *self {
+            TickState::ReadYPosition => (0, 0),
+            TickState::WriteYPosition(y) => (1, y),
+            TickState::ReadTileIndex => (2, 0),
+            TickState::WriteTileIndex(idx) => (3, idx),
+            TickState::ReadAttributes => (4, 0),
+            TickState::WriteAttributes(attr) => (5, attr),
+            TickState::ReadXPosition => (6, 0),
+            TickState::WriteXPosition(x) => (7, x),
+            TickState::IncrementN => (8, 0),
+            TickState::OverflowDetection(m) => (9, m as u8),
+            TickState::Phase3ExtraRead(m) => (10, m),
+            TickState::BusyLoop => (11, 0),
+        };
+
+        w.u8(tag);
+        w.u8(val);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        let tag = r.u8()?;
+        let val = r.u8()?;
+
+        *self = match tag {
+            0 => TickState::ReadYPosition,
+            1 => TickState::WriteYPosition(val),
+            2 => TickState::ReadTileIndex,
+            3 => TickState::WriteTileIndex(val),
+            4 => TickState::ReadAttributes,
+            5 => TickState::WriteAttributes(val),
+            6 => TickState::ReadXPosition,
+            7 => TickState::WriteXPosition(val),
+            8 => TickState::IncrementN,
+            9 => TickState::OverflowDetection(val as usize),
+            10 => TickState::Phase3ExtraRead(val),
+            11 => TickState::BusyLoop,
+            _ => return Err(StateError::Corrupt),
+        };
+
+        Ok(())
+    }
+}
+
+impl Snapshot for Oam {
+    fn save(&self, w: &mut StateWriter) {
+        w.u8(self.addr as u8);
+        w.bytes(&self.oam);
+        w.bytes(&self.oam2);
+        w.u8(self.oam2_index as u8);
+        w.u8(self.n as u8);
+        self.tick_state.save(w);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.addr = r.u8()? as usize;
+        r.bytes(&mut self.oam)?;
+        r.bytes(&mut self.oam2)?;
+        self.oam2_index = r.u8()? as usize;
+        self.n = r.u8()? as usize;
+
+        // Both are used to index into OAM, so a corrupt state could panic
+        if self.oam2_index > 8 || self.n > 63 {
+            return Err(StateError::Corrupt);
+        }
+
+        self.tick_state.load(r)
+    }
+}
@@ -1,3 +1,4 @@
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
 use log::warn;
 use std::ops::Deref;
 
@@ -144,14 +145,9 @@ impl Deref for PpuMask {
 #[derive(Default, Debug, Copy, Clone)]
 pub struct PpuStatus {
     val: u8,
-    last_write: u8,
 }
 
 impl PpuStatus {
-    pub fn set_last_ppu_write(&mut self, val: u8) {
-        self.last_write = val;
-    }
-
     pub fn set_sprite_overflow(&mut self, val: bool) {
         toggle_bit!(self.val, 5, val);
     }
@@ -177,7 +173,7 @@ impl PpuStatus {
     }
 
     pub fn get(&self) -> u8 {
-        self.val & 0xE0 | self.last_write & 0x1F
+        self.val & 0xE0
     }
 }
 
@@ -344,6 +340,56 @@ impl Vtwx {
     }
 }
 
+impl Snapshot for PpuControl {
+    fn save(&self, w: &mut StateWriter) {
+        w.u8(self.0);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.0 = r.u8()?;
+        Ok(())
+    }
+}
+
+impl Snapshot for PpuMask {
+    fn save(&self, w: &mut StateWriter) {
+        w.u8(self.0);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.0 = r.u8()?;
+        Ok(())
+    }
+}
+
+impl Snapshot for PpuStatus {
+    fn save(&self, w: &mut StateWriter) {
+        w.u8(self.val);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.val = r.u8()?;
+        Ok(())
+    }
+}
+
+impl Snapshot for Vtwx {
+    fn save(&self, w: &mut StateWriter) {
+        w.u16(self.t);
+        w.u16(self.v);
+        w.bool(self.w.0);
+        w.u8(self.x);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.t = r.u16()?;
+        self.v = r.u16()?;
+        self.w.0 = r.bool()?;
+        self.x = r.u8()?;
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1,3 +1,5 @@
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
 #[derive(Default)]
 pub struct PatternShifter {
     nametable_byte: u8,
@@ -64,3 +66,30 @@ impl PatternShifter {
         self.attribute_hi <<= 1;
     }
 }
+
+impl Snapshot for PatternShifter {
+    fn save(&self, w: &mut StateWriter) {
+        w.u8(self.nametable_byte);
+        w.u16(self.attribute_lo);
+        w.u16(self.attribute_hi);
+        w.u8(self.attribute_lo_latch);
+        w.u8(self.attribute_hi_latch);
+        w.u16(self.pattern_lo);
+        w.u16(self.pattern_hi);
+        w.u8(self.pattern_lo_latch);
+        w.u8(self.pattern_hi_latch);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.nametable_byte = r.u8()?;
+        self.attribute_lo = r.u16()?;
+        self.attribute_hi = r.u16()?;
+        self.attribute_lo_latch = r.u8()?;
+        self.attribute_hi_latch = r.u8()?;
+        self.pattern_lo = r.u16()?;
+        self.pattern_hi = r.u16()?;
+        self.pattern_lo_latch = r.u8()?;
+        self.pattern_hi_latch = r.u8()?;
+        Ok(())
+    }
+}
@@ -1,3 +1,5 @@
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
 pub enum SpritePriority {
     AboveBackground,
     BelowBackground,
@@ -74,3 +76,20 @@ impl SpriteShift {
         lo | hi
     }
 }
+
+impl Snapshot for SpriteShift {
+    fn save(&self, w: &mut StateWriter) {
+        w.u8(self.counter);
+        w.u8(self.latch);
+        w.u8(self.shift_lo);
+        w.u8(self.shift_hi);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.counter = r.u8()?;
+        self.latch = r.u8()?;
+        self.shift_lo = r.u8()?;
+        self.shift_hi = r.u8()?;
+        Ok(())
+    }
+}
@@ -1,56 +1,101 @@
-use crate::mapper::ChrMem;
-use crate::mem::Mem;
+use crate::mapper::Mapper;
+use crate::rom::NametableMirror;
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
 
 /// Nametable memory
 ///
-/// Stores the layout of the background
+/// Stores the layout of the background. Pattern tables live on the cartridge,
+/// so every access to PPU address space takes the mapper along with it.
 pub struct Vram {
-    pub mapper: ChrMem,
-    pub nametables: Box<[u8; 0x0800]>, // 2 nametables, 0x400 each
+    // 2 nametables of internal VRAM, 0x400 each, followed by the 2 extra
+    // nametables that four-screen cartridges provide
+    pub nametables: Box<[u8; 0x1000]>,
     pub palette: [u8; 0x20],
 }
 
 impl Vram {
-    pub fn new(mapper: ChrMem) -> Vram {
+    pub fn new() -> Vram {
         Vram {
-            mapper,
-            nametables: Box::new([0; 0x0800]),
+            nametables: Box::new([0; 0x1000]),
             palette: [0; 0x20],
         }
     }
-}
 
-impl Mem for Vram {
-    fn peekb(&self, addr: u16) -> u8 {
+    /// Translate an address in $2000-$3EFF to an offset into the nametables,
+    /// based on the mirroring that the cartridge currently selects
+    fn nametable_addr(mirroring: NametableMirror, addr: u16) -> usize {
+        let addr = addr as usize & 0x0FFF;
+        let table = addr / 0x0400;
+        let offset = addr & 0x03FF;
+
+        let table = match mirroring {
+            NametableMirror::Horizontal => table >> 1,
+            NametableMirror::Vertical => table & 0x01,
+            NametableMirror::SingleScreenLower => 0,
+            NametableMirror::SingleScreenUpper => 1,
+            NametableMirror::FourScreen => table,
+        };
+
+        table * 0x0400 + offset
+    }
+
+    /// Read a color from palette RAM ($3F00-$3FFF)
+    pub fn peek_palette(&self, addr: u16) -> u8 {
+        self.palette[addr as usize & 0x1F]
+    }
+
+    /// For debugging only - show the current value of a byte in PPU address
+    /// space without modifying the system state
+    pub fn peekb(&self, mapper: &dyn Mapper, addr: u16) -> u8 {
         let addr = addr & 0x3FFF;
 
         if addr < 0x2000 {
-            self.mapper.as_ref().peekb(addr)
+            mapper.peek_chr(addr)
         } else if addr < 0x3F00 {
-            self.nametables[addr as usize & 0x07FF]
-        } else if addr < 0x4000 {
-            self.palette[addr as usize & 0x1F]
+            self.nametables[Vram::nametable_addr(mapper.mirroring(), addr)]
         } else {
-            unreachable!()
+            self.peek_palette(addr)
         }
     }
 
-    fn storeb(&mut self, addr: u16, val: u8) {
+    /// Load a byte from PPU address space
+    pub fn loadb(&mut self, mapper: &mut dyn Mapper, addr: u16) -> u8 {
         let addr = addr & 0x3FFF;
 
         if addr < 0x2000 {
-            self.mapper.as_mut().storeb(addr, val);
+            mapper.load_chr(addr)
+        } else {
+            self.peekb(mapper, addr)
+        }
+    }
+
+    /// Store a byte into PPU address space
+    pub fn storeb(&mut self, mapper: &mut dyn Mapper, addr: u16, val: u8) {
+        let addr = addr & 0x3FFF;
+
+        if addr < 0x2000 {
+            mapper.store_chr(addr, val);
         } else if addr < 0x3F00 {
-            let addr = addr & 0x07FF;
-            self.nametables[addr as usize] = val;
-        } else if addr < 0x4000 {
+            let addr = Vram::nametable_addr(mapper.mirroring(), addr);
+            self.nametables[addr] = val;
+        } else {
             let mut addr = addr & 0x1F;
             if addr == 0x10 {
                 addr = 0x00; // Mirror sprite background color into universal background color
             }
             self.palette[addr as usize] = val;
-        } else {
-            unreachable!()
         }
     }
 }
+
+impl Snapshot for Vram {
+    fn save(&self, w: &mut StateWriter) {
+        w.bytes(self.nametables.as_ref());
+        w.bytes(&self.palette);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        r.bytes(self.nametables.as_mut())?;
+        r.bytes(&mut self.palette)
+    }
+}
@@ -1,4 +1,5 @@
-use crate::mem::Mem;
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+use mos6502::Mem;
 
 pub struct Ram(pub Vec<u8>);
 
@@ -17,3 +18,13 @@ impl Mem for Ram {
         self.0[addr as usize] = val
     }
 }
+
+impl Snapshot for Ram {
+    fn save(&self, w: &mut StateWriter) {
+        w.bytes(&self.0);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        r.bytes(&mut self.0)
+    }
+}
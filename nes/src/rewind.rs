@@ -0,0 +1,291 @@
+use crate::{
+    headless::{FrameSink, Headless},
+    Nes,
+};
+use std::collections::VecDeque;
+
+/// Snapshot once per frame by default
+pub const DEFAULT_REWIND_INTERVAL: usize = 1;
+
+/// About 10 seconds of history at the default interval
+pub const DEFAULT_REWIND_CAPACITY: usize = 600;
+
+/// A ring buffer of recent save states, used to step backwards through
+/// emulation.
+///
+/// Only the newest state is kept whole. Each older state is stored as the
+/// difference between it and the state that came after it, which is usually
+/// tiny since little of the machine changes from one frame to the next.
+pub struct Rewind {
+    interval: usize,
+    capacity: usize,
+    frames_since_capture: usize,
+    latest: Option<RewindFrame>,
+    deltas: VecDeque<RewindFrame>,
+}
+
+/// A save state, along with the CPU cycle it was taken on. Everything but
+/// the newest snapshot holds a delta instead of a full state.
+struct RewindFrame {
+    state: Vec<u8>,
+    cycle: u64,
+}
+
+impl Rewind {
+    /// Keep up to `capacity` states, capturing one every `interval` frames
+    pub fn new(interval: usize, capacity: usize) -> Self {
+        Self {
+            interval: interval.max(1),
+            capacity: capacity.max(1),
+            frames_since_capture: 0,
+            latest: None,
+            deltas: VecDeque::new(),
+        }
+    }
+
+    /// The number of states that can be rewound through
+    pub fn len(&self) -> usize {
+        self.deltas.len() + self.latest.is_some() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.latest.is_none()
+    }
+
+    pub fn clear(&mut self) {
+        self.latest = None;
+        self.deltas.clear();
+        self.frames_since_capture = 0;
+    }
+
+    /// Call once each time the PPU finishes a frame. Every `interval` frames
+    /// the state of the machine is captured.
+    pub fn frame(&mut self, nes: &Nes) {
+        self.frames_since_capture += 1;
+
+        if self.frames_since_capture >= self.interval {
+            self.capture(nes);
+        }
+    }
+
+    /// Capture the state of the machine right now
+    pub fn capture(&mut self, nes: &Nes) {
+        let snapshot = RewindFrame {
+            state: nes.save_state(),
+            cycle: nes.cpu.cy,
+        };
+
+        if let Some(previous) = self.latest.replace(snapshot) {
+            let newest = &self.latest.as_ref().unwrap().state;
+            self.deltas.push_back(RewindFrame {
+                state: encode_delta(newest, &previous.state),
+                cycle: previous.cycle,
+            });
+        }
+
+        if self.len() > self.capacity {
+            self.deltas.pop_front();
+        }
+
+        self.frames_since_capture = 0;
+    }
+
+    /// Go back one snapshot and redraw the frame that was on screen at that
+    /// point. Returns false if there isn't enough history to go back.
+    pub fn step_back(&mut self, emulator: &mut Headless, video: &mut dyn FrameSink) -> bool {
+        // If the machine hasn't moved since the newest snapshot, that one is
+        // already on screen
+        let at_latest = self
+            .latest
+            .as_ref()
+            .is_some_and(|s| s.cycle == emulator.nes.cpu.cy);
+        let needed = if at_latest { 3 } else { 2 };
+
+        if self.len() < needed {
+            return false;
+        }
+
+        if at_latest {
+            self.pop();
+        }
+
+        // The states are taken after a frame is finished, so to show the
+        // frame for the target snapshot, run forward from the one before it
+        self.pop();
+        let previous = &self.latest.as_ref().unwrap().state;
+
+        if emulator.nes.load_state(previous).is_err() {
+            self.clear();
+            return false;
+        }
+
+        emulator.run_frames(self.interval, video, &mut ());
+        self.capture(&emulator.nes);
+
+        true
+    }
+
+    /// Throw away the newest snapshot, rebuilding the one before it
+    fn pop(&mut self) {
+        let delta = self.deltas.pop_back();
+
+        match (delta, self.latest.as_mut()) {
+            (Some(delta), Some(latest)) => {
+                latest.state = apply_delta(&latest.state, &delta.state);
+                latest.cycle = delta.cycle;
+            }
+            _ => self.latest = None,
+        }
+    }
+}
+
+impl Default for Rewind {
+    fn default() -> Self {
+        Self::new(DEFAULT_REWIND_INTERVAL, DEFAULT_REWIND_CAPACITY)
+    }
+}
+
+/// Encode `to` relative to `from`. The result is the length of `to` followed
+/// by the XOR of the two states as alternating runs of unchanged bytes (zero
+/// after the XOR) and changed bytes, with each run length stored as a varint.
+fn encode_delta(from: &[u8], to: &[u8]) -> Vec<u8> {
+    let xor = to
+        .iter()
+        .enumerate()
+        .map(|(i, b)| b ^ from.get(i).copied().unwrap_or(0))
+        .collect::<Vec<_>>();
+
+    let mut out = Vec::new();
+    write_varint(&mut out, to.len());
+
+    let mut pos = 0;
+    while pos < xor.len() {
+        let same = xor[pos..].iter().take_while(|&&b| b == 0).count();
+        pos += same;
+
+        // Trailing unchanged bytes don't need to be written down
+        if pos == xor.len() {
+            break;
+        }
+
+        let changed = xor[pos..].iter().take_while(|&&b| b != 0).count();
+        write_varint(&mut out, same);
+        write_varint(&mut out, changed);
+        out.extend_from_slice(&xor[pos..pos + changed]);
+        pos += changed;
+    }
+
+    out
+}
+
+/// Undo [encode_delta], rebuilding `to` from `from`
+fn apply_delta(from: &[u8], delta: &[u8]) -> Vec<u8> {
+    let mut pos = 0;
+    let len = read_varint(delta, &mut pos);
+    let mut out = (0..len)
+        .map(|i| from.get(i).copied().unwrap_or(0))
+        .collect::<Vec<_>>();
+
+    let mut i = 0;
+    while pos < delta.len() {
+        i += read_varint(delta, &mut pos);
+        let changed = read_varint(delta, &mut pos);
+
+        for (dst, src) in out[i..i + changed].iter_mut().zip(&delta[pos..]) {
+            *dst ^= src;
+        }
+
+        i += changed;
+        pos += changed;
+    }
+
+    out
+}
+
+fn write_varint(out: &mut Vec<u8>, mut val: usize) {
+    while val >= 0x80 {
+        out.push((val as u8 & 0x7F) | 0x80);
+        val >>= 7;
+    }
+
+    out.push(val as u8);
+}
+
+fn read_varint(buf: &[u8], pos: &mut usize) -> usize {
+    let mut val = 0;
+    let mut shift = 0;
+
+    loop {
+        let byte = buf[*pos];
+        *pos += 1;
+        val |= ((byte & 0x7F) as usize) << shift;
+        shift += 7;
+
+        if byte & 0x80 == 0 {
+            return val;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headless::tests::spin_rom;
+
+    #[test]
+    fn delta_round_trip() {
+        let from = vec![1, 2, 3, 4, 5, 6, 7, 8];
+        let to = vec![1, 2, 9, 4, 5, 6, 0, 0, 10];
+
+        let delta = encode_delta(&from, &to);
+        assert_eq!(apply_delta(&from, &delta), to);
+
+        let delta = encode_delta(&to, &from);
+        assert_eq!(apply_delta(&to, &delta), from);
+    }
+
+    #[test]
+    fn delta_of_long_unchanged_run_is_small() {
+        let from = vec![0xAA; 1000];
+        let mut to = from.clone();
+        to[500] = 0;
+
+        assert!(encode_delta(&from, &to).len() < 8);
+    }
+
+    #[test]
+    fn capacity_drops_oldest() {
+        let mut emulator = Headless::new(spin_rom());
+        let mut rewind = Rewind::new(1, 3);
+
+        for _ in 0..5 {
+            emulator.run_frames(1, &mut (), &mut ());
+            rewind.frame(&emulator.nes);
+        }
+
+        assert_eq!(rewind.len(), 3);
+    }
+
+    #[test]
+    fn step_back_restores_previous_frames() {
+        let mut emulator = Headless::new(spin_rom());
+        let mut rewind = Rewind::default();
+        let mut states = Vec::new();
+
+        for _ in 0..4 {
+            emulator.run_frames(1, &mut (), &mut ());
+            rewind.frame(&emulator.nes);
+            states.push(emulator.nes.save_state());
+        }
+
+        let mut frames: Vec<crate::Frame> = Vec::new();
+        assert!(rewind.step_back(&mut emulator, &mut frames));
+        assert_eq!(emulator.nes.save_state(), states[2]);
+        assert!(rewind.step_back(&mut emulator, &mut frames));
+        assert_eq!(emulator.nes.save_state(), states[1]);
+        assert_eq!(frames.len(), 2);
+
+        // Drawing the first frame would need the state from before it
+        assert!(!rewind.step_back(&mut emulator, &mut frames));
+    }
+}
@@ -1,4 +1,10 @@
+use crate::{
+    mapper,
+    state::{Snapshot, StateError, StateReader, StateWriter},
+};
+use log::warn;
 use std::{
+    error::Error,
     fmt::Display,
     io::{self, Read},
 };
@@ -6,18 +12,65 @@ use std::{
 /// The size of one bank of PRG ROM: 16KiB (16384b)
 pub const PRG_ROM_BANK_SIZE: usize = 0x4000;
 /// The size of one bank of PRG RAM: 8KiB (8192b)
-pub const _PRG_RAM_BANK_SIZE: usize = 0x2000;
+pub const PRG_RAM_BANK_SIZE: usize = 0x2000;
 /// The size of one bank of PRG ROM: 8KiB (8192b)
 pub const CHR_ROM_BANK_SIZE: usize = 0x2000;
+/// The size of a trainer, which is loaded at $7000-$71FF
+pub const TRAINER_SIZE: usize = 0x0200;
+/// The most PRG or CHR ROM a header may ask for: 64MiB. That's a little more
+/// than the largest bank count NES 2.0 can express, but the exponent form can
+/// describe sizes far bigger than any real cartridge.
+const MAX_ROM_SIZE: usize = 0x0400_0000;
 
 #[derive(Debug)]
 pub enum RomLoadError {
     /// IO Error while reading the ROM image
     IoError(io::Error),
-    /// The ROM image has an invalid format
-    FormatError,
+    /// The file ended before the 16 byte header was complete
+    TruncatedHeader,
+    /// The file does not start with "NES\x1a"
+    BadMagic([u8; 4]),
+    /// The header says there is a trainer, but the file ended first
+    TruncatedTrainer { expected: usize, actual: usize },
+    /// The file ended before all of the PRG ROM in the header was read
+    TruncatedPrg { expected: usize, actual: usize },
+    /// The file ended before all of the CHR ROM in the header was read
+    TruncatedChr { expected: usize, actual: usize },
+    /// The ROM needs a mapper this emulator doesn't implement
+    UnsupportedMapper(u16),
+    /// The header asks for more PRG ROM than any cartridge could have
+    PrgTooLarge(usize),
+    /// The header asks for more CHR ROM than any cartridge could have
+    ChrTooLarge(usize),
+    /// The header says there is no PRG ROM, so there is no code to run
+    NoPrgRom,
+}
+
+impl Display for RomLoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::IoError(err) => write!(f, "unable to read ROM: {}", err),
+            Self::TruncatedHeader => write!(f, "file is too short to contain an iNES header"),
+            Self::BadMagic(magic) => write!(f, "not an iNES file (magic bytes {:02X?})", magic),
+            Self::TruncatedTrainer { expected, actual } => {
+                write!(f, "trainer is truncated ({} of {} bytes)", actual, expected)
+            }
+            Self::TruncatedPrg { expected, actual } => {
+                write!(f, "PRG ROM is truncated ({} of {} bytes)", actual, expected)
+            }
+            Self::TruncatedChr { expected, actual } => {
+                write!(f, "CHR ROM is truncated ({} of {} bytes)", actual, expected)
+            }
+            Self::UnsupportedMapper(mapper) => write!(f, "mapper {} is not supported", mapper),
+            Self::PrgTooLarge(size) => write!(f, "header asks for {} bytes of PRG ROM", size),
+            Self::ChrTooLarge(size) => write!(f, "header asks for {} bytes of CHR ROM", size),
+            Self::NoPrgRom => write!(f, "header says there is no PRG ROM"),
+        }
+    }
 }
 
+impl Error for RomLoadError {}
+
 impl From<io::Error> for RomLoadError {
     fn from(err: io::Error) -> Self {
         RomLoadError::IoError(err)
@@ -30,10 +83,39 @@ pub enum RomRegion {
     Ntsc,
 }
 
+/// How the four logical nametables at $2000-$2FFF map onto physical VRAM
 #[derive(Debug, Eq, PartialEq, Copy, Clone)]
 pub enum NametableMirror {
+    /// $2000 = $2400 and $2800 = $2C00
     Horizontal,
+    /// $2000 = $2800 and $2400 = $2C00
     Vertical,
+    /// All four nametables show the first page of VRAM
+    SingleScreenLower,
+    /// All four nametables show the second page of VRAM
+    SingleScreenUpper,
+    /// Each nametable is backed by its own memory, using 2KiB of extra VRAM
+    /// on the cartridge
+    FourScreen,
+}
+
+impl Snapshot for NametableMirror {
+    fn save(&self, w: &mut StateWriter) {
+        w.u8(*self as u8);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        *self = match r.u8()? {
+            0 => NametableMirror::Horizontal,
+            1 => NametableMirror::Vertical,
+            2 => NametableMirror::SingleScreenLower,
+            3 => NametableMirror::SingleScreenUpper,
+            4 => NametableMirror::FourScreen,
+            _ => return Err(StateError::Corrupt),
+        };
+
+        Ok(())
+    }
 }
 
 #[derive(Debug, Eq, PartialEq, Copy, Clone)]
@@ -49,46 +131,158 @@ impl Display for INesVersion {
     }
 }
 
+/// The CPU/PPU timing the game was designed for
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum TimingMode {
+    /// RP2C02 ("NTSC NES")
+    Ntsc,
+    /// RP2C07 ("Licensed PAL NES")
+    Pal,
+    /// Runs identically on NTSC and PAL consoles
+    MultiRegion,
+    /// UA6538 ("Dendy")
+    Dendy,
+}
+
+/// The kind of console the game was made for
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum ConsoleType {
+    /// Nintendo Entertainment System / Family Computer
+    Nes,
+    /// Nintendo Vs. System, with the given PPU and hardware types
+    VsSystem { ppu: u8, hardware: u8 },
+    /// Nintendo PlayChoice-10
+    Playchoice10,
+    /// One of the NES 2.0 extended console types (clones, famiclones, etc.)
+    Extended(u8),
+}
+
 #[derive(Debug, Clone)]
 pub struct INesHeader {
     /// 'N' 'E' 'S' '\x1a'
     magic: [u8; 4],
-    /// number of 16k units of PRG-ROM
+    /// number of 16k units of PRG-ROM (LSB in NES 2.0)
     prg_rom_size: u8,
-    /// number of 8k units of CHR-ROM
+    /// number of 8k units of CHR-ROM (LSB in NES 2.0)
     chr_rom_size: u8,
     /// MMMMATPA
     ///
     /// * M: Low nibble of mapper number
     /// * A: 0xx0: vertical arrangement/horizontal mirroring (CIRAM A10 = PPU A11)
-    ///      0xx1: horizontal arrangement/vertical mirroring (CIRAM A10 = PPU A10)
-    ///      1xxx: four-screen VRAM
+    ///   0xx1: horizontal arrangement/vertical mirroring (CIRAM A10 = PPU A10)
+    ///   1xxx: four-screen VRAM
     /// * T: ROM contains a trainer
     /// * P: Cartridge has persistent memory
     flags_6: u8,
-    /// MMMMVVPU
+    /// MMMMVVCC
     ///
-    /// * M: High nibble of mapper number
+    /// * M: Middle nibble of mapper number
     /// * V: If 0b10, all following flags are in NES2.0 format
-    /// * P: ROM is for the PlayChoice-10
-    /// * U: ROM is for VS Unisystem
+    /// * C: Console type (0: NES, 1: Vs. System, 2: PlayChoice-10, 3: Extended)
     flags_7: u8,
-    /// number of 8k units of PRG-RAM
-    prg_ram_size: u8,
-    /// RRRRRRRT
+    /// iNES: number of 8k units of PRG-RAM
+    ///
+    /// NES 2.0: SSSSMMMM
+    ///
+    /// * S: Submapper number
+    /// * M: High nibble of mapper number
+    flags_8: u8,
+    /// iNES: RRRRRRRT
     ///
     /// * R: Reserved (= 0)
     /// * T: 0 for NTSC, 1 for PAL
+    ///
+    /// NES 2.0: CCCCPPPP
+    ///
+    /// * C: CHR-ROM size MSB
+    /// * P: PRG-ROM size MSB
     flags_9: u8,
-    _flags_10: u8,
-    /// always zero
-    _zero: [u8; 5],
+    /// NES 2.0: NNNNRRRR
+    ///
+    /// * N: PRG-NVRAM shift count
+    /// * R: PRG-RAM shift count
+    flags_10: u8,
+    /// NES 2.0: NNNNRRRR
+    ///
+    /// * N: CHR-NVRAM shift count
+    /// * R: CHR-RAM shift count
+    flags_11: u8,
+    /// NES 2.0: xxxxxxTT
+    ///
+    /// * T: CPU/PPU timing (0: NTSC, 1: PAL, 2: Multi-region, 3: Dendy)
+    flags_12: u8,
+    /// NES 2.0: Vs. System PPU type (low nibble) and hardware type (high
+    /// nibble), or the extended console type (low nibble)
+    flags_13: u8,
+    /// NES 2.0: xxxxxxRR
+    ///
+    /// * R: Number of miscellaneous ROMs present
+    flags_14: u8,
+    /// NES 2.0: xxDDDDDD
+    ///
+    /// * D: Default expansion device
+    flags_15: u8,
 }
 
 impl INesHeader {
-    /// Returns the mapper ID
-    pub fn mapper(&self) -> u8 {
-        (self.flags_7 * 0xf0) | (self.flags_6 >> 4)
+    pub fn from_bytes(bytes: &[u8; 16]) -> Self {
+        Self {
+            magic: [bytes[0], bytes[1], bytes[2], bytes[3]],
+            prg_rom_size: bytes[4],
+            chr_rom_size: bytes[5],
+            flags_6: bytes[6],
+            flags_7: bytes[7],
+            flags_8: bytes[8],
+            flags_9: bytes[9],
+            flags_10: bytes[10],
+            flags_11: bytes[11],
+            flags_12: bytes[12],
+            flags_13: bytes[13],
+            flags_14: bytes[14],
+            flags_15: bytes[15],
+        }
+    }
+
+    fn is_nes2(&self) -> bool {
+        self.ines_version() == INesVersion::INes2
+    }
+
+    /// Old dumping tools wrote their signature (most famously "DiskDude!")
+    /// over bytes 7-15 of the header. Those headers predate flags 7 entirely,
+    /// so anything in it is garbage and only the low mapper nibble is usable.
+    fn is_archaic(&self) -> bool {
+        match self.flags_7 & 0x0C {
+            0x04 => true,
+            0x00 => [self.flags_12, self.flags_13, self.flags_14, self.flags_15] != [0; 4],
+            _ => false,
+        }
+    }
+
+    /// Returns the mapper ID. NES 2.0 headers extend this to 12 bits.
+    pub fn mapper(&self) -> u16 {
+        let lo = (self.flags_6 >> 4) as u16;
+
+        if self.is_archaic() {
+            return lo;
+        }
+
+        let mid = (self.flags_7 & 0xF0) as u16;
+
+        if self.is_nes2() {
+            let hi = (self.flags_8 & 0x0F) as u16;
+            (hi << 8) | mid | lo
+        } else {
+            mid | lo
+        }
+    }
+
+    /// Returns the NES 2.0 submapper ID, or 0 for iNES headers
+    pub fn submapper(&self) -> u8 {
+        if self.is_nes2() {
+            self.flags_8 >> 4
+        } else {
+            0
+        }
     }
 
     pub fn ines_version(&self) -> INesVersion {
@@ -104,88 +298,433 @@ impl INesHeader {
     }
 
     pub fn mirroring(&self) -> NametableMirror {
-        if self.flags_6 & 1 == 0 {
+        if self.flags_6 & 0x08 != 0 {
+            NametableMirror::FourScreen
+        } else if self.flags_6 & 1 == 0 {
             NametableMirror::Horizontal
         } else {
             NametableMirror::Vertical
         }
     }
 
+    /// The number of bytes of PRG ROM in the image
+    pub fn prg_rom_bytes(&self) -> usize {
+        let msb = if self.is_nes2() {
+            self.flags_9 & 0x0F
+        } else {
+            0
+        };
+        rom_bytes(self.prg_rom_size, msb, PRG_ROM_BANK_SIZE)
+    }
+
+    /// The number of bytes of CHR ROM in the image
+    pub fn chr_rom_bytes(&self) -> usize {
+        let msb = if self.is_nes2() { self.flags_9 >> 4 } else { 0 };
+        rom_bytes(self.chr_rom_size, msb, CHR_ROM_BANK_SIZE)
+    }
+
     pub fn chr_banks(&self) -> usize {
-        self.chr_rom_size.into()
+        self.chr_rom_bytes() / CHR_ROM_BANK_SIZE
     }
 
     pub fn prg_banks(&self) -> usize {
-        self.prg_rom_size.into()
+        self.prg_rom_bytes() / PRG_ROM_BANK_SIZE
     }
 
-    pub fn prg_ram_size(&self) -> usize {
-        self.prg_ram_size.into()
+    /// The number of bytes of volatile PRG RAM on the cartridge
+    pub fn prg_ram_bytes(&self) -> usize {
+        if self.is_nes2() {
+            shift_count_bytes(self.flags_10 & 0x0F)
+        } else if self.has_battery_save() {
+            0
+        } else {
+            self.ines_prg_ram_bytes()
+        }
+    }
+
+    /// The number of bytes of battery-backed PRG RAM on the cartridge
+    pub fn prg_nvram_bytes(&self) -> usize {
+        if self.is_nes2() {
+            shift_count_bytes(self.flags_10 >> 4)
+        } else if self.has_battery_save() {
+            self.ines_prg_ram_bytes()
+        } else {
+            0
+        }
+    }
+
+    /// The number of bytes of volatile CHR RAM on the cartridge. iNES headers
+    /// can't express this, so carts without CHR ROM are assumed to have 8KiB.
+    pub fn chr_ram_bytes(&self) -> usize {
+        if self.is_nes2() {
+            shift_count_bytes(self.flags_11 & 0x0F)
+        } else if self.chr_rom_size == 0 {
+            CHR_ROM_BANK_SIZE
+        } else {
+            0
+        }
+    }
+
+    /// The number of bytes of battery-backed CHR RAM on the cartridge
+    pub fn chr_nvram_bytes(&self) -> usize {
+        if self.is_nes2() {
+            shift_count_bytes(self.flags_11 >> 4)
+        } else {
+            0
+        }
+    }
+
+    /// Older dumps leave the PRG RAM size as zero, which means one 8KiB bank
+    /// for compatibility
+    fn ines_prg_ram_bytes(&self) -> usize {
+        if self.is_archaic() {
+            return PRG_RAM_BANK_SIZE;
+        }
+
+        (self.flags_8 as usize).max(1) * PRG_RAM_BANK_SIZE
     }
 
     pub fn has_battery_save(&self) -> bool {
         self.flags_6 & 0b10 != 0
     }
 
+    pub fn timing(&self) -> TimingMode {
+        if self.is_nes2() {
+            match self.flags_12 & 0x03 {
+                0 => TimingMode::Ntsc,
+                1 => TimingMode::Pal,
+                2 => TimingMode::MultiRegion,
+                3 => TimingMode::Dendy,
+                _ => unreachable!(),
+            }
+        } else if !self.is_archaic() && self.flags_9 & 1 == 1 {
+            TimingMode::Pal
+        } else {
+            TimingMode::Ntsc
+        }
+    }
+
     pub fn region(&self) -> RomRegion {
-        if self.flags_9 & 1 == 1 {
+        if self.timing() == TimingMode::Pal {
             RomRegion::Pal
         } else {
             RomRegion::Ntsc
         }
     }
+
+    pub fn console_type(&self) -> ConsoleType {
+        if self.is_archaic() {
+            return ConsoleType::Nes;
+        }
+
+        match self.flags_7 & 0x03 {
+            0 => ConsoleType::Nes,
+            1 if self.is_nes2() => ConsoleType::VsSystem {
+                ppu: self.flags_13 & 0x0F,
+                hardware: self.flags_13 >> 4,
+            },
+            1 => ConsoleType::VsSystem {
+                ppu: 0,
+                hardware: 0,
+            },
+            2 => ConsoleType::Playchoice10,
+            3 if self.is_nes2() => ConsoleType::Extended(self.flags_13 & 0x0F),
+            // Before NES 2.0, bit 1 alone meant PlayChoice-10
+            3 => ConsoleType::Playchoice10,
+            _ => unreachable!(),
+        }
+    }
+
+    /// The number of miscellaneous ROMs following CHR ROM in a NES 2.0 image
+    pub fn misc_roms(&self) -> usize {
+        if self.is_nes2() {
+            (self.flags_14 & 0x03) as usize
+        } else {
+            0
+        }
+    }
+
+    /// The NES 2.0 default expansion device ID. 1 is the standard NES/Famicom
+    /// controllers, and 0 means unspecified.
+    pub fn default_expansion_device(&self) -> u8 {
+        if self.is_nes2() {
+            self.flags_15 & 0x3F
+        } else {
+            0
+        }
+    }
+}
+
+/// Decode a NES 2.0 ROM size. When the MSB nibble is $F the LSB holds an
+/// exponent and multiplier instead of a bank count.
+fn rom_bytes(lsb: u8, msb: u8, bank_size: usize) -> usize {
+    if msb == 0x0F {
+        let exponent = (lsb >> 2) as u32;
+        let multiplier = ((lsb & 0x03) * 2 + 1) as usize;
+        2usize.saturating_pow(exponent).saturating_mul(multiplier)
+    } else {
+        (((msb as usize) << 8) | lsb as usize) * bank_size
+    }
+}
+
+/// Decode a NES 2.0 RAM size, which is stored as a shift count
+fn shift_count_bytes(shift: u8) -> usize {
+    if shift == 0 {
+        0
+    } else {
+        64 << shift
+    }
 }
 
 /// A ROM image
 pub struct Rom {
     pub header: INesHeader,
+    /// 512 bytes to be copied to $7000 before the game starts
+    pub trainer: Option<Vec<u8>>,
     /// PRG ROM
     pub prg: Vec<u8>,
-    /// PRG RAM
+    /// CHR ROM
     pub chr: Vec<u8>,
 }
 
 impl Rom {
     pub fn from_path(reader: &mut dyn Read) -> Result<Rom, RomLoadError> {
         let mut header = [0u8; 16];
-        read_to_buf(&mut header, reader)?;
-
-        let header = INesHeader {
-            magic: [header[0], header[1], header[2], header[3]],
-            prg_rom_size: header[4],
-            chr_rom_size: header[5],
-            flags_6: header[6],
-            flags_7: header[7],
-            prg_ram_size: header[8],
-            flags_9: header[9],
-            _flags_10: header[10],
-            _zero: [0; 5],
-        };
+        if read_to_buf(&mut header, reader)? < header.len() {
+            Err(RomLoadError::TruncatedHeader)?;
+        }
+
+        let header = INesHeader::from_bytes(&header);
 
         if header.magic != *b"NES\x1a" {
-            Err(RomLoadError::FormatError)?;
+            Err(RomLoadError::BadMagic(header.magic))?;
         }
 
-        let prg_bytes = header.prg_rom_size as usize * PRG_ROM_BANK_SIZE;
-        let mut prg = vec![0u8; prg_bytes];
-        read_to_buf(&mut prg, reader)?;
+        if !mapper::is_supported(header.mapper()) {
+            Err(RomLoadError::UnsupportedMapper(header.mapper()))?;
+        }
 
-        let chr_bytes = header.chr_rom_size as usize * CHR_ROM_BANK_SIZE;
-        let mut chr = vec![0u8; chr_bytes];
-        read_to_buf(&mut chr, reader)?;
+        if header.prg_rom_bytes() == 0 {
+            Err(RomLoadError::NoPrgRom)?;
+        }
 
-        Ok(Rom { header, prg, chr })
+        // Check the sizes before allocating anything, since a corrupt header
+        // can ask for more memory than there is
+        if header.prg_rom_bytes() > MAX_ROM_SIZE {
+            Err(RomLoadError::PrgTooLarge(header.prg_rom_bytes()))?;
+        }
+
+        if header.chr_rom_bytes() > MAX_ROM_SIZE {
+            Err(RomLoadError::ChrTooLarge(header.chr_rom_bytes()))?;
+        }
+
+        let trainer = if header.has_trainer() {
+            let mut trainer = vec![0u8; TRAINER_SIZE];
+            let (expected, actual) = (trainer.len(), read_to_buf(&mut trainer, reader)?);
+            if actual < expected {
+                Err(RomLoadError::TruncatedTrainer { expected, actual })?;
+            }
+            Some(trainer)
+        } else {
+            None
+        };
+
+        let mut prg = vec![0u8; header.prg_rom_bytes()];
+        let (expected, actual) = (prg.len(), read_to_buf(&mut prg, reader)?);
+        if actual < expected {
+            Err(RomLoadError::TruncatedPrg { expected, actual })?;
+        }
+
+        let mut chr = vec![0u8; header.chr_rom_bytes()];
+        let (expected, actual) = (chr.len(), read_to_buf(&mut chr, reader)?);
+        if actual < expected {
+            Err(RomLoadError::TruncatedChr { expected, actual })?;
+        }
+
+        // NES 2.0 images may have miscellaneous ROMs after CHR, which we
+        // don't use yet. Plenty of dumps have junk after CHR too, which is
+        // harmless, so it's skipped over rather than rejected.
+        let extra = io::copy(reader, &mut io::sink())?;
+        if extra > 0 && header.misc_roms() == 0 {
+            warn!("Ignoring {} unexpected bytes after CHR ROM", extra);
+        }
+
+        Ok(Rom {
+            header,
+            trainer,
+            prg,
+            chr,
+        })
     }
 }
 
-fn read_to_buf(buf: &mut [u8], reader: &mut dyn Read) -> io::Result<()> {
+/// Fill as much of the buffer as possible, returning the number of bytes read.
+/// Only comes up short if the reader hits EOF.
+fn read_to_buf(buf: &mut [u8], reader: &mut dyn Read) -> io::Result<usize> {
     let mut total = 0;
     while total < buf.len() {
         let count = reader.read(&mut buf[total..])?;
         if count == 0 {
-            return Err(io::Error::new(io::ErrorKind::Other, "unexpected eof"));
+            break;
         }
         total += count;
     }
-    Ok(())
+    Ok(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(bytes: [u8; 16]) -> INesHeader {
+        INesHeader::from_bytes(&bytes)
+    }
+
+    #[test]
+    fn mapper_numbers_from_real_headers() {
+        #[rustfmt::skip]
+        let table: &[(&str, [u8; 16], u16)] = &[
+            ("Super Mario Bros.", [0x4E, 0x45, 0x53, 0x1A, 0x02, 0x01, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], 0),
+            ("The Legend of Zelda", [0x4E, 0x45, 0x53, 0x1A, 0x08, 0x00, 0x12, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], 1),
+            ("Mega Man", [0x4E, 0x45, 0x53, 0x1A, 0x08, 0x00, 0x21, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], 2),
+            ("Super Mario Bros. 3", [0x4E, 0x45, 0x53, 0x1A, 0x10, 0x10, 0x40, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], 4),
+            ("Dragon Ball Z (Bandai)", [0x4E, 0x45, 0x53, 0x1A, 0x10, 0x10, 0x00, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], 16),
+            ("Super Mario Bros. + Duck Hunt", [0x4E, 0x45, 0x53, 0x1A, 0x04, 0x04, 0x20, 0x40, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], 66),
+            ("Tengen Tetris", [0x4E, 0x45, 0x53, 0x1A, 0x02, 0x04, 0x40, 0x40, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], 68),
+            ("Mega Man (DiskDude!)", [0x4E, 0x45, 0x53, 0x1A, 0x08, 0x00, 0x21, 0x44, 0x69, 0x73, 0x6B, 0x44, 0x75, 0x64, 0x65, 0x21], 2),
+            ("Super Mario Bros. (junk in 12-15)", [0x4E, 0x45, 0x53, 0x1A, 0x02, 0x01, 0x01, 0xA0, 0x00, 0x00, 0x00, 0x00, 0x4E, 0x69, 0x30, 0x33], 0),
+            ("NES 2.0 Super Mario Bros.", [0x4E, 0x45, 0x53, 0x1A, 0x02, 0x01, 0x01, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01], 0),
+            ("NES 2.0 mapper 256", [0x4E, 0x45, 0x53, 0x1A, 0x10, 0x10, 0x00, 0x08, 0x01, 0x00, 0x07, 0x00, 0x00, 0x00, 0x00, 0x01], 256),
+            ("NES 2.0 mapper 4095", [0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0xF0, 0xF8, 0x0F, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], 4095),
+        ];
+
+        for (name, bytes, mapper) in table {
+            assert_eq!(header(*bytes).mapper(), *mapper, "{}", name);
+        }
+    }
+
+    #[test]
+    fn nes2_submapper() {
+        let bytes = [
+            0x4E, 0x45, 0x53, 0x1A, 0x02, 0x01, 0x40, 0x08, 0x30, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00,
+        ];
+        assert_eq!(header(bytes).submapper(), 3);
+    }
+
+    #[test]
+    fn submapper_ignored_for_ines() {
+        let bytes = [
+            0x4E, 0x45, 0x53, 0x1A, 0x02, 0x01, 0x40, 0x00, 0x30, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00,
+        ];
+        assert_eq!(header(bytes).submapper(), 0);
+    }
+
+    fn image(header: &[u8], body_len: usize) -> Vec<u8> {
+        let mut image = header.to_vec();
+        image.extend((0..body_len).map(|i| i as u8));
+        image
+    }
+
+    #[test]
+    fn load_with_trainer() {
+        let bytes = image(
+            b"NES\x1a\x01\x01\x04\x00\x00\x00\x00\x00\x00\x00\x00\x00",
+            0x6200,
+        );
+        let rom = Rom::from_path(&mut bytes.as_slice()).unwrap();
+        let trainer = rom.trainer.unwrap();
+
+        assert_eq!(trainer.len(), TRAINER_SIZE);
+        assert_eq!(trainer[1], 1);
+        assert_eq!(rom.prg[0], bytes[16 + TRAINER_SIZE]);
+    }
+
+    #[test]
+    fn load_bad_magic() {
+        let bytes = image(
+            b"NES\x00\x01\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00",
+            0x6000,
+        );
+        let err = Rom::from_path(&mut bytes.as_slice()).err().unwrap();
+        assert!(matches!(err, RomLoadError::BadMagic(_)));
+    }
+
+    #[test]
+    fn load_truncated_chr() {
+        let bytes = image(
+            b"NES\x1a\x01\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00",
+            0x5000,
+        );
+        let err = Rom::from_path(&mut bytes.as_slice()).err().unwrap();
+        assert!(matches!(
+            err,
+            RomLoadError::TruncatedChr {
+                expected: 0x2000,
+                actual: 0x1000
+            }
+        ));
+    }
+
+    #[test]
+    fn load_oversized() {
+        let bytes = image(
+            b"NES\x1a\x01\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00",
+            0x6010,
+        );
+        let rom = Rom::from_path(&mut bytes.as_slice()).unwrap();
+        assert_eq!(rom.prg, &bytes[0x10..0x4010]);
+        assert_eq!(rom.chr, &bytes[0x4010..0x6010]);
+    }
+
+    #[test]
+    fn load_without_prg() {
+        let bytes = image(
+            b"NES\x1a\x00\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00",
+            0x2000,
+        );
+        let err = Rom::from_path(&mut bytes.as_slice()).err().unwrap();
+        assert!(matches!(err, RomLoadError::NoPrgRom));
+    }
+
+    #[test]
+    fn load_huge_exponent_size() {
+        // PRG ROM size of 2^63 bytes
+        let bytes = b"NES\x1a\xFC\x00\x00\x08\x00\x0F\x00\x00\x00\x00\x00\x00".to_vec();
+        let err = Rom::from_path(&mut bytes.as_slice()).err().unwrap();
+        assert!(matches!(err, RomLoadError::PrgTooLarge(_)));
+
+        // CHR ROM size of 2^62 * 3 bytes
+        let bytes = b"NES\x1a\x01\xF9\x00\x08\x00\xF0\x00\x00\x00\x00\x00\x00".to_vec();
+        let err = Rom::from_path(&mut bytes.as_slice()).err().unwrap();
+        assert!(matches!(err, RomLoadError::ChrTooLarge(_)));
+    }
+
+    #[test]
+    fn load_unsupported_mapper() {
+        let bytes = image(
+            b"NES\x1a\x01\x01\x00\x10\x00\x00\x00\x00\x00\x00\x00\x00",
+            0x6000,
+        );
+        let err = Rom::from_path(&mut bytes.as_slice()).err().unwrap();
+        assert!(matches!(err, RomLoadError::UnsupportedMapper(16)));
+    }
+
+    #[test]
+    fn diskdude_header_is_plain_nes() {
+        let bytes = *b"NES\x1a\x08\x00\x21DiskDude!";
+        let header = header(bytes);
+        assert_eq!(header.ines_version(), INesVersion::INes1);
+        assert_eq!(header.console_type(), ConsoleType::Nes);
+    }
+
+    #[test]
+    fn diskdude_header_ignores_ram_size_and_timing() {
+        let bytes = *b"NES\x1a\x08\x00\x20DiskDude!";
+        let header = header(bytes);
+        assert_eq!(header.mapper(), 2);
+        assert_eq!(header.prg_ram_bytes(), 8 * 1024);
+        assert_eq!(header.timing(), TimingMode::Ntsc);
+    }
 }
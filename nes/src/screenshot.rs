@@ -0,0 +1,483 @@
+use crate::{
+    movie::is_fm2, state::fnv1a, Frame, Headless, Movie, MovieError, MoviePlayer, Rom, RomLoadError,
+};
+use std::{
+    error::Error,
+    fmt::Display,
+    fs::{self, File},
+    io::{self, BufWriter},
+    path::{Path, PathBuf},
+};
+
+const WIDTH: u32 = 256;
+const HEIGHT: u32 = 240;
+
+/// Pixels that differ from the reference image are drawn in this color
+const DIFF_COLOR: [u8; 3] = [0xFF, 0x00, 0xFF];
+
+#[derive(Debug)]
+pub enum ScreenshotError {
+    /// IO Error while reading a manifest or ROM, or writing an image
+    IoError(io::Error),
+    /// A line of the manifest couldn't be understood
+    Manifest { line: usize, message: String },
+    /// The ROM under test couldn't be loaded
+    Rom(RomLoadError),
+    /// The movie to play back couldn't be loaded
+    Movie(MovieError),
+    /// An image of a mismatched frame couldn't be written
+    Png(png::EncodingError),
+}
+
+impl Display for ScreenshotError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::IoError(err) => write!(f, "{}", err),
+            Self::Manifest { line, message } => write!(f, "manifest line {}: {}", line, message),
+            Self::Rom(err) => write!(f, "unable to load ROM: {}", err),
+            Self::Movie(err) => write!(f, "unable to load movie: {}", err),
+            Self::Png(err) => write!(f, "unable to write image: {}", err),
+        }
+    }
+}
+
+impl Error for ScreenshotError {}
+
+impl From<io::Error> for ScreenshotError {
+    fn from(err: io::Error) -> Self {
+        ScreenshotError::IoError(err)
+    }
+}
+
+impl From<RomLoadError> for ScreenshotError {
+    fn from(err: RomLoadError) -> Self {
+        ScreenshotError::Rom(err)
+    }
+}
+
+impl From<MovieError> for ScreenshotError {
+    fn from(err: MovieError) -> Self {
+        ScreenshotError::Movie(err)
+    }
+}
+
+impl From<png::EncodingError> for ScreenshotError {
+    fn from(err: png::EncodingError) -> Self {
+        ScreenshotError::Png(err)
+    }
+}
+
+/// A ROM to run from power on, and the frames to check along the way
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ScreenshotTest {
+    pub rom: PathBuf,
+
+    /// Input to play back while the ROM runs
+    pub movie: Option<PathBuf>,
+
+    /// Frames to check, counting the first frame after power on as 1, and
+    /// the hash each one should have (see [hash_frame])
+    pub frames: Vec<(usize, u64)>,
+}
+
+/// A frame that didn't look the way the manifest expected
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ScreenshotMismatch {
+    pub frame: usize,
+    pub expected: u64,
+    pub actual: u64,
+
+    /// Where the frame that was actually drawn was saved
+    pub image: PathBuf,
+
+    /// Where the difference from the reference image was saved, if there is
+    /// a reference image for this frame
+    pub diff: Option<PathBuf>,
+}
+
+pub fn hash_frame(frame: &Frame) -> u64 {
+    fnv1a(frame.as_ref())
+}
+
+/// Parse a manifest of expected frame hashes. Each line names a ROM, a frame
+/// number, the frame's hash in hex, and optionally a movie to play:
+///
+/// ```text
+/// # rom     frame  hash              movie
+/// smb.nes   120    4c2a6e0b51d3f9a7
+/// smb.nes   600    0f3e1b7c9a2d4e85  smb-walk.nesm
+/// ```
+///
+/// Paths are relative to `dir`. Consecutive lines for the same ROM and movie
+/// are checked in a single run.
+pub fn parse_manifest(text: &str, dir: &Path) -> Result<Vec<ScreenshotTest>, ScreenshotError> {
+    let mut tests: Vec<ScreenshotTest> = Vec::new();
+
+    for (i, line) in text.lines().enumerate() {
+        let line_num = i + 1;
+        let error = |message: &str| ScreenshotError::Manifest {
+            line: line_num,
+            message: message.to_string(),
+        };
+
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let fields = line.split_whitespace().collect::<Vec<_>>();
+        let (rom, frame, hash, movie) = match fields.as_slice() {
+            [rom, frame, hash] => (rom, frame, hash, None),
+            [rom, frame, hash, movie] => (rom, frame, hash, Some(dir.join(movie))),
+            _ => return Err(error("expected a ROM, frame, hash, and optional movie")),
+        };
+
+        let frame = frame
+            .parse()
+            .ok()
+            .filter(|&frame| frame > 0)
+            .ok_or_else(|| error("frame must be a number greater than 0"))?;
+        let hash = u64::from_str_radix(hash, 16).map_err(|_| error("hash must be hex"))?;
+        let rom = dir.join(rom);
+
+        match tests.last_mut() {
+            Some(test) if test.rom == rom && test.movie == movie => test.frames.push((frame, hash)),
+            _ => tests.push(ScreenshotTest {
+                rom,
+                movie,
+                frames: vec![(frame, hash)],
+            }),
+        }
+    }
+
+    Ok(tests)
+}
+
+/// Write tests out as a manifest that [parse_manifest] can read back, with
+/// paths relative to `dir`
+pub fn write_manifest(tests: &[ScreenshotTest], dir: &Path) -> String {
+    let relative = |path: &Path| path.strip_prefix(dir).unwrap_or(path).display().to_string();
+    let mut out = String::from("# rom frame hash movie\n");
+
+    for test in tests {
+        let movie = test.movie.as_deref().map(relative);
+
+        for &(frame, hash) in &test.frames {
+            out.push_str(&format!("{} {} {:016x}", relative(&test.rom), frame, hash));
+            if let Some(movie) = &movie {
+                out.push_str(&format!(" {}", movie));
+            }
+            out.push('\n');
+        }
+    }
+
+    out
+}
+
+impl ScreenshotTest {
+    /// Run the ROM and check each frame. Frames that don't match are saved
+    /// as PNGs in `out_dir`. If there is a reference image for the frame next
+    /// to the ROM, named `<movie or ROM name>-<frame>.png`, an image
+    /// highlighting the pixels that changed is saved too.
+    pub fn run(&self, out_dir: &Path) -> Result<Vec<ScreenshotMismatch>, ScreenshotError> {
+        let captured = self.capture()?;
+        let mut mismatches = Vec::new();
+
+        for (&(frame, expected), image) in self.frames.iter().zip(captured.iter()) {
+            let actual = hash_frame(image);
+            if actual == expected {
+                continue;
+            }
+
+            fs::create_dir_all(out_dir)?;
+            let file_name = format!("{}-{}", self.name(), frame);
+            let image_path = out_dir.join(format!("{}.png", file_name));
+            write_png(&image_path, image)?;
+
+            let diff = match read_png(&self.reference_path(frame)) {
+                Some(reference) => {
+                    let diff_path = out_dir.join(format!("{}-diff.png", file_name));
+                    write_png(&diff_path, &diff_frames(&reference, image))?;
+                    Some(diff_path)
+                }
+                None => None,
+            };
+
+            mismatches.push(ScreenshotMismatch {
+                frame,
+                expected,
+                actual,
+                image: image_path,
+                diff,
+            });
+        }
+
+        Ok(mismatches)
+    }
+
+    /// Accept every frame as the ROM draws it now. Each frame's hash is
+    /// updated, and the frame is saved next to the ROM as the reference
+    /// image that [ScreenshotTest::run] diffs against.
+    pub fn bless(&mut self) -> Result<(), ScreenshotError> {
+        let captured = self.capture()?;
+
+        for (i, image) in captured.iter().enumerate() {
+            write_png(&self.reference_path(self.frames[i].0), image)?;
+            self.frames[i].1 = hash_frame(image);
+        }
+
+        Ok(())
+    }
+
+    /// Run the ROM and capture each of the frames under test
+    fn capture(&self) -> Result<Vec<Frame>, ScreenshotError> {
+        let rom = Rom::from_path(&mut File::open(&self.rom)?)?;
+        let mut emulator = Headless::new(rom);
+
+        let movie = match &self.movie {
+            Some(path) if is_fm2(path) => Some(Movie::load_fm2_file(
+                path,
+                emulator.nes.rom_hash(),
+                emulator.nes.rom_md5(),
+            )?),
+            Some(path) => Some(Movie::load_file(path)?),
+            None => None,
+        };
+
+        let frame_numbers = self
+            .frames
+            .iter()
+            .map(|&(frame, _)| frame)
+            .collect::<Vec<_>>();
+
+        Ok(capture_frames(&mut emulator, movie, &frame_numbers)?)
+    }
+
+    /// Where the reference image for a frame is kept
+    fn reference_path(&self, frame: usize) -> PathBuf {
+        self.rom
+            .with_file_name(format!("{}-{}.png", self.name(), frame))
+    }
+
+    /// The name images of this test's frames are saved under
+    fn name(&self) -> String {
+        let path = self.movie.as_ref().unwrap_or(&self.rom);
+
+        path.file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+}
+
+/// Run from power on, playing back `movie` if there is one, and return a copy
+/// of each of the given frames in the order they were asked for
+pub fn capture_frames(
+    emulator: &mut Headless,
+    movie: Option<Movie>,
+    frames: &[usize],
+) -> Result<Vec<Frame>, MovieError> {
+    let mut player = MoviePlayer::new();
+    match movie {
+        Some(movie) => player.play(&mut emulator.nes, movie)?,
+        None => emulator.nes.power_cycle(),
+    }
+
+    let last = frames.iter().copied().max().unwrap_or(0);
+    let mut captured = vec![Frame::new(); frames.len()];
+
+    for frame in 1..=last {
+        emulator.run_frames(1, &mut (), &mut ());
+        player.end_frame(&mut emulator.nes);
+
+        for (&wanted, image) in frames.iter().zip(captured.iter_mut()) {
+            if wanted == frame {
+                *image = emulator.nes.cpu.mem.ppu.screen.clone();
+            }
+        }
+    }
+
+    Ok(captured)
+}
+
+/// Dim the actual frame, and draw every pixel that differs from the
+/// reference on top of it in a bright color
+fn diff_frames(reference: &Frame, actual: &Frame) -> Frame {
+    let mut diff = Frame::new();
+    let pixels = reference.as_ref().chunks(3).zip(actual.as_ref().chunks(3));
+
+    for (i, (expected, actual)) in pixels.enumerate() {
+        let color = if expected == actual {
+            [actual[0] / 4, actual[1] / 4, actual[2] / 4]
+        } else {
+            DIFF_COLOR
+        };
+
+        for (channel, &val) in color.iter().enumerate() {
+            diff[i * 3 + channel] = val;
+        }
+    }
+
+    diff
+}
+
+fn write_png(path: &Path, frame: &Frame) -> Result<(), ScreenshotError> {
+    let w = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(w, WIDTH, HEIGHT);
+
+    encoder.set_color(png::ColorType::RGB);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(frame.as_ref())?;
+    Ok(())
+}
+
+/// Read a reference image, if there is one and it's a screenshot of the
+/// right size and format
+fn read_png(path: &Path) -> Option<Frame> {
+    let decoder = png::Decoder::new(File::open(path).ok()?);
+    let (info, mut reader) = decoder.read_info().ok()?;
+
+    let is_screenshot = info.width == WIDTH
+        && info.height == HEIGHT
+        && info.color_type == png::ColorType::RGB
+        && info.bit_depth == png::BitDepth::Eight;
+
+    if !is_screenshot {
+        return None;
+    }
+
+    let mut frame = Frame::new();
+    reader.next_frame(frame.as_mut()).ok()?;
+    Some(frame)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headless::tests::{spin_image, spin_rom};
+    use std::env;
+
+    /// Set to accept the frames the ROMs draw now. The manifest's hashes are
+    /// rewritten and reference images are saved next to the ROMs.
+    const BLESS_ENV: &str = "BLESS_SCREENSHOTS";
+
+    #[test]
+    fn manifest_groups_frames_by_run() {
+        let text = "# rom frame hash movie\n\
+                    a.nes 1 ff\n\
+                    a.nes 2 0A # second frame\n\
+                    a.nes 3 1 a.fm2\n";
+        let tests = parse_manifest(text, Path::new("roms")).unwrap();
+
+        assert_eq!(tests.len(), 2);
+        assert_eq!(tests[0].rom, Path::new("roms/a.nes"));
+        assert_eq!(tests[0].frames, vec![(1, 0xFF), (2, 0x0A)]);
+        assert_eq!(tests[1].movie, Some(PathBuf::from("roms/a.fm2")));
+    }
+
+    #[test]
+    fn manifest_rejects_bad_hash() {
+        let err = parse_manifest("\na.nes 1 xyz\n", Path::new(""))
+            .err()
+            .unwrap();
+
+        assert!(matches!(err, ScreenshotError::Manifest { line: 2, .. }));
+    }
+
+    #[test]
+    fn manifest_round_trip() {
+        let text = "a.nes 1 ff\na.nes 2 0a\na.nes 3 1 a.fm2\n";
+        let dir = Path::new("roms");
+        let tests = parse_manifest(text, dir).unwrap();
+
+        let written = write_manifest(&tests, dir);
+
+        assert_eq!(parse_manifest(&written, dir).unwrap(), tests);
+    }
+
+    #[test]
+    fn blessed_frames_match() {
+        let dir = env::temp_dir().join(format!("nes-bless-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("spin.nes"), spin_image()).unwrap();
+
+        let mut test = ScreenshotTest {
+            rom: dir.join("spin.nes"),
+            movie: None,
+            frames: vec![(2, 0)],
+        };
+        test.bless().unwrap();
+        let mismatches = test.run(&dir);
+        let reference = read_png(&dir.join("spin-2.png"));
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(mismatches.unwrap(), vec![]);
+        assert!(reference.is_some());
+    }
+
+    #[test]
+    fn capture_is_reproducible() {
+        let mut emulator = Headless::new(spin_rom());
+        let first = capture_frames(&mut emulator, None, &[3, 1]).unwrap();
+        let second = capture_frames(&mut emulator, None, &[1, 3]).unwrap();
+
+        assert_eq!(hash_frame(&first[0]), hash_frame(&second[1]));
+        assert_eq!(hash_frame(&first[1]), hash_frame(&second[0]));
+    }
+
+    #[test]
+    fn diff_highlights_changed_pixels() {
+        let reference = Frame::new();
+        let mut actual = Frame::new();
+        actual[3] = 0x80;
+
+        let diff = diff_frames(&reference, &actual);
+
+        assert_eq!(diff.as_ref()[0..3], [0, 0, 0]);
+        assert_eq!(diff.as_ref()[3..6], DIFF_COLOR);
+    }
+
+    /// Checks every frame listed in test-roms/screenshots/manifest.txt
+    #[test]
+    fn screenshot_manifest() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("test-roms/screenshots");
+        let manifest = dir.join("manifest.txt");
+        let text = fs::read_to_string(&manifest)
+            .unwrap_or_else(|e| panic!("{}: {}", manifest.display(), e));
+        let mut tests = parse_manifest(&text, &dir).unwrap();
+
+        if env::var_os(BLESS_ENV).is_some() {
+            for test in &mut tests {
+                test.bless().unwrap();
+            }
+
+            fs::write(&manifest, write_manifest(&tests, &dir)).unwrap();
+            return;
+        }
+
+        let out_dir = env::temp_dir().join("nes-screenshots");
+        let mut failures = Vec::new();
+
+        for test in tests {
+            for mismatch in test.run(&out_dir).unwrap() {
+                let diff = match &mismatch.diff {
+                    Some(path) => format!(", diff at {}", path.display()),
+                    None => String::new(),
+                };
+
+                failures.push(format!(
+                    "{} frame {}: expected {:016x}, got {:016x}, saved to {}{}",
+                    test.rom.display(),
+                    mismatch.frame,
+                    mismatch.expected,
+                    mismatch.actual,
+                    mismatch.image.display(),
+                    diff
+                ));
+            }
+        }
+
+        assert!(failures.is_empty(), "{}", failures.join("\n"));
+    }
+}
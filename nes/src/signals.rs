@@ -1,11 +1,34 @@
 use crate::{
-    cpu::Flags,
-    emulation::EmulationState,
     frame_buffer::Frame,
     input::Buttons,
     ppu::{PpuControl, PpuMask, PpuStatus},
     Rgb,
 };
+use mos6502::Flags;
+use std::path::PathBuf;
+
+#[derive(Debug)]
+pub enum VideoMessage {
+    ControlResponse(ControlResponse),
+    CpuStep(String),
+    FrameAvailable(Frame),
+    StateChanged(EmulationState),
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum EmulationState {
+    /// The emulation has been ended by the user
+    Kill,
+
+    /// The emulation should step forward by one CPU tick, then transition into the Pause state
+    Step,
+
+    /// The emulation should remain paused forever
+    Pause,
+
+    /// The emulation should run uninterrupted until a user changes the state
+    Run(Option<usize>),
+}
 
 #[derive(Debug)]
 pub enum ControlRequest {
@@ -14,6 +37,7 @@ pub enum ControlRequest {
     RegisterState,
     PaletteState,
     RomContents,
+    SaveData,
 }
 
 #[derive(Debug)]
@@ -27,6 +51,20 @@ pub enum ControlMessage {
     SetState(EmulationState),
     SetProgramCounter(u16),
     RecycleFrame(Frame),
+    LoadSaveData(Vec<u8>),
+    SaveState(PathBuf),
+    LoadState(PathBuf),
+    /// Rewind to the previous snapshot in the rewind buffer. Ignored unless
+    /// emulation is paused.
+    StepBack,
+    /// Start recording a movie, which is written to `path` when stopped
+    RecordMovie {
+        path: PathBuf,
+        from_power_on: bool,
+    },
+    /// Play back a movie. Paths ending in .fm2 are read as FCEUX movies.
+    PlayMovie(PathBuf),
+    StopMovie,
     ControlRequest(ControlRequest),
 }
 
@@ -81,4 +119,5 @@ pub enum ControlResponse {
     PpuState(PpuState),
     RegisterState(RegisterState),
     RomContents(Vec<u8>),
+    SaveData(Option<Vec<u8>>),
 }
@@ -0,0 +1,230 @@
+use std::{error::Error, fmt::Display, io};
+
+/// The version of the save state format. This must be bumped whenever a
+/// component changes what it saves, since states are not self-describing.
+pub const STATE_VERSION: u16 = 8;
+
+const STATE_MAGIC: [u8; 4] = *b"NESS";
+
+#[derive(Debug)]
+pub enum StateError {
+    /// IO Error while reading or writing a state file
+    IoError(io::Error),
+    /// The data is not a save state
+    BadMagic,
+    /// The state was written by a different version of the emulator
+    UnsupportedVersion(u16),
+    /// The state was saved while a different ROM was loaded
+    RomMismatch,
+    /// The state ended early or contained a value that can't be restored
+    Corrupt,
+}
+
+impl Display for StateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::IoError(err) => write!(f, "unable to access save state: {}", err),
+            Self::BadMagic => write!(f, "not a save state"),
+            Self::UnsupportedVersion(v) => write!(
+                f,
+                "save state version {} is not supported (expected {})",
+                v, STATE_VERSION
+            ),
+            Self::RomMismatch => write!(f, "save state belongs to a different ROM"),
+            Self::Corrupt => write!(f, "save state is corrupt"),
+        }
+    }
+}
+
+impl Error for StateError {}
+
+impl From<io::Error> for StateError {
+    fn from(err: io::Error) -> Self {
+        StateError::IoError(err)
+    }
+}
+
+/// A component of the machine whose state can be saved and restored
+pub trait Snapshot {
+    fn save(&self, w: &mut StateWriter);
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError>;
+}
+
+/// Builds up a save state. All values are written little endian.
+pub struct StateWriter(Vec<u8>);
+
+impl StateWriter {
+    /// Start a new state, beginning with the format header
+    pub fn new(rom_hash: u64) -> Self {
+        let mut w = Self(Vec::new());
+        w.bytes(&STATE_MAGIC);
+        w.u16(STATE_VERSION);
+        w.u64(rom_hash);
+        w
+    }
+
+    pub fn into_inner(self) -> Vec<u8> {
+        self.0
+    }
+
+    pub fn u8(&mut self, val: u8) {
+        self.0.push(val);
+    }
+
+    pub fn bool(&mut self, val: bool) {
+        self.u8(val as u8);
+    }
+
+    pub fn u16(&mut self, val: u16) {
+        self.0.extend_from_slice(&val.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, val: u32) {
+        self.0.extend_from_slice(&val.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, val: u64) {
+        self.0.extend_from_slice(&val.to_le_bytes());
+    }
+
+    /// Write a fixed-size block of bytes. The reader must know the length.
+    pub fn bytes(&mut self, val: &[u8]) {
+        self.0.extend_from_slice(val);
+    }
+}
+
+/// Reads back a save state written by [StateWriter]
+pub struct StateReader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> StateReader<'a> {
+    /// Validate the format header and prepare to read components
+    pub fn new(buf: &'a [u8], rom_hash: u64) -> Result<Self, StateError> {
+        let mut r = Self { buf, pos: 0 };
+
+        let mut magic = [0; 4];
+        r.bytes(&mut magic).map_err(|_| StateError::BadMagic)?;
+        if magic != STATE_MAGIC {
+            return Err(StateError::BadMagic);
+        }
+
+        let version = r.u16()?;
+        if version != STATE_VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+
+        if r.u64()? != rom_hash {
+            return Err(StateError::RomMismatch);
+        }
+
+        Ok(r)
+    }
+
+    /// Fail unless every byte of the state has been read
+    pub fn finish(self) -> Result<(), StateError> {
+        if self.pos == self.buf.len() {
+            Ok(())
+        } else {
+            Err(StateError::Corrupt)
+        }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        let end = self.pos + len;
+        let slice = self.buf.get(self.pos..end).ok_or(StateError::Corrupt)?;
+        self.pos = end;
+        Ok(slice)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, StateError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(StateError::Corrupt),
+        }
+    }
+
+    pub fn u16(&mut self) -> Result<u16, StateError> {
+        let mut buf = [0; 2];
+        self.bytes(&mut buf)?;
+        Ok(u16::from_le_bytes(buf))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, StateError> {
+        let mut buf = [0; 4];
+        self.bytes(&mut buf)?;
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, StateError> {
+        let mut buf = [0; 8];
+        self.bytes(&mut buf)?;
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    /// Fill the buffer with the next `buf.len()` bytes of the state
+    pub fn bytes(&mut self, buf: &mut [u8]) -> Result<(), StateError> {
+        buf.copy_from_slice(self.take(buf.len())?);
+        Ok(())
+    }
+}
+
+/// A 64 bit FNV-1a hash, used to tie save states to the ROM they came from
+pub fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xCBF2_9CE4_8422_2325, |hash, &b| {
+        (hash ^ b as u64).wrapping_mul(0x0000_0100_0000_01B3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip_values() {
+        let mut w = StateWriter::new(42);
+        w.u8(0x12);
+        w.bool(true);
+        w.u16(0x3456);
+        w.u32(0x789A_BCDE);
+        w.u64(u64::MAX);
+        w.bytes(&[1, 2, 3]);
+        let state = w.into_inner();
+
+        let mut r = StateReader::new(&state, 42).unwrap();
+        let mut bytes = [0; 3];
+        assert_eq!(r.u8().unwrap(), 0x12);
+        assert!(r.bool().unwrap());
+        assert_eq!(r.u16().unwrap(), 0x3456);
+        assert_eq!(r.u32().unwrap(), 0x789A_BCDE);
+        assert_eq!(r.u64().unwrap(), u64::MAX);
+        r.bytes(&mut bytes).unwrap();
+        assert_eq!(bytes, [1, 2, 3]);
+        assert!(r.finish().is_ok());
+    }
+
+    #[test]
+    fn rejects_other_roms() {
+        let state = StateWriter::new(1).into_inner();
+        let err = StateReader::new(&state, 2).err().unwrap();
+        assert!(matches!(err, StateError::RomMismatch));
+    }
+
+    #[test]
+    fn rejects_truncated_state() {
+        let mut w = StateWriter::new(1);
+        w.u8(0);
+        let state = w.into_inner();
+
+        let mut r = StateReader::new(&state, 1).unwrap();
+        r.u8().unwrap();
+        assert!(matches!(r.u16(), Err(StateError::Corrupt)));
+    }
+}
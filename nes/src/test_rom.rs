@@ -0,0 +1,239 @@
+use crate::{Headless, Nes, Rom};
+use std::fmt;
+
+/// How many frames a test ROM gets to report a result before giving up. Most
+/// of blargg's ROMs finish in well under a minute of emulated time.
+pub const DEFAULT_TEST_ROM_TIMEOUT: usize = 60 * 60;
+
+/// Written to $6001-$6003 once the status at $6000 is valid
+const SIGNATURE: [u8; 3] = [0xDE, 0xB0, 0x61];
+
+const STATUS_ADDR: u16 = 0x6000;
+const TEXT_ADDR: u16 = 0x6004;
+
+const STATUS_RUNNING: u8 = 0x80;
+const STATUS_NEEDS_RESET: u8 = 0x81;
+
+/// How long to wait before pressing reset when a ROM asks for it. The ROM
+/// needs at least 100ms.
+const RESET_DELAY_FRAMES: usize = 6;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum TestRomStatus {
+    Passed,
+
+    /// The ROM reported a non-zero result code
+    Failed(u8),
+
+    /// The ROM didn't report a result in time. It may not use the $6000
+    /// protocol at all.
+    TimedOut,
+}
+
+/// The outcome of running a test ROM, along with the text it wrote to $6004
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TestRomResult {
+    pub status: TestRomStatus,
+    pub message: String,
+}
+
+impl TestRomResult {
+    pub fn passed(&self) -> bool {
+        self.status == TestRomStatus::Passed
+    }
+}
+
+impl fmt::Display for TestRomResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.status {
+            TestRomStatus::Passed => write!(f, "passed")?,
+            TestRomStatus::Failed(code) => write!(f, "failed with code {}", code)?,
+            TestRomStatus::TimedOut => write!(f, "timed out")?,
+        }
+
+        let message = self.message.trim();
+        if !message.is_empty() {
+            write!(f, ": {}", message)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Run a test ROM that reports its result through PRG RAM, as blargg's test
+/// ROMs do. $6000 holds the status: $80 while running, $81 when the ROM wants
+/// the reset button pressed, and the result code once finished, where 0 means
+/// it passed. $6001-$6003 hold a signature once the status is valid, and
+/// $6004 onwards holds a zero-terminated message.
+pub fn run_test_rom(rom: Rom, timeout_frames: usize) -> TestRomResult {
+    let mut emulator = Headless::new(rom);
+    let mut reset_in = None;
+
+    for _ in 0..timeout_frames {
+        emulator.run_frames(1, &mut (), &mut ());
+
+        let nes = &emulator.nes;
+        let status = match read_status(nes) {
+            Some(status) => status,
+            None => continue,
+        };
+
+        match status {
+            STATUS_RUNNING => {}
+            STATUS_NEEDS_RESET => match reset_in {
+                Some(0) => {
+                    emulator.nes.reset();
+                    reset_in = None;
+                }
+                Some(frames) => reset_in = Some(frames - 1),
+                None => reset_in = Some(RESET_DELAY_FRAMES),
+            },
+            0 => return result(nes, TestRomStatus::Passed),
+            code => return result(nes, TestRomStatus::Failed(code)),
+        }
+    }
+
+    result(&emulator.nes, TestRomStatus::TimedOut)
+}
+
+fn read_status(nes: &Nes) -> Option<u8> {
+    let signature = [
+        nes.cpu.mem.mapper.peek_prg(STATUS_ADDR + 1)?,
+        nes.cpu.mem.mapper.peek_prg(STATUS_ADDR + 2)?,
+        nes.cpu.mem.mapper.peek_prg(STATUS_ADDR + 3)?,
+    ];
+
+    if signature == SIGNATURE {
+        nes.cpu.mem.mapper.peek_prg(STATUS_ADDR)
+    } else {
+        None
+    }
+}
+
+fn result(nes: &Nes, status: TestRomStatus) -> TestRomResult {
+    let bytes = (TEXT_ADDR..0x8000)
+        .map(|addr| nes.cpu.mem.mapper.peek_prg(addr).unwrap_or(0))
+        .take_while(|&b| b != 0)
+        .collect::<Vec<_>>();
+
+    TestRomResult {
+        status,
+        message: String::from_utf8_lossy(&bytes).into_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs, path::Path};
+
+    /// Points at a directory of test ROMs that report through the $6000
+    /// protocol, such as blargg's. The suite is ignored by default, and fails
+    /// if this isn't set when it's run.
+    const ROMS_ENV: &str = "BLARGG_TEST_ROMS";
+
+    /// An NROM image that reports `code` and `message` through the $6000
+    /// protocol, then spins forever
+    fn protocol_rom(code: u8, message: &str) -> Rom {
+        let mut program = Vec::new();
+        let mut store = |addr: u16, val: u8| {
+            // LDA #val, STA addr
+            program.extend([0xA9, val, 0x8D, addr as u8, (addr >> 8) as u8]);
+        };
+
+        for (addr, &val) in (STATUS_ADDR + 1..).zip(SIGNATURE.iter()) {
+            store(addr, val);
+        }
+
+        for (addr, val) in (TEXT_ADDR..).zip(message.bytes().chain([0])) {
+            store(addr, val);
+        }
+
+        store(STATUS_ADDR, code);
+
+        let spin = 0x8000 + program.len() as u16;
+        program.extend([0x4C, spin as u8, (spin >> 8) as u8]);
+
+        let mut image = b"NES\x1a\x01\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00".to_vec();
+        let mut prg = vec![0; 0x4000];
+        prg[..program.len()].copy_from_slice(&program);
+        prg[0x3FFC..0x3FFE].copy_from_slice(&[0x00, 0x80]);
+        image.extend(prg);
+        image.extend(vec![0; 0x2000]);
+
+        Rom::from_path(&mut image.as_slice()).unwrap()
+    }
+
+    #[test]
+    fn reports_pass_with_message() {
+        let result = run_test_rom(protocol_rom(0, "All tests passed"), 10);
+
+        assert!(result.passed());
+        assert_eq!(result.message, "All tests passed");
+    }
+
+    #[test]
+    fn reports_failure_code() {
+        let result = run_test_rom(protocol_rom(3, "Wrong flags"), 10);
+
+        assert_eq!(result.status, TestRomStatus::Failed(3));
+        assert_eq!(result.message, "Wrong flags");
+    }
+
+    #[test]
+    fn times_out_without_signature() {
+        let result = run_test_rom(crate::headless::tests::spin_rom(), 10);
+
+        assert_eq!(result.status, TestRomStatus::TimedOut);
+    }
+
+    /// Runs every ROM under the directory in BLARGG_TEST_ROMS
+    #[test]
+    #[ignore = "needs BLARGG_TEST_ROMS"]
+    fn blargg_suite() {
+        let root = env::var_os(ROMS_ENV)
+            .unwrap_or_else(|| panic!("{} must point at the test ROMs", ROMS_ENV));
+        let dir = Path::new(&root);
+        assert!(dir.is_dir(), "{} is missing", dir.display());
+
+        let mut roms = Vec::new();
+        find_roms(dir, &mut roms);
+        roms.sort();
+        assert!(!roms.is_empty(), "no ROMs found in {}", dir.display());
+
+        let failures = roms
+            .iter()
+            .filter_map(|path| {
+                let data = fs::read(path).unwrap();
+                let rom = Rom::from_path(&mut data.as_slice()).unwrap();
+                let result = run_test_rom(rom, DEFAULT_TEST_ROM_TIMEOUT);
+
+                if result.passed() {
+                    None
+                } else {
+                    Some(format!("{}: {}", path.display(), result))
+                }
+            })
+            .collect::<Vec<_>>();
+
+        assert!(
+            failures.is_empty(),
+            "{} of {} ROMs failed\n{}",
+            failures.len(),
+            roms.len(),
+            failures.join("\n")
+        );
+    }
+
+    fn find_roms(dir: &Path, roms: &mut Vec<std::path::PathBuf>) {
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+
+            if path.is_dir() {
+                find_roms(&path, roms);
+            } else if path.extension().is_some_and(|ext| ext == "nes") {
+                roms.push(path);
+            }
+        }
+    }
+}